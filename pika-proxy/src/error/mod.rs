@@ -63,64 +63,61 @@ impl Error {
         &self.kind
     }
     pub fn is_network_error(&self) -> bool {
-        match self.kind {
-            ErrorKind::Network => true,
-            _ => false,
-        }
+        matches!(self.kind, ErrorKind::Network)
     }
     pub fn is_unknown_error(&self) -> bool {
-        match self.kind {
-            ErrorKind::Unknown => true,
-            _ => false,
-        }
+        matches!(self.kind, ErrorKind::Unknown)
     }
     pub fn is_initialize_error(&self) -> bool {
-        match self.kind {
-            ErrorKind::Initialize => true,
-            _ => false,
-        }
+        matches!(self.kind, ErrorKind::Initialize)
     }
     pub fn is_protocol_error(&self) -> bool {
-        match self.kind {
-            ErrorKind::Protocol => true,
-            _ => false,
-        }
+        matches!(self.kind, ErrorKind::Protocol)
     }
     pub fn is_proxy_error(&self) -> bool {
-        match self.kind {
-            ErrorKind::Proxy => true,
-            _ => false,
-        }
+        matches!(self.kind, ErrorKind::Proxy)
     }
     pub fn is_server_error(&self) -> bool {
-        match self.kind {
-            ErrorKind::Server => true,
-            _ => false,
-        }
+        matches!(self.kind, ErrorKind::Server)
+    }
+
+    /// Returns true if the error is caused by a backend server, such as a
+    /// broken connection, sessions with `break_on_failure` are closed on it.
+    pub fn is_backend_failure(&self) -> bool {
+        matches!(self.kind, ErrorKind::Network | ErrorKind::Server)
+    }
+
+    /// Converts the error to the error reply sent to client:
+    ///
+    /// | kind       | reply                          |
+    /// |------------|--------------------------------|
+    /// | Network    | `IOERR <msg>`                  |
+    /// | Protocol   | `ERR Protocol error: <msg>`    |
+    /// | Unknown    | `ERR unexpected error: <msg>`  |
+    /// | others     | `ERR <msg>`                    |
+    pub fn to_resp(&self) -> redis::RedisResp {
+        let msg = match self.kind {
+            ErrorKind::Network => format!("IOERR {}", self.inner),
+            ErrorKind::Protocol => format!("ERR Protocol error: {}", self.inner),
+            ErrorKind::Unknown => format!("ERR unexpected error: {}", self.inner),
+            ErrorKind::Initialize | ErrorKind::Proxy | ErrorKind::Server => {
+                format!("ERR {}", self.inner)
+            }
+        };
+        // a newline would break the simple string of the reply
+        redis::RedisResp::error(msg.replace(['\r', '\n'], " "))
     }
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self.kind {
-            ErrorKind::Unknown => {
-                f.write_fmt(format_args!("unexpected error: {}", self.inner.to_string()))
-            }
-            ErrorKind::Network => {
-                f.write_fmt(format_args!("network error: {}", self.inner.to_string()))
-            }
-            ErrorKind::Initialize => {
-                f.write_fmt(format_args!("initialize error: {}", self.inner.to_string()))
-            }
-            ErrorKind::Protocol => {
-                f.write_fmt(format_args!("protocol error: {}", self.inner.to_string()))
-            }
-            ErrorKind::Proxy => {
-                f.write_fmt(format_args!("proxy error: {}", self.inner.to_string()))
-            }
-            ErrorKind::Server => {
-                f.write_fmt(format_args!("server error: {}", self.inner.to_string()))
-            }
+            ErrorKind::Unknown => f.write_fmt(format_args!("unexpected error: {}", self.inner)),
+            ErrorKind::Network => f.write_fmt(format_args!("network error: {}", self.inner)),
+            ErrorKind::Initialize => f.write_fmt(format_args!("initialize error: {}", self.inner)),
+            ErrorKind::Protocol => f.write_fmt(format_args!("protocol error: {}", self.inner)),
+            ErrorKind::Proxy => f.write_fmt(format_args!("proxy error: {}", self.inner)),
+            ErrorKind::Server => f.write_fmt(format_args!("server error: {}", self.inner)),
         }
     }
 }
@@ -133,8 +130,42 @@ impl Debug for Error {
     }
 }
 
+impl From<redis::error::RedisError> for Error {
+    fn from(err: redis::error::RedisError) -> Self {
+        match err {
+            redis::error::RedisError::Io(_) => Error::network(err),
+            _ => Error::protocol(err),
+        }
+    }
+}
+
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         self.inner.source()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::*;
+
+    #[test]
+    fn test_error_to_resp() {
+        let resp = Error::network(anyhow!("backend 127.0.0.1:6379 is unavailable")).to_resp();
+        assert_eq!(
+            resp.error_message(),
+            Some("IOERR backend 127.0.0.1:6379 is unavailable")
+        );
+        let resp = Error::protocol(anyhow!("invalid bulk\r\nlength")).to_resp();
+        assert_eq!(
+            resp.error_message(),
+            Some("ERR Protocol error: invalid bulk  length")
+        );
+        let resp = Error::proxy(anyhow!("slot-0001 is not ready")).to_resp();
+        assert_eq!(resp.error_message(), Some("ERR slot-0001 is not ready"));
+        assert!(Error::server(anyhow!("")).is_backend_failure());
+        assert!(!Error::proxy(anyhow!("")).is_backend_failure());
+    }
+}
@@ -1,23 +1,42 @@
 use clap::Parser;
 
-use proxy::server::{ProxyOptions, ProxyServer};
-
-mod error;
-mod models;
-mod proxy;
-mod utils;
+use pika_proxy::proxy::config::{Config, Override};
+use pika_proxy::proxy::server::{ProxyOptions, ProxyServer};
 
+/// Keys of the config file are overridden by the env vars such as
+/// `PIKA_PROXY_PROXY__ADDR`, and then by `--set`.
 #[derive(Parser, Debug)]
 struct Args {
     #[arg(short, long, default_value_t = String::from("config/proxy.toml"))]
     config_path: String,
+    /// Override a key of the config, such as `backend.max_pipeline=40000`
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    overrides: Vec<Override>,
+    /// Print the config in effect and exit
+    #[arg(long)]
+    print_config: bool,
 }
 
 fn main() {
     let args = Args::parse();
-    // let config: Config = Config::from_path(args.config_path.as_str());
+    let mut overrides = Override::from_env();
+    overrides.extend(args.overrides);
+    if args.print_config {
+        let printed = Config::from_path_with(&args.config_path, &overrides)
+            .map_err(|e| e.to_string())
+            .and_then(|config| toml::to_string_pretty(&config).map_err(|e| e.to_string()));
+        match printed {
+            Ok(printed) => print!("{}", printed),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
     let option = ProxyOptions {
         config_path: args.config_path,
+        overrides,
     };
     let rt = tokio::runtime::Builder::new_multi_thread()
         .worker_threads(num_cpus::get()) // cpu*2个工作线程
@@ -25,7 +44,11 @@ fn main() {
         .enable_time() // 可在runtime中使用异步计时器(timer)
         .build() // 创建runtime
         .unwrap();
-    let mut proxy = ProxyServer::new(&option).expect("server config error");
-    rt.block_on(proxy.serve_proxy())
-        .expect("unhandled fatal error");
+    rt.block_on(async {
+        let mut proxy = ProxyServer::new(&option)
+            .await
+            .expect("server config error");
+        proxy.serve_proxy().await
+    })
+    .expect("unhandled fatal error");
 }
@@ -1,9 +1,572 @@
-use tokio::sync::mpsc::Sender;
+use std::collections::HashMap;
+use std::sync::OnceLock;
 
-use crate::models::response::Response;
+use bytes::Bytes;
 
+/// command modifies data, so it must be sent to the master
+pub const FLAG_WRITE: u32 = 1 << 0;
+/// command must be sent to the master even if it is read only
+pub const FLAG_MASTER_ONLY: u32 = 1 << 1;
+/// command may modify data depending on its arguments
+pub const FLAG_MAY_WRITE: u32 = FLAG_WRITE | FLAG_MASTER_ONLY;
+/// command is rejected by proxy
+pub const FLAG_NOT_ALLOW: u32 = 1 << 2;
+/// command may block the connection, XREAD only blocks with BLOCK
+pub const FLAG_BLOCKING: u32 = 1 << 3;
+/// command is served by the pubsub backend, see `PUBSUB_SLOT`
+pub const FLAG_PUBSUB: u32 = 1 << 4;
+/// all keys of the command must hash to the same slot, like EVAL
+pub const FLAG_SAME_SLOT: u32 = 1 << 5;
+/// command is sent to all masters and the replies are merged
+pub const FLAG_BROADCAST: u32 = 1 << 6;
+
+/// Pub/Sub commands are sent to the master of this slot, so publishers and
+/// subscribers meet on the same server.
+pub const PUBSUB_SLOT: u64 = 0;
+
+/// KeySpec describes which arguments of a command are keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeySpec {
+    /// command has no key
+    None,
+    /// keys are at `first`, `first + step`, ... up to `last`, a negative
+    /// `last` counts from the end of the arguments
+    Range {
+        first: usize,
+        last: isize,
+        step: usize,
+    },
+    /// number of keys is at `numkeys`, followed by the keys, like EVAL
+    NumKeys { numkeys: usize },
+    /// destination key at 1, then number of keys at 2, like ZUNIONSTORE
+    DestNumKeys,
+    /// keys follow the STREAMS keyword, like XREAD
+    Streams,
+}
+
+const ONE_KEY: KeySpec = KeySpec::Range {
+    first: 1,
+    last: 1,
+    step: 1,
+};
+const ALL_KEYS: KeySpec = KeySpec::Range {
+    first: 1,
+    last: -1,
+    step: 1,
+};
+const TWO_KEYS: KeySpec = KeySpec::Range {
+    first: 1,
+    last: 2,
+    step: 1,
+};
+
+impl KeySpec {
+    /// Extracts keys from `args`, `args[0]` is the command name.
+    pub fn keys<'a>(&self, args: &'a [Bytes]) -> Vec<&'a [u8]> {
+        match *self {
+            KeySpec::None => vec![],
+            KeySpec::Range { first, last, step } => {
+                let last = if last < 0 {
+                    args.len() as isize + last
+                } else {
+                    last
+                };
+                if last < first as isize {
+                    return vec![];
+                }
+                let last = (last as usize).min(args.len().saturating_sub(1));
+                (first..=last)
+                    .step_by(step)
+                    .filter_map(|i| args.get(i).map(|k| &k[..]))
+                    .collect()
+            }
+            KeySpec::NumKeys { numkeys } => Self::counted_keys(args, numkeys, numkeys + 1),
+            KeySpec::DestNumKeys => {
+                let mut keys = Self::counted_keys(args, 2, 3);
+                if let Some(dest) = args.get(1) {
+                    keys.insert(0, &dest[..]);
+                }
+                keys
+            }
+            KeySpec::Streams => {
+                let Some(pos) = args
+                    .iter()
+                    .position(|arg| arg.eq_ignore_ascii_case(b"STREAMS"))
+                else {
+                    return vec![];
+                };
+                let rest = &args[pos + 1..];
+                rest[..rest.len() / 2].iter().map(|k| &k[..]).collect()
+            }
+        }
+    }
+
+    fn counted_keys(args: &[Bytes], numkeys: usize, first: usize) -> Vec<&[u8]> {
+        let count = args
+            .get(numkeys)
+            .and_then(|n| std::str::from_utf8(n).ok())
+            .and_then(|n| n.parse::<usize>().ok())
+            .unwrap_or(0);
+        args.iter()
+            .skip(first)
+            .take(count)
+            .map(|k| &k[..])
+            .collect()
+    }
+}
+
+/// Command is the proxy's knowledge about a redis command.
+#[derive(Debug)]
 pub struct Command {
-    //pub redis_cmd: Cmd,
-    pub response_channel: Sender<Response>,
-    pub id: usize,
+    pub name: &'static str,
+    pub flags: u32,
+    pub keys: KeySpec,
+}
+
+impl Command {
+    const fn new(name: &'static str, flags: u32, keys: KeySpec) -> Self {
+        Self { name, flags, keys }
+    }
+
+    pub fn is_not_allowed(&self) -> bool {
+        self.flags & FLAG_NOT_ALLOW != 0
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.flags & FLAG_MAY_WRITE == 0
+    }
+
+    pub fn is_blocking(&self) -> bool {
+        self.flags & FLAG_BLOCKING != 0
+    }
+
+    pub fn is_pubsub(&self) -> bool {
+        self.flags & FLAG_PUBSUB != 0
+    }
+
+    pub fn is_same_slot(&self) -> bool {
+        self.flags & FLAG_SAME_SLOT != 0
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        self.flags & FLAG_BROADCAST != 0
+    }
+
+    /// Position of the command in [`commands`], as all commands live in the
+    /// command table.
+    pub fn index(&'static self) -> usize {
+        (self as *const Command as usize - COMMANDS.as_ptr() as usize)
+            / std::mem::size_of::<Command>()
+    }
+}
+
+/// All commands known by the proxy.
+pub fn commands() -> &'static [Command] {
+    COMMANDS
+}
+
+/// Looks up a command by its upper-cased name.
+pub fn get_command(name: &str) -> Option<&'static Command> {
+    static TABLE: OnceLock<HashMap<&'static str, &'static Command>> = OnceLock::new();
+    TABLE
+        .get_or_init(|| COMMANDS.iter().map(|c| (c.name, c)).collect())
+        .get(name)
+        .copied()
+}
+
+static COMMANDS: &[Command] = &[
+    Command::new("APPEND", FLAG_WRITE, ONE_KEY),
+    Command::new("ASKING", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("AUTH", 0, KeySpec::None),
+    Command::new("BGREWRITEAOF", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("BGSAVE", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("BITCOUNT", 0, ONE_KEY),
+    Command::new("BITFIELD", FLAG_WRITE, ONE_KEY),
+    Command::new(
+        "BITOP",
+        FLAG_WRITE,
+        KeySpec::Range {
+            first: 2,
+            last: -1,
+            step: 1,
+        },
+    ),
+    Command::new("BITPOS", 0, ONE_KEY),
+    Command::new("BLMOVE", FLAG_WRITE | FLAG_BLOCKING, TWO_KEYS),
+    Command::new(
+        "BLMPOP",
+        FLAG_WRITE | FLAG_BLOCKING,
+        KeySpec::NumKeys { numkeys: 2 },
+    ),
+    Command::new(
+        "BLPOP",
+        FLAG_WRITE | FLAG_BLOCKING,
+        KeySpec::Range {
+            first: 1,
+            last: -2,
+            step: 1,
+        },
+    ),
+    Command::new(
+        "BRPOP",
+        FLAG_WRITE | FLAG_BLOCKING,
+        KeySpec::Range {
+            first: 1,
+            last: -2,
+            step: 1,
+        },
+    ),
+    Command::new("BRPOPLPUSH", FLAG_WRITE | FLAG_BLOCKING, TWO_KEYS),
+    Command::new(
+        "BZMPOP",
+        FLAG_WRITE | FLAG_BLOCKING,
+        KeySpec::NumKeys { numkeys: 2 },
+    ),
+    Command::new(
+        "BZPOPMAX",
+        FLAG_WRITE | FLAG_BLOCKING,
+        KeySpec::Range {
+            first: 1,
+            last: -2,
+            step: 1,
+        },
+    ),
+    Command::new(
+        "BZPOPMIN",
+        FLAG_WRITE | FLAG_BLOCKING,
+        KeySpec::Range {
+            first: 1,
+            last: -2,
+            step: 1,
+        },
+    ),
+    Command::new("CLIENT", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("CLUSTER", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("COMMAND", 0, KeySpec::None),
+    Command::new("CONFIG", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("COPY", FLAG_WRITE, TWO_KEYS),
+    Command::new("DBSIZE", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("DEBUG", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("DECR", FLAG_WRITE, ONE_KEY),
+    Command::new("DECRBY", FLAG_WRITE, ONE_KEY),
+    Command::new("DEL", FLAG_WRITE, ALL_KEYS),
+    Command::new("DISCARD", 0, KeySpec::None),
+    Command::new("DUMP", 0, ONE_KEY),
+    Command::new("ECHO", 0, KeySpec::None),
+    Command::new(
+        "EVAL",
+        FLAG_WRITE | FLAG_SAME_SLOT,
+        KeySpec::NumKeys { numkeys: 2 },
+    ),
+    Command::new(
+        "EVALSHA",
+        FLAG_WRITE | FLAG_SAME_SLOT,
+        KeySpec::NumKeys { numkeys: 2 },
+    ),
+    Command::new(
+        "EVALSHA_RO",
+        FLAG_MASTER_ONLY | FLAG_SAME_SLOT,
+        KeySpec::NumKeys { numkeys: 2 },
+    ),
+    Command::new(
+        "EVAL_RO",
+        FLAG_MASTER_ONLY | FLAG_SAME_SLOT,
+        KeySpec::NumKeys { numkeys: 2 },
+    ),
+    Command::new("EXEC", 0, KeySpec::None),
+    Command::new("EXISTS", 0, ALL_KEYS),
+    Command::new("EXPIRE", FLAG_WRITE, ONE_KEY),
+    Command::new("EXPIREAT", FLAG_WRITE, ONE_KEY),
+    Command::new(
+        "FCALL",
+        FLAG_WRITE | FLAG_SAME_SLOT,
+        KeySpec::NumKeys { numkeys: 2 },
+    ),
+    Command::new(
+        "FCALL_RO",
+        FLAG_MASTER_ONLY | FLAG_SAME_SLOT,
+        KeySpec::NumKeys { numkeys: 2 },
+    ),
+    Command::new("FLUSHALL", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("FLUSHDB", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("FUNCTION", FLAG_WRITE | FLAG_BROADCAST, KeySpec::None),
+    Command::new("GEOADD", FLAG_WRITE, ONE_KEY),
+    Command::new("GEODIST", 0, ONE_KEY),
+    Command::new("GEOHASH", 0, ONE_KEY),
+    Command::new("GEOPOS", 0, ONE_KEY),
+    Command::new("GEORADIUS", FLAG_WRITE, ONE_KEY),
+    Command::new("GEORADIUSBYMEMBER", FLAG_WRITE, ONE_KEY),
+    Command::new("GEOSEARCH", 0, ONE_KEY),
+    Command::new("GET", 0, ONE_KEY),
+    Command::new("GETBIT", 0, ONE_KEY),
+    Command::new("GETDEL", FLAG_WRITE, ONE_KEY),
+    Command::new("GETEX", FLAG_WRITE, ONE_KEY),
+    Command::new("GETRANGE", 0, ONE_KEY),
+    Command::new("GETSET", FLAG_WRITE, ONE_KEY),
+    Command::new("HDEL", FLAG_WRITE, ONE_KEY),
+    Command::new("HEXISTS", 0, ONE_KEY),
+    Command::new("HGET", 0, ONE_KEY),
+    Command::new("HGETALL", 0, ONE_KEY),
+    Command::new("HINCRBY", FLAG_WRITE, ONE_KEY),
+    Command::new("HINCRBYFLOAT", FLAG_WRITE, ONE_KEY),
+    Command::new("HKEYS", 0, ONE_KEY),
+    Command::new("HLEN", 0, ONE_KEY),
+    Command::new("HMGET", 0, ONE_KEY),
+    Command::new("HMSET", FLAG_WRITE, ONE_KEY),
+    Command::new("HRANDFIELD", 0, ONE_KEY),
+    Command::new("HSCAN", FLAG_MASTER_ONLY, ONE_KEY),
+    Command::new("HSET", FLAG_WRITE, ONE_KEY),
+    Command::new("HSETNX", FLAG_WRITE, ONE_KEY),
+    Command::new("HSTRLEN", 0, ONE_KEY),
+    Command::new("HVALS", 0, ONE_KEY),
+    Command::new("INCR", FLAG_WRITE, ONE_KEY),
+    Command::new("INCRBY", FLAG_WRITE, ONE_KEY),
+    Command::new("INCRBYFLOAT", FLAG_WRITE, ONE_KEY),
+    Command::new("INFO", 0, KeySpec::None),
+    Command::new("KEYS", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("LASTSAVE", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("LINDEX", 0, ONE_KEY),
+    Command::new("LINSERT", FLAG_WRITE, ONE_KEY),
+    Command::new("LLEN", 0, ONE_KEY),
+    Command::new("LMOVE", FLAG_WRITE, TWO_KEYS),
+    Command::new("LMPOP", FLAG_WRITE, KeySpec::NumKeys { numkeys: 1 }),
+    Command::new("LPOP", FLAG_WRITE, ONE_KEY),
+    Command::new("LPOS", 0, ONE_KEY),
+    Command::new("LPUSH", FLAG_WRITE, ONE_KEY),
+    Command::new("LPUSHX", FLAG_WRITE, ONE_KEY),
+    Command::new("LRANGE", 0, ONE_KEY),
+    Command::new("LREM", FLAG_WRITE, ONE_KEY),
+    Command::new("LSET", FLAG_WRITE, ONE_KEY),
+    Command::new("LTRIM", FLAG_WRITE, ONE_KEY),
+    Command::new("MGET", 0, ALL_KEYS),
+    Command::new("MIGRATE", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("MONITOR", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("MOVE", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new(
+        "MSET",
+        FLAG_WRITE,
+        KeySpec::Range {
+            first: 1,
+            last: -1,
+            step: 2,
+        },
+    ),
+    Command::new(
+        "MSETNX",
+        FLAG_WRITE,
+        KeySpec::Range {
+            first: 1,
+            last: -1,
+            step: 2,
+        },
+    ),
+    Command::new("MULTI", 0, KeySpec::None),
+    Command::new("OBJECT", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("PERSIST", FLAG_WRITE, ONE_KEY),
+    Command::new("PEXPIRE", FLAG_WRITE, ONE_KEY),
+    Command::new("PEXPIREAT", FLAG_WRITE, ONE_KEY),
+    Command::new("PFADD", FLAG_WRITE, ONE_KEY),
+    Command::new("PFCOUNT", 0, ALL_KEYS),
+    Command::new("PFMERGE", FLAG_WRITE, ALL_KEYS),
+    Command::new("PING", 0, KeySpec::None),
+    Command::new("PSETEX", FLAG_WRITE, ONE_KEY),
+    Command::new("PSUBSCRIBE", FLAG_PUBSUB, KeySpec::None),
+    Command::new("PSYNC", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("PTTL", 0, ONE_KEY),
+    Command::new("PUBLISH", FLAG_MASTER_ONLY | FLAG_PUBSUB, KeySpec::None),
+    Command::new("PUBSUB", FLAG_MASTER_ONLY | FLAG_PUBSUB, KeySpec::None),
+    Command::new("PUNSUBSCRIBE", FLAG_PUBSUB, KeySpec::None),
+    Command::new("QUIT", 0, KeySpec::None),
+    Command::new("RANDOMKEY", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("READONLY", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("READWRITE", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("RENAME", FLAG_WRITE, TWO_KEYS),
+    Command::new("RENAMENX", FLAG_WRITE, TWO_KEYS),
+    Command::new("REPLICAOF", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("RESTORE", FLAG_WRITE, ONE_KEY),
+    Command::new("ROLE", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("RPOP", FLAG_WRITE, ONE_KEY),
+    Command::new("RPOPLPUSH", FLAG_WRITE, TWO_KEYS),
+    Command::new("RPUSH", FLAG_WRITE, ONE_KEY),
+    Command::new("RPUSHX", FLAG_WRITE, ONE_KEY),
+    Command::new("SADD", FLAG_WRITE, ONE_KEY),
+    Command::new("SAVE", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("SCAN", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("SCARD", 0, ONE_KEY),
+    Command::new("SCRIPT", FLAG_WRITE | FLAG_BROADCAST, KeySpec::None),
+    Command::new("SDIFF", 0, ALL_KEYS),
+    Command::new("SDIFFSTORE", FLAG_WRITE, ALL_KEYS),
+    Command::new("SELECT", 0, KeySpec::None),
+    Command::new("SET", FLAG_WRITE, ONE_KEY),
+    Command::new("SETBIT", FLAG_WRITE, ONE_KEY),
+    Command::new("SETEX", FLAG_WRITE, ONE_KEY),
+    Command::new("SETNX", FLAG_WRITE, ONE_KEY),
+    Command::new("SETRANGE", FLAG_WRITE, ONE_KEY),
+    Command::new("SHUTDOWN", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("SINTER", 0, ALL_KEYS),
+    Command::new("SINTERCARD", 0, KeySpec::NumKeys { numkeys: 1 }),
+    Command::new("SINTERSTORE", FLAG_WRITE, ALL_KEYS),
+    Command::new("SISMEMBER", 0, ONE_KEY),
+    Command::new("SLAVEOF", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("SLOTSMGRTONE", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("SLOTSMGRTSLOT", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("SLOTSMGRTTAGONE", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("SLOTSMGRTTAGSLOT", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("SMEMBERS", 0, ONE_KEY),
+    Command::new("SMISMEMBER", 0, ONE_KEY),
+    Command::new("SMOVE", FLAG_WRITE, TWO_KEYS),
+    Command::new("SORT", FLAG_WRITE, ONE_KEY),
+    Command::new("SPOP", FLAG_WRITE, ONE_KEY),
+    Command::new("SRANDMEMBER", 0, ONE_KEY),
+    Command::new("SPUBLISH", FLAG_MASTER_ONLY | FLAG_PUBSUB, KeySpec::None),
+    Command::new("SREM", FLAG_WRITE, ONE_KEY),
+    Command::new("SSCAN", FLAG_MASTER_ONLY, ONE_KEY),
+    Command::new("SSUBSCRIBE", FLAG_PUBSUB, KeySpec::None),
+    Command::new("STRLEN", 0, ONE_KEY),
+    Command::new("SUBSCRIBE", FLAG_PUBSUB, KeySpec::None),
+    Command::new("SUBSTR", 0, ONE_KEY),
+    Command::new("SUNION", 0, ALL_KEYS),
+    Command::new("SUNIONSTORE", FLAG_WRITE, ALL_KEYS),
+    Command::new("SUNSUBSCRIBE", FLAG_PUBSUB, KeySpec::None),
+    Command::new("SYNC", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("TIME", FLAG_NOT_ALLOW, KeySpec::None),
+    Command::new("TOUCH", FLAG_WRITE, ALL_KEYS),
+    Command::new("TTL", 0, ONE_KEY),
+    Command::new("TYPE", 0, ONE_KEY),
+    Command::new("UNLINK", FLAG_WRITE, ALL_KEYS),
+    Command::new("UNSUBSCRIBE", FLAG_PUBSUB, KeySpec::None),
+    Command::new("UNWATCH", 0, KeySpec::None),
+    Command::new("WATCH", 0, ALL_KEYS),
+    Command::new("XACK", FLAG_WRITE, ONE_KEY),
+    Command::new("XADD", FLAG_WRITE, ONE_KEY),
+    Command::new("XAUTOCLAIM", FLAG_WRITE, ONE_KEY),
+    Command::new("XCLAIM", FLAG_WRITE, ONE_KEY),
+    Command::new("XDEL", FLAG_WRITE, ONE_KEY),
+    Command::new(
+        "XGROUP",
+        FLAG_WRITE,
+        KeySpec::Range {
+            first: 2,
+            last: 2,
+            step: 1,
+        },
+    ),
+    Command::new(
+        "XINFO",
+        0,
+        KeySpec::Range {
+            first: 2,
+            last: 2,
+            step: 1,
+        },
+    ),
+    Command::new("XLEN", 0, ONE_KEY),
+    Command::new("XPENDING", 0, ONE_KEY),
+    Command::new("XRANGE", 0, ONE_KEY),
+    Command::new("XREAD", FLAG_MASTER_ONLY | FLAG_BLOCKING, KeySpec::Streams),
+    Command::new("XREADGROUP", FLAG_WRITE | FLAG_BLOCKING, KeySpec::Streams),
+    Command::new("XREVRANGE", 0, ONE_KEY),
+    Command::new("XTRIM", FLAG_WRITE, ONE_KEY),
+    Command::new("ZADD", FLAG_WRITE, ONE_KEY),
+    Command::new("ZCARD", 0, ONE_KEY),
+    Command::new("ZCOUNT", 0, ONE_KEY),
+    Command::new("ZDIFF", 0, KeySpec::NumKeys { numkeys: 1 }),
+    Command::new("ZDIFFSTORE", FLAG_WRITE, KeySpec::DestNumKeys),
+    Command::new("ZINCRBY", FLAG_WRITE, ONE_KEY),
+    Command::new("ZINTER", 0, KeySpec::NumKeys { numkeys: 1 }),
+    Command::new("ZINTERCARD", 0, KeySpec::NumKeys { numkeys: 1 }),
+    Command::new("ZINTERSTORE", FLAG_WRITE, KeySpec::DestNumKeys),
+    Command::new("ZLEXCOUNT", 0, ONE_KEY),
+    Command::new("ZMPOP", FLAG_WRITE, KeySpec::NumKeys { numkeys: 1 }),
+    Command::new("ZMSCORE", 0, ONE_KEY),
+    Command::new("ZPOPMAX", FLAG_WRITE, ONE_KEY),
+    Command::new("ZPOPMIN", FLAG_WRITE, ONE_KEY),
+    Command::new("ZRANDMEMBER", 0, ONE_KEY),
+    Command::new("ZRANGE", 0, ONE_KEY),
+    Command::new("ZRANGEBYLEX", 0, ONE_KEY),
+    Command::new("ZRANGEBYSCORE", 0, ONE_KEY),
+    Command::new("ZRANGESTORE", FLAG_WRITE, TWO_KEYS),
+    Command::new("ZRANK", 0, ONE_KEY),
+    Command::new("ZREM", FLAG_WRITE, ONE_KEY),
+    Command::new("ZREMRANGEBYLEX", FLAG_WRITE, ONE_KEY),
+    Command::new("ZREMRANGEBYRANK", FLAG_WRITE, ONE_KEY),
+    Command::new("ZREMRANGEBYSCORE", FLAG_WRITE, ONE_KEY),
+    Command::new("ZREVRANGE", 0, ONE_KEY),
+    Command::new("ZREVRANGEBYLEX", 0, ONE_KEY),
+    Command::new("ZREVRANGEBYSCORE", 0, ONE_KEY),
+    Command::new("ZREVRANK", 0, ONE_KEY),
+    Command::new("ZSCAN", FLAG_MASTER_ONLY, ONE_KEY),
+    Command::new("ZSCORE", 0, ONE_KEY),
+    Command::new("ZUNION", 0, KeySpec::NumKeys { numkeys: 1 }),
+    Command::new("ZUNIONSTORE", FLAG_WRITE, KeySpec::DestNumKeys),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&'static str]) -> Vec<Bytes> {
+        args.iter()
+            .map(|a| Bytes::from_static(a.as_bytes()))
+            .collect()
+    }
+
+    fn keys(name: &str, cmd: &[&'static str]) -> Vec<Vec<u8>> {
+        get_command(name)
+            .unwrap()
+            .keys
+            .keys(&args(cmd))
+            .into_iter()
+            .map(|k| k.to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn test_command_keys() {
+        assert_eq!(keys("GET", &["GET", "a"]), vec![b"a".to_vec()]);
+        assert_eq!(
+            keys("MSET", &["MSET", "a", "1", "b", "2"]),
+            vec![b"a".to_vec(), b"b".to_vec()]
+        );
+        assert_eq!(
+            keys("BLPOP", &["BLPOP", "a", "b", "0"]),
+            vec![b"a".to_vec(), b"b".to_vec()]
+        );
+        assert_eq!(
+            keys("EVAL", &["EVAL", "return 1", "1", "a", "arg"]),
+            vec![b"a".to_vec()]
+        );
+        assert!(keys("EVAL", &["EVAL", "return 1", "0"]).is_empty());
+        assert_eq!(
+            keys("ZUNIONSTORE", &["ZUNIONSTORE", "d", "2", "a", "b"]),
+            vec![b"d".to_vec(), b"a".to_vec(), b"b".to_vec()]
+        );
+        assert_eq!(
+            keys(
+                "XREAD",
+                &["XREAD", "COUNT", "2", "STREAMS", "a", "b", "0", "0"]
+            ),
+            vec![b"a".to_vec(), b"b".to_vec()]
+        );
+        assert!(keys("PING", &["PING"]).is_empty());
+    }
+
+    #[test]
+    fn test_command_index() {
+        for (i, command) in commands().iter().enumerate() {
+            assert_eq!(command.index(), i);
+        }
+        assert_eq!(commands()[get_command("GET").unwrap().index()].name, "GET");
+    }
+
+    #[test]
+    fn test_command_flags() {
+        assert!(get_command("GET").unwrap().is_read_only());
+        assert!(!get_command("SET").unwrap().is_read_only());
+        assert!(!get_command("HSCAN").unwrap().is_read_only());
+        assert!(get_command("KEYS").unwrap().is_not_allowed());
+        assert!(get_command("BRPOP").unwrap().is_blocking());
+        assert!(!get_command("BRPOP").unwrap().is_not_allowed());
+        assert!(get_command("get").is_none());
+    }
 }
@@ -1,7 +1,40 @@
-use crate::error::Result;
+use anyhow::anyhow;
 
-pub struct ConnectionInfo {}
+use crate::error::{Error, Result};
+
+/// ConnectionInfo describes how to connect to a backend server.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    pub addr: String,
+    pub auth: String,
+    pub database: u32,
+}
 
 pub trait IntoConnectionInfo: Send + Clone + 'static {
     fn into_connection_info(self) -> Result<ConnectionInfo>;
 }
+
+impl IntoConnectionInfo for ConnectionInfo {
+    fn into_connection_info(self) -> Result<ConnectionInfo> {
+        if self.addr.is_empty() {
+            return Err(Error::initialize(anyhow!("backend address is empty")));
+        }
+        Ok(self)
+    }
+}
+
+impl IntoConnectionInfo for String {
+    fn into_connection_info(self) -> Result<ConnectionInfo> {
+        ConnectionInfo {
+            addr: self,
+            ..Default::default()
+        }
+        .into_connection_info()
+    }
+}
+
+impl IntoConnectionInfo for &'static str {
+    fn into_connection_info(self) -> Result<ConnectionInfo> {
+        self.to_string().into_connection_info()
+    }
+}
@@ -3,9 +3,11 @@ mod connection_info;
 mod request;
 mod response;
 mod slots;
+mod topology;
 
 pub use command::*;
 pub use connection_info::*;
 pub use request::*;
 pub use response::*;
 pub use slots::*;
+pub use topology::*;
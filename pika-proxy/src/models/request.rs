@@ -1,4 +1,296 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use bytes::Bytes;
+use redis::{RedisCmd, RedisResp};
+use tokio::sync::oneshot;
+
+use crate::error::{Error, Result};
+use crate::models::{get_command, hash_slot, Command, Response};
+
+/// ReplyMeter counts the bytes of the replies to a client once they are
+/// completed, even if they are not taken by its writer yet.
+pub trait ReplyMeter: Send + Sync {
+    fn count(&self, bytes: u64);
+}
+
+/// A response with the bytes counted by the meter of the request.
+type Reply = (Result<Response>, u64);
+
+/// Request is a command waiting for its response, the response is sent back
+/// through the channel paired with a `PendingResponse`.
 pub struct Request {
-    redis: redis::RedisCmd,
+    redis: RedisCmd,
     id: u64,
+    database: u32,
+    name: String,
+    command: Option<&'static Command>,
+    meter: Option<Arc<dyn ReplyMeter>>,
+    response_channel: oneshot::Sender<Reply>,
+}
+
+impl Request {
+    pub fn new(redis: RedisCmd, id: u64, database: u32) -> (Self, PendingResponse) {
+        Self::with_meter(redis, id, database, None)
+    }
+
+    /// A request of a client, whose reply is counted by `meter` once it's
+    /// responded.
+    pub fn metered(
+        redis: RedisCmd,
+        id: u64,
+        database: u32,
+        meter: Arc<dyn ReplyMeter>,
+    ) -> (Self, PendingResponse) {
+        Self::with_meter(redis, id, database, Some(meter))
+    }
+
+    fn with_meter(
+        redis: RedisCmd,
+        id: u64,
+        database: u32,
+        meter: Option<Arc<dyn ReplyMeter>>,
+    ) -> (Self, PendingResponse) {
+        let (tx, rx) = oneshot::channel();
+        let name = redis.name();
+        let command = get_command(&name);
+        let request_len = redis.encoded_len() as u64;
+        let request = Self {
+            redis,
+            id,
+            database,
+            name,
+            command,
+            meter: meter.clone(),
+            response_channel: tx,
+        };
+        let pending = PendingResponse {
+            id,
+            request_len,
+            op: Some((command, Instant::now())),
+            meter,
+            receiver: rx,
+        };
+        (request, pending)
+    }
+
+    pub fn redis(&self) -> &RedisCmd {
+        &self.redis
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn database(&self) -> u32 {
+        self.database
+    }
+
+    /// Upper-cased command name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Command info, `None` if the command is unknown to proxy.
+    pub fn command(&self) -> Option<&'static Command> {
+        self.command
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.command.is_some_and(|c| c.is_read_only())
+    }
+
+    /// XREAD and XREADGROUP block only with the BLOCK option.
+    pub fn is_blocking(&self) -> bool {
+        match self.command {
+            Some(command) if command.is_blocking() => match self.name.as_str() {
+                "XREAD" | "XREADGROUP" => self.block_option().is_some(),
+                _ => true,
+            },
+            _ => false,
+        }
+    }
+
+    /// The timeout of a blocking command, zero means it blocks forever, the
+    /// same as redis.
+    pub fn block_timeout(&self) -> Duration {
+        let secs = |arg: Option<&Bytes>| {
+            arg.and_then(|arg| std::str::from_utf8(arg).ok())
+                .and_then(|arg| arg.parse::<f64>().ok())
+                .filter(|secs| secs.is_finite() && *secs > 0.0)
+                .map(Duration::from_secs_f64)
+                .unwrap_or_default()
+        };
+        match self.name.as_str() {
+            "XREAD" | "XREADGROUP" => self
+                .block_option()
+                .map(|ms| Duration::from_millis(ms.max(0) as u64))
+                .unwrap_or_default(),
+            // the timeout comes before the keys
+            "BLMPOP" | "BZMPOP" => secs(self.redis.arg(1)),
+            _ => secs(self.redis.args().last()),
+        }
+    }
+
+    fn block_option(&self) -> Option<i64> {
+        let args = self.redis.args();
+        let pos = args
+            .iter()
+            .position(|arg| arg.eq_ignore_ascii_case(b"BLOCK"))?;
+        std::str::from_utf8(args.get(pos + 1)?).ok()?.parse().ok()
+    }
+
+    pub fn keys(&self) -> Vec<&[u8]> {
+        match self.command {
+            Some(command) => command.keys.keys(self.redis.args()),
+            None => self.redis.arg(1).map(|k| &k[..]).into_iter().collect(),
+        }
+    }
+
+    /// The key used to route the request, unknown commands use their first
+    /// argument like codis does.
+    pub fn hash_key(&self) -> Option<&[u8]> {
+        match self.command {
+            Some(command) => command.keys.keys(self.redis.args()).first().copied(),
+            None => self.redis.arg(1).map(|k| &k[..]),
+        }
+    }
+
+    /// Returns true if the keys hash to different slots.
+    pub fn is_cross_slot(&self) -> bool {
+        let mut slots = self.keys().into_iter().map(hash_slot);
+        match slots.next() {
+            Some(first) => slots.any(|slot| slot != first),
+            None => false,
+        }
+    }
+
+    pub fn slot(&self) -> u64 {
+        hash_slot(self.hash_key().unwrap_or_default())
+    }
+
+    /// Sends the response back, it's fine if nobody is waiting any more.
+    pub fn respond(self, result: Result<RedisResp>) {
+        let id = self.id;
+        let counted = count(self.meter.as_deref(), &result);
+        let _ = self
+            .response_channel
+            .send((result.map(|redis| Response::new(redis, id)), counted));
+    }
+}
+
+/// Counts the encoded bytes of `result` by `meter`, returns the bytes counted.
+fn count(meter: Option<&dyn ReplyMeter>, result: &Result<RedisResp>) -> u64 {
+    let Some(meter) = meter else {
+        return 0;
+    };
+    let bytes = match result {
+        Ok(resp) => resp.encoded_len(),
+        Err(e) => e.to_resp().encoded_len(),
+    } as u64;
+    meter.count(bytes);
+    bytes
+}
+
+/// PendingResponse resolves once the paired `Request` is responded.
+pub struct PendingResponse {
+    id: u64,
+    // bytes of the request, held until the response is written
+    request_len: u64,
+    // the command requested and when, none for the pushes of subscriptions
+    op: Option<(Option<&'static Command>, Instant)>,
+    meter: Option<Arc<dyn ReplyMeter>>,
+    receiver: oneshot::Receiver<Reply>,
+}
+
+impl PendingResponse {
+    /// A response which is already known, such as a local error.
+    pub fn ready(id: u64, result: Result<RedisResp>) -> Self {
+        Self::resolved(id, result, None)
+    }
+
+    /// A response already known, such as a push of a subscription, which is
+    /// counted by `meter` at once.
+    pub fn metered(id: u64, result: Result<RedisResp>, meter: Arc<dyn ReplyMeter>) -> Self {
+        Self::resolved(id, result, Some(meter))
+    }
+
+    fn resolved(id: u64, result: Result<RedisResp>, meter: Option<Arc<dyn ReplyMeter>>) -> Self {
+        let (tx, rx) = oneshot::channel();
+        let counted = count(meter.as_deref(), &result);
+        let _ = tx.send((result.map(|redis| Response::new(redis, id)), counted));
+        Self {
+            id,
+            request_len: 0,
+            op: None,
+            meter,
+            receiver: rx,
+        }
+    }
+
+    /// Resolves to `result` instead, such as a request failed before it's
+    /// sent, the request is still counted.
+    pub fn replace(self, result: Result<RedisResp>) -> Self {
+        Self {
+            request_len: self.request_len,
+            op: self.op,
+            ..Self::resolved(self.id, result, self.meter)
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn request_len(&self) -> u64 {
+        self.request_len
+    }
+
+    /// The command requested and when, if it's a response of a request.
+    pub fn op(&self) -> Option<(Option<&'static Command>, Instant)> {
+        self.op
+    }
+
+    pub async fn wait(self) -> Result<Response> {
+        self.wait_counted().await.0
+    }
+
+    /// Waits for the response, and the bytes of it counted by the meter, zero
+    /// if it's not metered or the request is dropped.
+    pub async fn wait_counted(self) -> Reply {
+        self.receiver.await.unwrap_or_else(|_| {
+            let e = Error::proxy(anyhow!("request is dropped before responded"));
+            (Err(e), 0)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(args: &[&str]) -> Request {
+        let cmd = RedisCmd::new(args.iter().map(|arg| arg.to_string()));
+        Request::new(cmd, 1, 0).0
+    }
+
+    #[test]
+    fn test_block_timeout() {
+        let brpop = request(&["BRPOP", "a", "b", "1.5"]);
+        assert!(brpop.is_blocking());
+        assert_eq!(brpop.block_timeout(), Duration::from_millis(1500));
+        let blmpop = request(&["BLMPOP", "2", "1", "a", "LEFT"]);
+        assert_eq!(blmpop.block_timeout(), Duration::from_secs(2));
+        assert_eq!(
+            request(&["BLPOP", "a", "0"]).block_timeout(),
+            Duration::ZERO
+        );
+
+        let xread = request(&["XREAD", "BLOCK", "100", "STREAMS", "a", "$"]);
+        assert!(xread.is_blocking());
+        assert_eq!(xread.block_timeout(), Duration::from_millis(100));
+        assert!(!request(&["XREAD", "STREAMS", "a", "0"]).is_blocking());
+        assert!(!request(&["GET", "a"]).is_blocking());
+    }
 }
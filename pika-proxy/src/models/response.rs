@@ -1,4 +1,24 @@
+use redis::RedisResp;
+
 pub struct Response {
-    redis: redis::RedisResp,
+    redis: RedisResp,
     id: u64,
 }
+
+impl Response {
+    pub fn new(redis: RedisResp, id: u64) -> Self {
+        Self { redis, id }
+    }
+
+    pub fn redis(&self) -> &RedisResp {
+        &self.redis
+    }
+
+    pub fn into_redis(self) -> RedisResp {
+        self.redis
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
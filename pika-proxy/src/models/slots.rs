@@ -1,14 +1,52 @@
-const MAX_SLOT_NUM: usize = 1024;
+use serde::{Deserialize, Serialize};
 
+pub const MAX_SLOT_NUM: usize = 1024;
+
+/// Slot is the routing model of a slot, shared with codis-dashboard.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
 pub struct Slot {
-    id: u64,
-    locked: bool,
+    pub id: u64,
+    pub locked: bool,
+
+    pub backend_addr: String,
+    #[serde(rename = "backend_addr_group_id")]
+    pub backend_add_group_id: u64,
+    pub migrate_from: String,
+    pub migrate_from_group_id: u64,
+
+    pub forward_method: u64,
+    pub replica_groups: Vec<Vec<String>>,
+}
+
+/// Returns the slot of `key`, only the hash tag inside `{}` is hashed if
+/// there is one, the same as codis.
+pub fn hash_slot(key: &[u8]) -> u64 {
+    crc32fast::hash(hash_tag(key)) as u64 % MAX_SLOT_NUM as u64
+}
+
+fn hash_tag(key: &[u8]) -> &[u8] {
+    if let Some(beg) = key.iter().position(|b| *b == b'{') {
+        if let Some(end) = key[beg + 1..].iter().position(|b| *b == b'}') {
+            return &key[beg + 1..beg + 1 + end];
+        }
+    }
+    key
+}
 
-    backend_addr: String,
-    backend_add_group_id: u64,
-    migrate_from: String,
-    migrate_from_group_id: u64,
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    forward_method: u64,
-    replica_groups: Vec<Vec<String>>,
+    #[test]
+    fn test_hash_slot() {
+        assert_eq!(hash_slot(b"foo"), crc32fast::hash(b"foo") as u64 % 1024);
+        assert_eq!(hash_slot(b"{user1}.name"), hash_slot(b"{user1}.age"));
+        assert_eq!(hash_slot(b"{user1}.name"), hash_slot(b"user1"));
+        assert_eq!(
+            hash_slot(b"{user1"),
+            crc32fast::hash(b"{user1") as u64 % 1024
+        );
+        assert_eq!(hash_slot(b"{}.name"), 0);
+    }
 }
@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::Slot;
+
+/// Root of the codis keys in a registry.
+pub const CODIS_DIR: &str = "/codis3";
+
+/// Paths of the codis models of a product, such as
+/// `/codis3/<product_name>/slots/slot-0001`.
+#[derive(Debug, Clone)]
+pub struct CodisPaths {
+    product_dir: String,
+}
+
+impl CodisPaths {
+    pub fn new(product_name: &str) -> Self {
+        Self {
+            product_dir: format!("{}/{}", CODIS_DIR, product_name),
+        }
+    }
+
+    pub fn product_dir(&self) -> &str {
+        &self.product_dir
+    }
+
+    pub fn slot_dir(&self) -> String {
+        format!("{}/slots", self.product_dir)
+    }
+
+    pub fn slot_path(&self, id: u64) -> String {
+        format!("{}/slot-{:04}", self.slot_dir(), id)
+    }
+
+    pub fn group_dir(&self) -> String {
+        format!("{}/group", self.product_dir)
+    }
+
+    pub fn group_path(&self, id: u64) -> String {
+        format!("{}/group-{:04}", self.group_dir(), id)
+    }
+
+    pub fn proxy_dir(&self) -> String {
+        format!("{}/proxy", self.product_dir)
+    }
+
+    pub fn proxy_path(&self, token: &str) -> String {
+        format!("{}/proxy-{}", self.proxy_dir(), token)
+    }
+
+    pub fn sentinel_path(&self) -> String {
+        format!("{}/sentinel", self.product_dir)
+    }
+}
+
+/// Action of a slot in migration, `target_id` is the group migrated to.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct SlotAction {
+    #[serde(skip_serializing_if = "is_zero")]
+    pub index: u64,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub state: String,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub target_id: u64,
+}
+
+/// States of `SlotAction`, a slot is migrated from `group_id` to
+/// `target_id` through them in order.
+pub const ACTION_PENDING: &str = "pending";
+pub const ACTION_PREPARING: &str = "preparing";
+pub const ACTION_PREPARED: &str = "prepared";
+pub const ACTION_MIGRATING: &str = "migrating";
+pub const ACTION_FINISHED: &str = "finished";
+
+/// SlotMapping is the codis model of which group serves a slot.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct SlotMapping {
+    pub id: u64,
+    pub group_id: u64,
+    pub action: SlotAction,
+}
+
+impl SlotMapping {
+    /// Builds the routing model of the slot the same way as codis-dashboard,
+    /// replicas in `datacenter` are preferred for reads.
+    pub fn to_slot(&self, groups: &HashMap<u64, Group>, datacenter: &str) -> Slot {
+        let master = |id: u64| {
+            groups
+                .get(&id)
+                .map(|group| group.master().to_string())
+                .unwrap_or_default()
+        };
+        let locked = |id: u64| groups.get(&id).is_some_and(Group::is_locked);
+        let mut slot = Slot {
+            id: self.id,
+            ..Default::default()
+        };
+        let (source, target) = (self.group_id, self.action.target_id);
+        match self.action.state.as_str() {
+            // all proxies are locked at the barrier before the migration
+            ACTION_PREPARED | ACTION_MIGRATING => {
+                slot.locked =
+                    self.action.state == ACTION_PREPARED || locked(source) || locked(target);
+                slot.backend_addr = master(target);
+                slot.backend_add_group_id = target;
+                slot.migrate_from = master(source);
+                slot.migrate_from_group_id = source;
+            }
+            ACTION_FINISHED => {
+                slot.locked = locked(target);
+                slot.backend_addr = master(target);
+                slot.backend_add_group_id = target;
+            }
+            // replicas are not read once the migration is going to start
+            ACTION_PREPARING => {
+                slot.locked = locked(source);
+                slot.backend_addr = master(source);
+                slot.backend_add_group_id = source;
+            }
+            _ => {
+                slot.locked = locked(source);
+                slot.backend_addr = master(source);
+                slot.backend_add_group_id = source;
+                if let Some(group) = groups.get(&source) {
+                    slot.replica_groups = group.replica_groups(datacenter);
+                }
+            }
+        }
+        slot
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct GroupAction {
+    #[serde(skip_serializing_if = "is_zero")]
+    pub index: u64,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub state: String,
+}
+
+/// GroupServer is a server of a group, the first one is the master.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct GroupServer {
+    #[serde(rename = "server")]
+    pub addr: String,
+    pub datacenter: String,
+    pub action: GroupAction,
+    pub replica_group: bool,
+}
+
+/// Group is the codis model of a master and its replicas.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Group {
+    pub id: u64,
+    pub servers: Vec<GroupServer>,
+    pub promoting: GroupAction,
+    pub out_of_sync: bool,
+}
+
+impl Group {
+    /// A group is locked while its replica is being promoted, the same as
+    /// codis-dashboard.
+    pub fn is_locked(&self) -> bool {
+        self.promoting.state == ACTION_PREPARED
+    }
+
+    /// Address of the master, empty if the group has no server.
+    pub fn master(&self) -> &str {
+        self.servers
+            .first()
+            .map(|server| server.addr.as_str())
+            .unwrap_or_default()
+    }
+
+    /// Servers which serve reads, the ones in `datacenter` come first.
+    pub fn replica_groups(&self, datacenter: &str) -> Vec<Vec<String>> {
+        let (local, remote): (Vec<_>, Vec<_>) = self
+            .servers
+            .iter()
+            .filter(|server| server.replica_group)
+            .partition(|server| server.datacenter == datacenter);
+        [local, remote]
+            .into_iter()
+            .filter(|servers| !servers.is_empty())
+            .map(|servers| servers.iter().map(|s| s.addr.clone()).collect())
+            .collect()
+    }
+}
+
+/// ProxyModel is how a proxy is registered, the same as codis.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct ProxyModel {
+    #[serde(skip_serializing_if = "is_zero")]
+    pub id: u64,
+    pub token: String,
+    pub start_time: String,
+    pub admin_addr: String,
+    pub proto_type: String,
+    pub proxy_addr: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub jodis_path: String,
+    pub product_name: String,
+    pub pid: u32,
+    pub pwd: String,
+    pub sys: String,
+    pub hostname: String,
+    pub datacenter: String,
+}
+
+/// Sentinel is the codis model of the sentinels which monitor all groups.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct SentinelModel {
+    pub servers: Vec<String>,
+    pub out_of_sync: bool,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codis_models() {
+        let paths = CodisPaths::new("codis-demo");
+        assert_eq!(paths.slot_path(1), "/codis3/codis-demo/slots/slot-0001");
+        assert_eq!(paths.group_path(12), "/codis3/codis-demo/group/group-0012");
+        assert_eq!(
+            paths.proxy_path("abc"),
+            "/codis3/codis-demo/proxy/proxy-abc"
+        );
+
+        let slot: SlotMapping = serde_json::from_str(
+            r#"{"id":1,"group_id":2,"action":{"index":3,"state":"pending","target_id":4}}"#,
+        )
+        .unwrap();
+        assert_eq!(slot.action.target_id, 4);
+        let slot = SlotMapping {
+            id: 1,
+            group_id: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_string(&slot).unwrap(),
+            r#"{"id":1,"group_id":2,"action":{}}"#
+        );
+
+        let group: Group = serde_json::from_str(
+            r#"{"id":1,"servers":[{"server":"127.0.0.1:6379","datacenter":"","action":{},"replica_group":false},
+                {"server":"127.0.0.1:6380","datacenter":"","action":{},"replica_group":true}],
+                "promoting":{},"out_of_sync":false}"#,
+        )
+        .unwrap();
+        assert_eq!(group.master(), "127.0.0.1:6379");
+        assert!(group.servers[1].replica_group);
+        assert_eq!(group.replica_groups(""), vec![vec!["127.0.0.1:6380"]]);
+
+        let source = Group {
+            id: 3,
+            servers: vec![GroupServer {
+                addr: "127.0.0.1:6381".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let groups = HashMap::from([(2, group.clone()), (3, source)]);
+        let slot = SlotMapping {
+            id: 1,
+            group_id: 2,
+            ..Default::default()
+        }
+        .to_slot(&groups, "");
+        assert_eq!(slot.backend_addr, "127.0.0.1:6379");
+        assert_eq!(slot.replica_groups.len(), 1);
+        let migrating = SlotMapping {
+            id: 1,
+            group_id: 3,
+            action: SlotAction {
+                state: ACTION_MIGRATING.to_string(),
+                target_id: 2,
+                ..Default::default()
+            },
+        };
+        let slot = migrating.to_slot(&groups, "");
+        assert_eq!(slot.backend_add_group_id, 2);
+        assert_eq!(slot.migrate_from, "127.0.0.1:6381");
+        assert!(!slot.locked && slot.replica_groups.is_empty());
+    }
+
+    #[test]
+    fn test_slot_action_states() {
+        let group = |id: u64, addr: &str, promoting: &str| Group {
+            id,
+            servers: vec![
+                GroupServer {
+                    addr: addr.to_string(),
+                    ..Default::default()
+                },
+                GroupServer {
+                    addr: format!("{}-replica", addr),
+                    replica_group: true,
+                    ..Default::default()
+                },
+            ],
+            promoting: GroupAction {
+                state: promoting.to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mapping = |state: &str| SlotMapping {
+            id: 1,
+            group_id: 1,
+            action: SlotAction {
+                state: state.to_string(),
+                target_id: 2,
+                ..Default::default()
+            },
+        };
+        let source = "127.0.0.1:6379";
+        let target = "127.0.0.1:6380";
+        let replicas = vec![vec![format!("{}-replica", source)]];
+        // (state, backend, migrate from, replicas, locked)
+        let cases = [
+            ("", source, "", replicas.clone(), false),
+            (ACTION_PENDING, source, "", replicas.clone(), false),
+            (ACTION_PREPARING, source, "", vec![], false),
+            (ACTION_PREPARED, target, source, vec![], true),
+            (ACTION_MIGRATING, target, source, vec![], false),
+            (ACTION_FINISHED, target, "", vec![], false),
+        ];
+        for (state, backend, from, replica_groups, locked) in cases {
+            let groups = HashMap::from([(1, group(1, source, "")), (2, group(2, target, ""))]);
+            let slot = mapping(state).to_slot(&groups, "");
+            assert_eq!(slot.backend_addr, backend, "{}", state);
+            assert_eq!(slot.migrate_from, from, "{}", state);
+            assert_eq!(slot.replica_groups, replica_groups, "{}", state);
+            assert_eq!(slot.locked, locked, "{}", state);
+
+            // a promoting group serving the slot locks it in any state
+            let (id, addr) = if backend == source {
+                (1, source)
+            } else {
+                (2, target)
+            };
+            let mut groups = groups;
+            groups.insert(id, group(id, addr, ACTION_PREPARED));
+            let slot = mapping(state).to_slot(&groups, "");
+            assert!(slot.locked, "{} promoting", state);
+            // a group which is only preparing the promotion is not locked
+            groups.insert(id, group(id, addr, ACTION_PREPARING));
+            let slot = mapping(state).to_slot(&groups, "");
+            assert_eq!(slot.locked, locked, "{} preparing promotion", state);
+        }
+
+        // the source of a migrating slot locks it as well
+        let groups = HashMap::from([
+            (1, group(1, source, ACTION_PREPARED)),
+            (2, group(2, target, "")),
+        ]);
+        assert!(mapping(ACTION_MIGRATING).to_slot(&groups, "").locked);
+        assert!(!mapping(ACTION_FINISHED).to_slot(&groups, "").locked);
+    }
+}
@@ -0,0 +1,161 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use dashmap::DashMap;
+use redis::{RedisCmd, RedisRequester, RedisResp, RedisResponseReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+
+use crate::error::{Error, Result};
+use crate::models::ConnectionInfo;
+use crate::proxy::backend::connection_pool::db_connection::open_connection;
+use crate::proxy::config::ConfigHandle;
+use crate::utils::time::with_timeout;
+
+/// BlockingConnection runs one blocking command at a time, such as BLPOP,
+/// so a blocked command never stalls requests of other sessions.
+pub struct BlockingConnection {
+    info: ConnectionInfo,
+    config: ConfigHandle,
+    requester: RedisRequester<OwnedWriteHalf>,
+    reader: RedisResponseReader<OwnedReadHalf>,
+    // counted as checked out of the pool until it's put back or dropped
+    busy: Option<Busy>,
+}
+
+/// Busy is a checked out connection of a server, uncounted once dropped.
+struct Busy(Arc<AtomicUsize>);
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl BlockingConnection {
+    pub async fn connect(info: ConnectionInfo, config: ConfigHandle) -> Result<Self> {
+        let (requester, reader) = open_connection(&info, &config.load_full()).await?;
+        Ok(Self {
+            info,
+            config,
+            requester,
+            reader,
+            busy: None,
+        })
+    }
+
+    pub fn addr(&self) -> &str {
+        &self.info.addr
+    }
+
+    pub fn database(&self) -> u32 {
+        self.info.database
+    }
+
+    /// Sends `cmd` and waits for its response without a read timeout, the
+    /// caller decides how long a blocking command may wait.
+    pub async fn call(&mut self, cmd: &RedisCmd) -> Result<RedisResp> {
+        let requester = &mut self.requester;
+        with_timeout(self.config.load().backend.send_timeout, async {
+            Ok(requester.send_request(cmd).await?)
+        })
+        .await?;
+        Ok(self.reader.read_response().await?)
+    }
+}
+
+/// BlockingPool keeps a few idle blocking connections for each backend
+/// server, connections beyond `blocking_pool_size` are closed after use. At
+/// most `max_blocking_conns` connections of a server are checked out at once.
+pub struct BlockingPool {
+    config: ConfigHandle,
+    idle: DashMap<String, Vec<BlockingConnection>>,
+    busy: DashMap<String, Arc<AtomicUsize>>,
+}
+
+impl BlockingPool {
+    pub fn new(config: ConfigHandle) -> Self {
+        Self {
+            config,
+            idle: DashMap::new(),
+            busy: DashMap::new(),
+        }
+    }
+
+    /// Takes an idle connection to `info`, or connects a new one, fails if
+    /// too many connections to the server are checked out.
+    pub async fn take(&self, info: ConnectionInfo) -> Result<BlockingConnection> {
+        let busy = self.check_out(&info.addr)?;
+        let idle = self.idle.get_mut(&info.addr).and_then(|mut conns| {
+            let pos = conns.iter().position(|c| c.database() == info.database)?;
+            Some(conns.swap_remove(pos))
+        });
+        let mut conn = match idle {
+            Some(conn) => conn,
+            None => BlockingConnection::connect(info, self.config.clone()).await?,
+        };
+        conn.busy = Some(busy);
+        Ok(conn)
+    }
+
+    fn check_out(&self, addr: &str) -> Result<Busy> {
+        let max = self.config.load().backend.max_blocking_conns as usize;
+        let busy = self.busy.entry(addr.to_string()).or_default().clone();
+        busy.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+            (max == 0 || n < max).then_some(n + 1)
+        })
+        .map_err(|_| {
+            Error::proxy(anyhow!(
+                "too many blocking connections to {}, max is {}",
+                addr,
+                max
+            ))
+        })?;
+        Ok(Busy(busy))
+    }
+
+    /// Returns a connection whose last command is completed.
+    pub fn put(&self, mut conn: BlockingConnection) {
+        conn.busy = None;
+        let max_idle = self.config.load().backend.blocking_pool_size as usize;
+        let mut conns = self.idle.entry(conn.addr().to_string()).or_default();
+        if conns.len() < max_idle {
+            conns.push(conn);
+        }
+    }
+
+    /// Closes idle connections to `addr`.
+    pub fn clear(&self, addr: &str) {
+        self.idle.remove(addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::config::Config;
+    use crate::utils::testing::FakeBackend;
+
+    #[tokio::test]
+    async fn test_max_blocking_conns() {
+        let server = FakeBackend::start().await;
+        let mut config = Config::default();
+        config.backend.blocking_pool_size = 1;
+        config.backend.max_blocking_conns = 1;
+        let pool = BlockingPool::new(ConfigHandle::new(config));
+        let info = || ConnectionInfo {
+            addr: server.addr.clone(),
+            ..Default::default()
+        };
+
+        let conn = pool.take(info()).await.unwrap();
+        let err = pool.take(info()).await.err().unwrap();
+        assert!(err.to_string().contains("too many blocking connections"));
+        // an idle connection is not counted
+        pool.put(conn);
+        let conn = pool.take(info()).await.unwrap();
+        assert!(pool.take(info()).await.is_err());
+        drop(conn);
+        assert!(pool.take(info()).await.is_ok());
+    }
+}
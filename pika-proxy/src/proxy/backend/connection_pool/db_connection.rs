@@ -1,36 +1,393 @@
-use crate::error::Result;
-use crate::models::{IntoConnectionInfo, Request};
-use tokio::sync::mpsc::Receiver;
+use std::future::pending;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use redis::{RedisCmd, RedisRequester, RedisResponseReader};
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::error::{TryRecvError, TrySendError};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tokio::time::{interval_at, Instant, Interval};
 use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use crate::error::{Error, Result};
+use crate::models::{ConnectionInfo, IntoConnectionInfo, Request};
+use crate::proxy::config::{Config, ConfigHandle};
+use crate::utils::histogram::Histogram;
+use crate::utils::time::with_timeout;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
 
+/// DbConnection is a pipelined connection to one database of a backend
+/// server, requests are written in order and responded in the same order.
 pub struct DbConnection {
-    //client: Client,
-    cmd_channel: Receiver<Request>,
+    info: ConnectionInfo,
+    config: ConfigHandle,
+    cmd_channel: UnboundedReceiver<Request>,
+    // round trips in microseconds, from a request written to its response
+    latency: Arc<Histogram>,
+    // a dedicated connection is never reconnected or pinged, since the
+    // state of its session, such as WATCH, is lost with the connection
+    dedicated: bool,
 }
 
 impl DbConnection {
     pub fn new<I: IntoConnectionInfo>(
         info: I,
-        mut request_chan: Receiver<Request>,
+        config: ConfigHandle,
+        request_chan: UnboundedReceiver<Request>,
+        latency: Arc<Histogram>,
     ) -> Result<Self> {
-        unimplemented!();
-    }
-
-    pub async fn run(&self, cancel: CancellationToken) -> Result<()> {
-        // let mut con = self.client.get_async_connection().await?;
-        // while !cancel.is_cancelled() {
-        //     tokio::select! {
-        //         Some(c) =self.cmd_channel.recv() => {
-        //             let res = con.req_packed_command(&c.redis_cmd).await?;
-        //             let response = Response{};
-        //             //c.response_channel.send(response).await?;
-        //             println!("res: {:?}", res);
-        //         }
-        //         _ = cancel.cancelled() => {
-        //             break;
-        //         }
-        //     }
-        // }
-        Ok(())
+        Ok(Self {
+            info: info.into_connection_info()?,
+            config,
+            cmd_channel: request_chan,
+            latency,
+            dedicated: false,
+        })
+    }
+
+    /// Serves requests until the request channel is closed or `cancel` is
+    /// cancelled, the connection is (re)established on demand, except for a
+    /// dedicated connection, whose requests all fail once it's broken.
+    pub async fn run(mut self, cancel: CancellationToken) -> Result<()> {
+        loop {
+            let request = tokio::select! {
+                request = self.cmd_channel.recv() => match request {
+                    Some(request) => request,
+                    None => return Ok(()),
+                },
+                _ = cancel.cancelled() => return Ok(()),
+            };
+            let stream = match connect(&self.info, &self.config.load_full()).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("connect to backend {} failed: {}", self.info.addr, e);
+                    request.respond(Err(e));
+                    self.fail_queued();
+                    if self.dedicated {
+                        return self.fail_all(cancel).await;
+                    }
+                    continue;
+                }
+            };
+            match self.serve(stream, request, &cancel).await {
+                Ok(()) => return Ok(()),
+                Err(e) => warn!("backend {} connection is broken: {}", self.info.addr, e),
+            }
+            if self.dedicated {
+                return self.fail_all(cancel).await;
+            }
+        }
+    }
+
+    /// Fails every request until the request channel is closed.
+    async fn fail_all(mut self, cancel: CancellationToken) -> Result<()> {
+        loop {
+            tokio::select! {
+                request = self.cmd_channel.recv() => match request {
+                    Some(request) => request.respond(Err(self.lost())),
+                    None => return Ok(()),
+                },
+                _ = cancel.cancelled() => return Ok(()),
+            }
+        }
+    }
+
+    fn lost(&self) -> Error {
+        Error::network(anyhow!("backend {} connection is lost", self.info.addr))
+    }
+
+    /// Fails the queued requests, so they don't wait for another connect.
+    fn fail_queued(&mut self) {
+        while let Ok(request) = self.cmd_channel.try_recv() {
+            request.respond(Err(self.unavailable()));
+        }
+    }
+
+    fn unavailable(&self) -> Error {
+        Error::network(anyhow!("backend {} is unavailable", self.info.addr))
+    }
+
+    /// Serves requests on an established connection, returns `Ok` when the
+    /// connection is closed on purpose.
+    async fn serve(
+        &mut self,
+        stream: TcpStream,
+        first: Request,
+        cancel: &CancellationToken,
+    ) -> Result<()> {
+        // the buffers are sized for the connection, the timeouts are
+        // loaded on every use so a reload applies to them at once
+        let config = self.config.load_full();
+        let backend = &config.backend;
+        let (read_half, write_half) = stream.into_split();
+        let mut requester =
+            RedisRequester::with_capacity(write_half, backend.send_bufsize as usize);
+        let mut reader =
+            RedisResponseReader::with_capacity(read_half, backend.recv_bufsize as usize);
+        if let Err(e) = handshake(&self.info, &config, &mut requester, &mut reader).await {
+            first.respond(Err(e));
+            self.fail_queued();
+            return Err(self.unavailable());
+        }
+
+        let handle = &self.config;
+        let send_timeout = || handle.load().backend.send_timeout;
+        let recv_timeout = || handle.load().backend.recv_timeout;
+        // a ping could land inside a transaction of a dedicated connection
+        let mut ping = backend
+            .ping_period
+            .filter(|_| !self.dedicated)
+            .map(|period| interval_at(Instant::now() + period, period));
+        let database = self.info.database;
+        let (pending_tx, mut pending_rx) =
+            mpsc::channel::<(Request, Instant)>(backend.max_pipeline.max(1) as usize);
+        let cmd_channel = &mut self.cmd_channel;
+
+        let writer = async move {
+            let mut next = Some(first);
+            loop {
+                let request = match next.take() {
+                    Some(request) => request,
+                    None => match cmd_channel.try_recv() {
+                        Ok(request) => request,
+                        Err(TryRecvError::Empty) => {
+                            with_timeout(send_timeout(), async { Ok(requester.flush().await?) })
+                                .await?;
+                            tokio::select! {
+                                request = cmd_channel.recv() => match request {
+                                    Some(request) => request,
+                                    None => break,
+                                },
+                                _ = cancel.cancelled() => break,
+                                _ = tick(&mut ping) => {
+                                    Request::new(RedisCmd::new(["PING"]), 0, database).0
+                                }
+                            }
+                        }
+                        Err(TryRecvError::Disconnected) => break,
+                    },
+                };
+                requester.feed_request(request.redis());
+                match pending_tx.try_send((request, Instant::now())) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(request)) => {
+                        // the pipeline is full, let backend see what's buffered
+                        // before waiting for responses
+                        with_timeout(send_timeout(), async { Ok(requester.flush().await?) })
+                            .await?;
+                        if pending_tx.send(request).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(TrySendError::Closed(_)) => break,
+                }
+            }
+            with_timeout(send_timeout(), async { Ok(requester.flush().await?) }).await
+        };
+        let addr = &self.info.addr;
+        let latency = &self.latency;
+        let responder = async {
+            while let Some((request, written)) = pending_rx.recv().await {
+                let result =
+                    with_timeout(recv_timeout(), async { Ok(reader.read_response().await?) }).await;
+                match result {
+                    Ok(resp) => {
+                        latency.record(written.elapsed().as_micros() as u64);
+                        request.respond(Ok(resp))
+                    }
+                    Err(e) => {
+                        let msg = format!("backend {} connection is broken", addr);
+                        request.respond(Err(Error::network(anyhow!(msg))));
+                        return Err(e);
+                    }
+                }
+            }
+            Ok(())
+        };
+
+        let result = tokio::try_join!(writer, responder).map(|_| ());
+        if result.is_err() {
+            while let Ok((request, _)) = pending_rx.try_recv() {
+                request.respond(Err(self.unavailable()));
+            }
+        }
+        result
+    }
+}
+
+/// Connects to the backend server of `info`.
+async fn connect(info: &ConnectionInfo, config: &Config) -> Result<TcpStream> {
+    let stream = with_timeout(CONNECT_TIMEOUT, async {
+        TcpStream::connect(&info.addr)
+            .await
+            .map_err(|e| Error::network(anyhow!("connect to backend {} failed: {}", info.addr, e)))
+    })
+    .await?;
+    stream.set_nodelay(true).map_err(Error::network)?;
+    if let Some(keepalive) = config.backend.keepalive_period {
+        SockRef::from(&stream)
+            .set_tcp_keepalive(&TcpKeepalive::new().with_time(keepalive))
+            .map_err(Error::network)?;
+    }
+    debug!("connected to backend {}", info.addr);
+    Ok(stream)
+}
+
+/// Sends AUTH and SELECT of `info` on a new connection.
+async fn handshake(
+    info: &ConnectionInfo,
+    config: &Config,
+    requester: &mut RedisRequester<OwnedWriteHalf>,
+    reader: &mut RedisResponseReader<OwnedReadHalf>,
+) -> Result<()> {
+    let mut cmds = vec![];
+    if !info.auth.is_empty() {
+        cmds.push(RedisCmd::new(["AUTH".to_string(), info.auth.clone()]));
+    }
+    if info.database != 0 {
+        cmds.push(RedisCmd::new([
+            "SELECT".to_string(),
+            info.database.to_string(),
+        ]));
+    }
+    for cmd in cmds {
+        requester.send_request(&cmd).await?;
+        let resp = with_timeout(config.backend.recv_timeout, async {
+            Ok(reader.read_response().await?)
+        })
+        .await?;
+        if let Some(msg) = resp.error_message() {
+            return Err(Error::server(anyhow!(
+                "backend {} rejected {}: {}",
+                info.addr,
+                cmd.name(),
+                msg
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Opens a connection which is owned by the caller rather than a
+/// `DbConnection`, such as the one of a subscribed session.
+pub async fn open_connection(
+    info: &ConnectionInfo,
+    config: &Config,
+) -> Result<(
+    RedisRequester<OwnedWriteHalf>,
+    RedisResponseReader<OwnedReadHalf>,
+)> {
+    let backend = &config.backend;
+    let (read_half, write_half) = connect(info, config).await?.into_split();
+    let mut requester = RedisRequester::with_capacity(write_half, backend.send_bufsize as usize);
+    let mut reader = RedisResponseReader::with_capacity(read_half, backend.recv_bufsize as usize);
+    handshake(info, config, &mut requester, &mut reader).await?;
+    Ok((requester, reader))
+}
+
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => pending().await,
+    }
+}
+
+/// Spawns a connection task and returns the channel to send requests.
+pub fn spawn_db_connection<I: IntoConnectionInfo>(
+    info: I,
+    config: ConfigHandle,
+    latency: Arc<Histogram>,
+    cancel: CancellationToken,
+) -> Result<mpsc::UnboundedSender<Request>> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    spawn(DbConnection::new(info, config, rx, latency)?, cancel);
+    Ok(tx)
+}
+
+/// Spawns a dedicated connection task, which is never reconnected or
+/// pinged, and returns the channel to send requests.
+pub fn spawn_dedicated_connection<I: IntoConnectionInfo>(
+    info: I,
+    config: ConfigHandle,
+    latency: Arc<Histogram>,
+) -> Result<mpsc::UnboundedSender<Request>> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let connection = DbConnection {
+        dedicated: true,
+        ..DbConnection::new(info, config, rx, latency)?
+    };
+    spawn(connection, CancellationToken::new());
+    Ok(tx)
+}
+
+fn spawn(connection: DbConnection, cancel: CancellationToken) {
+    tokio::spawn(async move {
+        if let Err(e) = connection.run(cancel).await {
+            warn!("backend connection exits with error: {}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    use redis::{RedisRequestReader, RedisResp, RedisResponder};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_dedicated_connection() {
+        // answers OK to every command, and drops the connection on KILL
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let log = Arc::new(Mutex::new(vec![]));
+        let (server_accepted, server_log) = (accepted.clone(), log.clone());
+        tokio::spawn(async move {
+            while let Ok((conn, _)) = listener.accept().await {
+                server_accepted.fetch_add(1, Ordering::SeqCst);
+                let log = server_log.clone();
+                tokio::spawn(async move {
+                    let (reader, writer) = conn.into_split();
+                    let mut reader = RedisRequestReader::new(reader);
+                    let mut responder = RedisResponder::new(writer);
+                    while let Ok(cmd) = reader.read_request().await {
+                        log.lock().unwrap().push(cmd.name());
+                        if cmd.name() == "KILL" {
+                            break;
+                        }
+                        let _ = responder.send_response(&RedisResp::ok()).await;
+                    }
+                });
+            }
+        });
+
+        let mut config = Config::default();
+        config.backend.ping_period = Some(Duration::from_millis(10));
+        let sender =
+            spawn_dedicated_connection(addr, ConfigHandle::new(config), Arc::default()).unwrap();
+        let call = |name: &'static str| {
+            let (request, pending) = Request::new(RedisCmd::new([name]), 1, 0);
+            sender.send(request).unwrap();
+            pending.wait()
+        };
+        assert!(call("WATCH").await.is_ok());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(*log.lock().unwrap(), ["WATCH"]);
+
+        assert!(call("KILL").await.is_err());
+        let e = call("EXEC").await.err().unwrap();
+        assert!(e.to_string().contains("connection is lost"));
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
     }
 }
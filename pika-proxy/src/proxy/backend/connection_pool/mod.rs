@@ -1,31 +1,121 @@
-use dashmap::DashMap;
-use tokio::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use tokio::sync::mpsc::UnboundedSender;
 use tokio_util::sync::CancellationToken;
 
-use crate::error::Result;
-use crate::models::IntoConnectionInfo;
-use crate::proxy::backend::connection_pool::db_connection::DbConnection;
+use crate::error::{Error, Result};
+use crate::models::{ConnectionInfo, Request};
+use crate::proxy::config::ConfigHandle;
+use crate::utils::histogram::Histogram;
+
+pub use blocking_connection::{BlockingConnection, BlockingPool};
+pub use db_connection::{open_connection, spawn_db_connection, spawn_dedicated_connection};
 
+mod blocking_connection;
 mod db_connection;
 
+/// ConnectionPool holds the shared connections to one backend server,
+/// `parallel` connections for each database.
 pub struct ConnectionPool {
-    pool: DashMap<String, DbConnection>,
+    addr: String,
+    refcnt: usize,
+    cancel: CancellationToken,
+    pool: Vec<Vec<UnboundedSender<Request>>>,
 }
 
 impl ConnectionPool {
-    pub fn add_remote<I: IntoConnectionInfo>(&mut self, addr: I) -> Result<()> {
-        let (tx, mut rx) = channel(1024);
-        let pool_cancel_token = CancellationToken::new();
-        tokio::spawn(async move {
-            // before impl a error channel to process error, use unwrap() here
-            let client = DbConnection::new(addr, rx).unwrap();
-            let result = client.run(pool_cancel_token).await;
-            match result {
-                Ok(_) => {}
-                Err(_) => {}
-            }
-        });
-        //self.pool.insert(addr.get_id(), tx.clone());
-        Ok(())
+    pub fn new(
+        info: ConnectionInfo,
+        config: ConfigHandle,
+        latency: Arc<Histogram>,
+        parallel: u32,
+    ) -> Result<Self> {
+        let cancel = CancellationToken::new();
+        let databases = config.load().backend.number_databases.max(1);
+        let pool = (0..databases)
+            .map(|database| {
+                (0..parallel.max(1))
+                    .map(|_| {
+                        let info = ConnectionInfo {
+                            database,
+                            ..info.clone()
+                        };
+                        spawn_db_connection(
+                            info,
+                            config.clone(),
+                            latency.clone(),
+                            cancel.child_token(),
+                        )
+                    })
+                    .collect::<Result<Vec<_>>>()
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            addr: info.addr,
+            refcnt: 0,
+            cancel,
+            pool,
+        })
+    }
+
+    pub fn forward(&self, request: Request) -> Result<()> {
+        let Some(connections) = self.pool.get(request.database() as usize) else {
+            return Err(Error::proxy(anyhow!(
+                "database {} is out of range",
+                request.database()
+            )));
+        };
+        // requests of the same slot share a connection to keep their order
+        let connection = &connections[request.slot() as usize % connections.len()];
+        connection
+            .send(request)
+            .map_err(|_| Error::proxy(anyhow!("backend {} is closed", self.addr)))
+    }
+
+    pub fn retain(&mut self) {
+        self.refcnt += 1;
+    }
+
+    /// Returns true if no one references the pool any more.
+    pub fn release(&mut self) -> bool {
+        self.refcnt = self.refcnt.saturating_sub(1);
+        self.refcnt == 0
+    }
+}
+
+impl Drop for ConnectionPool {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+/// DedicatedConnection is a connection used by a single session, such as a
+/// transaction, it is closed after the in-flight requests are responded
+/// once dropped. It's never reconnected, requests fail once it's broken.
+pub struct DedicatedConnection {
+    addr: String,
+    sender: UnboundedSender<Request>,
+}
+
+impl DedicatedConnection {
+    pub fn new(
+        info: ConnectionInfo,
+        config: ConfigHandle,
+        latency: Arc<Histogram>,
+    ) -> Result<Self> {
+        let addr = info.addr.clone();
+        let sender = spawn_dedicated_connection(info, config, latency)?;
+        Ok(Self { addr, sender })
+    }
+
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    pub fn forward(&self, request: Request) -> Result<()> {
+        self.sender
+            .send(request)
+            .map_err(|_| Error::proxy(anyhow!("backend {} is closed", self.addr)))
     }
 }
@@ -1,23 +1,117 @@
 use std::sync::Arc;
 
+use anyhow::anyhow;
 use dashmap::DashMap;
+use redis::{RedisRequester, RedisResponseReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 
-use connection_pool::ConnectionPool;
+use connection_pool::{open_connection, BlockingPool, ConnectionPool};
+pub use connection_pool::{BlockingConnection, DedicatedConnection};
 
-use crate::proxy::config::Config;
+use crate::error::{Error, Result};
+use crate::models::{ConnectionInfo, Request};
+use crate::proxy::config::ConfigHandle;
+use crate::proxy::server::proxy_metrics::ProxyMetrics;
 
 mod connection_pool;
 
 pub struct Backend {
-    config: Arc<Config>,
+    config: ConfigHandle,
     db_connection_pool: DashMap<String, ConnectionPool>,
+    blocking_pool: BlockingPool,
+    metrics: Arc<ProxyMetrics>,
 }
 
 impl Backend {
-    pub fn new(config: Arc<Config>) -> Self {
+    pub fn new(config: ConfigHandle) -> Self {
+        Self::with_metrics(config, Arc::default())
+    }
+
+    /// Round trips of the shared and dedicated connections are recorded in
+    /// `metrics`.
+    pub fn with_metrics(config: ConfigHandle, metrics: Arc<ProxyMetrics>) -> Self {
         Self {
+            blocking_pool: BlockingPool::new(config.clone()),
             config,
             db_connection_pool: DashMap::new(),
+            metrics,
+        }
+    }
+
+    fn connection_info(&self, addr: &str, database: u32) -> ConnectionInfo {
+        ConnectionInfo {
+            addr: addr.to_string(),
+            auth: self.config.load().proxy.product_auth.clone(),
+            database,
+        }
+    }
+
+    /// Retains the shared connections to `addr`, they are created by the
+    /// first retain and closed by the last release.
+    pub fn retain(&self, addr: &str, parallel: u32) -> Result<()> {
+        self.db_connection_pool
+            .entry(addr.to_string())
+            .or_try_insert_with(|| {
+                ConnectionPool::new(
+                    self.connection_info(addr, 0),
+                    self.config.clone(),
+                    self.metrics.backend_latency(addr),
+                    parallel,
+                )
+            })?
+            .retain();
+        Ok(())
+    }
+
+    pub fn release(&self, addr: &str) {
+        let removed = self
+            .db_connection_pool
+            .remove_if_mut(addr, |_, pool| pool.release());
+        if removed.is_some() {
+            self.blocking_pool.clear(addr);
         }
     }
+
+    pub fn contains(&self, addr: &str) -> bool {
+        self.db_connection_pool.contains_key(addr)
+    }
+
+    pub fn forward(&self, addr: &str, request: Request) -> Result<()> {
+        match self.db_connection_pool.get(addr) {
+            Some(pool) => pool.forward(request),
+            None => Err(Error::proxy(anyhow!("backend {} is not found", addr))),
+        }
+    }
+
+    /// Opens a connection to `addr` which is not shared with other sessions.
+    pub fn dedicated_connection(&self, addr: &str, database: u32) -> Result<DedicatedConnection> {
+        DedicatedConnection::new(
+            self.connection_info(addr, database),
+            self.config.clone(),
+            self.metrics.backend_latency(addr),
+        )
+    }
+
+    /// Takes a connection for a blocking command, it should be given back
+    /// by `put_blocking` once the command is completed.
+    pub async fn take_blocking(&self, addr: &str, database: u32) -> Result<BlockingConnection> {
+        self.blocking_pool
+            .take(self.connection_info(addr, database))
+            .await
+    }
+
+    pub fn put_blocking(&self, conn: BlockingConnection) {
+        self.blocking_pool.put(conn);
+    }
+
+    /// Opens a connection to `addr` for a subscribed session.
+    pub async fn subscription_connection(
+        &self,
+        addr: &str,
+    ) -> Result<(
+        RedisRequester<OwnedWriteHalf>,
+        RedisResponseReader<OwnedReadHalf>,
+    )> {
+        open_connection(&self.connection_info(addr, 0), &self.config.load_full()).await
+    }
 }
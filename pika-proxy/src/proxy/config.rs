@@ -1,6 +1,10 @@
-use serde::{de, Deserialize, Deserializer, Serialize};
-use std::{path::Path, time::Duration};
+use arc_swap::{ArcSwap, Guard};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::{net::SocketAddr, path::Path, path::PathBuf, time::Duration};
 use thiserror::Error;
+use tokio::sync::watch;
 
 use crate::error::Error;
 
@@ -10,12 +14,48 @@ const GB: u64 = 1024 * MB;
 const TB: u64 = 1024 * GB;
 const PB: u64 = 1024 * TB;
 
+/// prefix of the env vars which override the config
+const ENV_PREFIX: &str = "PIKA_PROXY_";
+
+/// keys which are only applied by a restart, a section stands for all keys
+/// of it
+const RESTART_KEYS: &[&str] = &[
+    "proxy.protocol_type",
+    "proxy.addr",
+    "proxy.admin_addr",
+    "proxy.host_proxy",
+    "proxy.host_admin",
+    "proxy.product_name",
+    "proxy.product_auth",
+    "proxy.data_center",
+    // accepted for the configs of codis, there is no heap to reserve
+    "proxy.heap_place_holder",
+    // replicas of the slots filled are kept or dropped by it
+    "backend.primary_only",
+    "backend.primary_parallel",
+    "backend.replica_parallel",
+    "backend.number_databases",
+    // read when a backend connection is established
+    "backend.max_pipeline",
+    "backend.send_bufsize",
+    "backend.recv_bufsize",
+    "backend.keepalive_period",
+    "registry",
+    "sentinel",
+];
+
 #[derive(Error, Debug)]
 pub enum ConfigError {
-    #[error("config file format error")]
+    #[error("config file format error: {0}")]
     ParseToml(#[from] toml::de::Error),
     #[error("can not read config file")]
     ReadFile(#[from] std::io::Error),
+    #[error("invalid config:\n  {}", .0.join("\n  "))]
+    Invalid(Vec<String>),
+    #[error("restart is required to change {}", .0.join(", "))]
+    RestartRequired(Vec<String>),
+    #[error("config is not loaded from a file")]
+    NotReloadable,
 }
 
 type Result<T> = std::result::Result<T, ConfigError>;
@@ -29,25 +69,110 @@ impl From<ConfigError> for Error {
 #[derive(Serialize, Deserialize, Debug, Default)]
 #[serde(default)]
 pub struct SessionConfig {
-    #[serde(deserialize_with = "deserialize_string_to_size")]
+    #[serde(
+        deserialize_with = "deserialize_string_to_size",
+        serialize_with = "serialize_size_to_string"
+    )]
     pub recv_bufsize: u64,
-    #[serde(deserialize_with = "deserialize_string_to_duration")]
-    pub recv_timeout: Duration,
-    #[serde(deserialize_with = "deserialize_string_to_size")]
+    #[serde(
+        deserialize_with = "deserialize_string_to_optional_duration",
+        serialize_with = "serialize_optional_duration_to_string"
+    )]
+    pub recv_timeout: Option<Duration>,
+    #[serde(
+        deserialize_with = "deserialize_string_to_size",
+        serialize_with = "serialize_size_to_string"
+    )]
     pub send_bufsize: u64,
     pub auth: String,
-    #[serde(deserialize_with = "deserialize_string_to_duration")]
+    #[serde(
+        deserialize_with = "deserialize_string_to_duration",
+        serialize_with = "serialize_duration_to_string"
+    )]
     pub send_timeout: Duration,
     pub max_pipeline: u32,
-    #[serde(deserialize_with = "deserialize_string_to_duration")]
-    pub keepalive_timeout: Duration,
+    #[serde(
+        alias = "keepalive_timeout",
+        deserialize_with = "deserialize_string_to_optional_duration",
+        serialize_with = "serialize_optional_duration_to_string"
+    )]
+    pub keepalive_period: Option<Duration>,
     pub break_on_failure: bool,
+    pub output_buffer_limit_normal: OutputBufferLimit,
+    pub output_buffer_limit_pubsub: OutputBufferLimit,
+}
+
+impl SessionConfig {
+    /// The output buffer limit of a session, by whether it's subscribed.
+    pub fn output_buffer_limit(&self, subscribed: bool) -> OutputBufferLimit {
+        if subscribed {
+            self.output_buffer_limit_pubsub
+        } else {
+            self.output_buffer_limit_normal
+        }
+    }
+}
+
+/// OutputBufferLimit is like `client-output-buffer-limit` of redis, written
+/// as `<hard> <soft> <soft period>` such as `32mb 8mb 60s`. A client is
+/// disconnected once the replies not written to it exceed the hard limit,
+/// or stay over the soft limit for the soft period, 0 disables a limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OutputBufferLimit {
+    pub hard: Option<u64>,
+    pub soft: Option<u64>,
+    pub soft_period: Duration,
+}
+
+impl FromStr for OutputBufferLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let [hard, soft, soft_period] = s.split_whitespace().collect::<Vec<_>>()[..] else {
+            return Err(format!(
+                "`{}` is not in the form of `<hard> <soft> <soft period>`",
+                s
+            ));
+        };
+        let enabled = |size: u64| (size != 0).then_some(size);
+        // bare seconds are accepted as redis does
+        let soft_period = match soft_period.parse::<u64>() {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(_) => parse_duration(soft_period)?,
+        };
+        Ok(Self {
+            hard: enabled(parse_size(hard)?),
+            soft: enabled(parse_size(soft)?),
+            soft_period,
+        })
+    }
+}
+
+impl Serialize for OutputBufferLimit {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let text = format!(
+            "{} {} {}",
+            format_size(self.hard.unwrap_or_default()),
+            format_size(self.soft.unwrap_or_default()),
+            format_duration(self.soft_period)
+        );
+        serializer.serialize_str(&text)
+    }
+}
+
+impl<'de> Deserialize<'de> for OutputBufferLimit {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
 }
 
 /// configuration for proxy
-#[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd)]
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, PartialOrd)]
 pub enum ProxyProtocol {
     #[serde(rename = "tcp")]
+    #[default]
     Tcp,
     #[serde(rename = "tcp4")]
     Tcp4,
@@ -59,9 +184,15 @@ pub enum ProxyProtocol {
     UnixPacket,
 }
 
-impl Default for ProxyProtocol {
-    fn default() -> Self {
-        ProxyProtocol::Tcp
+impl ProxyProtocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProxyProtocol::Tcp => "tcp",
+            ProxyProtocol::Tcp4 => "tcp4",
+            ProxyProtocol::Tcp6 => "tcp6",
+            ProxyProtocol::Unix => "unix",
+            ProxyProtocol::UnixPacket => "unix_packet",
+        }
     }
 }
 
@@ -69,6 +200,8 @@ impl Default for ProxyProtocol {
 #[derive(Serialize, Deserialize, Debug, Default)]
 #[serde(default)]
 pub struct ProxyConfig {
+    // the codis names are accepted as well
+    #[serde(alias = "proto_type")]
     pub protocol_type: ProxyProtocol,
     pub addr: String,
     pub admin_addr: String,
@@ -76,54 +209,155 @@ pub struct ProxyConfig {
     pub host_admin: String,
     pub product_name: String,
     pub product_auth: String,
+    #[serde(alias = "datacenter")]
     pub data_center: String,
     pub max_clients: u32,
-    #[serde(deserialize_with = "deserialize_string_to_size")]
-    pub max_offheap_bytes: u64,
-    #[serde(deserialize_with = "deserialize_string_to_size")]
+    #[serde(
+        alias = "max_offheap_size",
+        deserialize_with = "deserialize_string_to_optional_size",
+        serialize_with = "serialize_optional_size_to_string"
+    )]
+    pub max_offheap_bytes: Option<u64>,
+    #[serde(
+        alias = "heap_placeholder",
+        deserialize_with = "deserialize_string_to_size",
+        serialize_with = "serialize_size_to_string"
+    )]
     pub heap_place_holder: u64,
 }
 
 /// configuration for metrics
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
 #[serde(default)]
 pub struct MetricsConfig {
     pub report_server: String,
-    #[serde(deserialize_with = "deserialize_string_to_duration")]
+    #[serde(
+        deserialize_with = "deserialize_string_to_duration",
+        serialize_with = "serialize_duration_to_string"
+    )]
     pub report_period: Duration,
     pub report_influxdb_server: String,
-    #[serde(deserialize_with = "deserialize_string_to_duration")]
+    #[serde(
+        deserialize_with = "deserialize_string_to_duration",
+        serialize_with = "serialize_duration_to_string"
+    )]
     pub report_influxdb_period: Duration,
     pub report_influxdb_username: String,
     pub report_influxdb_password: String,
     pub report_influxdb_database: String,
-    pub report_stats_server: String,
-    #[serde(deserialize_with = "deserialize_string_to_duration")]
-    pub report_stats_period: Duration,
-    pub report_stats_prefix: String,
+    // the names without `d` were used by mistake, they are still accepted
+    #[serde(alias = "report_stats_server")]
+    pub report_statsd_server: String,
+    #[serde(
+        alias = "report_stats_period",
+        deserialize_with = "deserialize_string_to_duration",
+        serialize_with = "serialize_duration_to_string"
+    )]
+    pub report_statsd_period: Duration,
+    #[serde(alias = "report_stats_prefix")]
+    pub report_statsd_prefix: String,
+    #[serde(
+        deserialize_with = "deserialize_string_to_optional_duration",
+        serialize_with = "serialize_optional_duration_to_string"
+    )]
+    pub latency_window: Option<Duration>,
 }
 
 /// configuration for backend
 #[derive(Serialize, Deserialize, Debug, Default)]
 #[serde(default)]
 pub struct BackendConfig {
-    #[serde(deserialize_with = "deserialize_string_to_duration")]
-    pub ping_period: Duration,
-    #[serde(deserialize_with = "deserialize_string_to_size")]
+    #[serde(
+        deserialize_with = "deserialize_string_to_optional_duration",
+        serialize_with = "serialize_optional_duration_to_string"
+    )]
+    pub ping_period: Option<Duration>,
+    #[serde(
+        deserialize_with = "deserialize_string_to_size",
+        serialize_with = "serialize_size_to_string"
+    )]
     pub recv_bufsize: u64,
-    #[serde(deserialize_with = "deserialize_string_to_duration")]
+    #[serde(
+        deserialize_with = "deserialize_string_to_duration",
+        serialize_with = "serialize_duration_to_string"
+    )]
     pub recv_timeout: Duration,
-    #[serde(deserialize_with = "deserialize_string_to_size")]
+    #[serde(
+        deserialize_with = "deserialize_string_to_size",
+        serialize_with = "serialize_size_to_string"
+    )]
     pub send_bufsize: u64,
-    #[serde(deserialize_with = "deserialize_string_to_duration")]
+    #[serde(
+        deserialize_with = "deserialize_string_to_duration",
+        serialize_with = "serialize_duration_to_string"
+    )]
     pub send_timeout: Duration,
     pub max_pipeline: u32,
     pub primary_only: bool,
     pub primary_parallel: u32,
     pub replica_parallel: u32,
-    #[serde(deserialize_with = "deserialize_string_to_duration")]
-    pub keepalive_period: Duration,
+    #[serde(
+        deserialize_with = "deserialize_string_to_optional_duration",
+        serialize_with = "serialize_optional_duration_to_string"
+    )]
+    pub keepalive_period: Option<Duration>,
     pub number_databases: u32,
+    pub blocking_pool_size: u32,
+    pub max_blocking_conns: u32,
+    #[serde(
+        deserialize_with = "deserialize_string_to_optional_duration",
+        serialize_with = "serialize_optional_duration_to_string"
+    )]
+    pub blocking_timeout: Option<Duration>,
+}
+
+/// kind of registry which holds the topology
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, PartialOrd)]
+pub enum RegistryType {
+    #[serde(rename = "etcd")]
+    Etcd,
+    #[serde(rename = "redis")]
+    Redis,
+    #[serde(rename = "file")]
+    #[default]
+    File,
+}
+
+/// configuration for registry
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct RegistryConfig {
+    pub registry_type: RegistryType,
+    pub addr: String,
+    pub auth: String,
+    #[serde(
+        deserialize_with = "deserialize_string_to_duration",
+        serialize_with = "serialize_duration_to_string"
+    )]
+    pub timeout: Duration,
+    #[serde(
+        deserialize_with = "deserialize_string_to_duration",
+        serialize_with = "serialize_duration_to_string"
+    )]
+    pub ttl: Duration,
+    #[serde(
+        deserialize_with = "deserialize_string_to_optional_duration",
+        serialize_with = "serialize_optional_duration_to_string"
+    )]
+    pub reconcile_period: Option<Duration>,
+}
+
+/// configuration for sentinel
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct SentinelConfig {
+    pub addrs: Vec<String>,
+    pub auth: String,
+    #[serde(
+        deserialize_with = "deserialize_string_to_duration",
+        serialize_with = "serialize_duration_to_string"
+    )]
+    pub timeout: Duration,
 }
 
 /// all config
@@ -134,63 +368,559 @@ pub struct Config {
     pub proxy: ProxyConfig,
     pub session: SessionConfig,
     pub metrics: MetricsConfig,
+    pub registry: RegistryConfig,
+    pub sentinel: SentinelConfig,
+}
+
+/// Formats a size in the largest unit it's a multiple of, so it's read back
+/// the same.
+fn format_size(size: u64) -> String {
+    let units = [(PB, "pb"), (TB, "tb"), (GB, "gb"), (MB, "mb"), (KB, "kb")];
+    match units.iter().find(|(unit, _)| size.is_multiple_of(*unit)) {
+        _ if size == 0 => "0".to_string(),
+        Some((unit, name)) => format!("{}{}", size / unit, name),
+        None => format!("{}b", size),
+    }
+}
+
+fn serialize_size_to_string<S: Serializer>(
+    size: &u64,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format_size(*size))
+}
+
+fn serialize_optional_size_to_string<S: Serializer>(
+    size: &Option<u64>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serialize_size_to_string(&size.unwrap_or_default(), serializer)
+}
+
+/// Formats a duration in the largest unit it's a multiple of, so it's read
+/// back the same.
+fn format_duration(duration: Duration) -> String {
+    let nanos = duration.as_nanos();
+    let units = [
+        (86_400_000_000_000, "d"),
+        (3_600_000_000_000, "h"),
+        (60_000_000_000, "m"),
+        (1_000_000_000, "s"),
+        (1_000_000, "ms"),
+        (1_000, "us"),
+    ];
+    match units.iter().find(|(unit, _)| nanos.is_multiple_of(*unit)) {
+        _ if nanos == 0 => "0".to_string(),
+        Some((unit, name)) => format!("{}{}", nanos / unit, name),
+        None => format!("{}ns", nanos),
+    }
+}
+
+fn serialize_duration_to_string<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format_duration(*duration))
+}
+
+fn serialize_optional_duration_to_string<S: Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serialize_duration_to_string(&duration.unwrap_or_default(), serializer)
+}
+
+/// A size or a duration is written as a string such as `128kb` or `1.5s`,
+/// or as a bare number.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumberOrString {
+    Number(u64),
+    String(String),
 }
 
 fn deserialize_string_to_size<'de, D>(deserializer: D) -> std::result::Result<u64, D::Error>
 where
     D: Deserializer<'de>,
 {
-    let s = String::deserialize(deserializer)?;
-    let (size_num, unit) = parse_string_to_num_and_unit(&s)?;
-    match unit {
-        "kb" => Ok(size_num * KB),
-        "mb" => Ok(size_num * MB),
-        "gb" => Ok(size_num * GB),
-        "tb" => Ok(size_num * TB),
-        "pb" => Ok(size_num * PB),
-        _ => Err(de::Error::missing_field("size")),
+    match NumberOrString::deserialize(deserializer)? {
+        NumberOrString::Number(bytes) => Ok(bytes),
+        NumberOrString::String(s) => parse_size(&s).map_err(de::Error::custom),
     }
 }
 
+/// A size of 0 means disabled, it's `None`.
+fn deserialize_string_to_optional_size<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let size = deserialize_string_to_size(deserializer)?;
+    Ok((size != 0).then_some(size))
+}
+
 fn deserialize_string_to_duration<'de, D>(
     deserializer: D,
 ) -> std::result::Result<Duration, D::Error>
 where
     D: Deserializer<'de>,
 {
-    let s = String::deserialize(deserializer)?;
-    let (time_num, unit) = parse_string_to_num_and_unit(&s)?;
-    match unit {
-        "m" => Ok(Duration::from_secs(time_num * 60)),
-        "s" => Ok(Duration::from_secs(time_num)),
-        "ms" => Ok(Duration::from_millis(time_num)),
-        "us" => Ok(Duration::from_micros(time_num)),
-        "ns" => Ok(Duration::from_nanos(time_num)),
-        _ => Err(de::Error::missing_field("duration")),
+    match NumberOrString::deserialize(deserializer)? {
+        NumberOrString::Number(0) => Ok(Duration::ZERO),
+        NumberOrString::Number(n) => {
+            Err(de::Error::custom(format!("missing unit of duration {}", n)))
+        }
+        NumberOrString::String(s) => parse_duration(&s).map_err(de::Error::custom),
     }
 }
 
-fn parse_string_to_num_and_unit<E: de::Error>(s: &str) -> std::result::Result<(u64, &str), E> {
-    if !s.starts_with(char::is_numeric) {
-        return Err(E::custom("missing number in configuration"));
+/// A duration of 0 means disabled, it's `None`.
+fn deserialize_string_to_optional_duration<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let duration = deserialize_string_to_duration(deserializer)?;
+    Ok((!duration.is_zero()).then_some(duration))
+}
+
+/// Splits `s` into the number and the lowercased unit, such as `1.5` and
+/// `mb` of `1.5MB`.
+fn split_number_and_unit(s: &str) -> std::result::Result<(f64, String), String> {
+    let s = s.trim();
+    let pos = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(pos);
+    let number = number
+        .parse::<f64>()
+        .map_err(|_| format!("missing number in `{}`", s))?;
+    Ok((number, unit.trim().to_ascii_lowercase()))
+}
+
+/// Parses a size such as `128kb`, `1.5GB` or `4096`, a bare number is in
+/// bytes.
+fn parse_size(s: &str) -> std::result::Result<u64, String> {
+    let (number, unit) = split_number_and_unit(s)?;
+    let unit = match unit.as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => KB,
+        "m" | "mb" => MB,
+        "g" | "gb" => GB,
+        "t" | "tb" => TB,
+        "p" | "pb" => PB,
+        _ => return Err(format!("unknown unit of size `{}`", s)),
+    };
+    let bytes = (number * unit as f64).round();
+    if bytes >= u64::MAX as f64 {
+        return Err(format!("size `{}` is too large", s));
     }
+    Ok(bytes as u64)
+}
 
-    let (h, u) = s.split_at(
-        s.chars()
-            .position(|x| x.is_alphabetic())
-            .ok_or_else(|| E::custom("expecting unit in configuration"))?,
-    );
-    Ok((
-        h.parse()
-            .map_err(|e| E::custom(format!("parsing number error: {}", e)))?,
-        u,
-    ))
+/// Parses a duration such as `30s`, `1.5h` or `0`, only 0 may be without a
+/// unit.
+fn parse_duration(s: &str) -> std::result::Result<Duration, String> {
+    let (number, unit) = split_number_and_unit(s)?;
+    let secs = match unit.as_str() {
+        "" if number == 0.0 => 0.0,
+        "" => return Err(format!("missing unit of duration `{}`", s)),
+        "ns" => number / 1e9,
+        "us" => number / 1e6,
+        "ms" => number / 1e3,
+        "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        "d" => number * 86400.0,
+        _ => return Err(format!("unknown unit of duration `{}`", s)),
+    };
+    // rounded to nanoseconds, so `0.1s` is not 99999999ns
+    let nanos = (secs * 1e9).round();
+    if nanos >= u64::MAX as f64 {
+        return Err(format!("duration `{}` is too long", s));
+    }
+    Ok(Duration::from_nanos(nanos as u64))
 }
 
 impl Config {
     pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_path_with(path, &[])
+    }
+
+    /// Loads the config file with `overrides` layered over it in order, so
+    /// the later ones win.
+    pub fn from_path_with<P: AsRef<Path>>(path: P, overrides: &[Override]) -> Result<Self> {
         let content = std::fs::read_to_string(path)?;
-        Ok(toml::from_str(&content)?)
+        if overrides.is_empty() {
+            // parsed from the text, so errors are told with the line
+            return Self::from_toml(&content);
+        }
+        let mut table: toml::Table = toml::from_str(&content)?;
+        let defaults = toml::Table::try_from(Config::default()).unwrap_or_default();
+        for o in overrides {
+            o.apply(&mut table, &defaults)?;
+        }
+        Self::parse(toml::Value::Table(table))
+    }
+
+    /// Parses and validates a config, unknown keys and invalid values are
+    /// reported all together.
+    pub fn from_toml(content: &str) -> Result<Self> {
+        Self::parse(toml::Deserializer::new(content))
+    }
+
+    fn parse<'de, D>(deserializer: D) -> Result<Self>
+    where
+        D: Deserializer<'de, Error = toml::de::Error>,
+    {
+        let mut problems = vec![];
+        let config: Config = serde_ignored::deserialize(deserializer, |path| {
+            problems.push(format!("unknown key `{}`", path))
+        })?;
+        problems.extend(config.validate());
+        if !problems.is_empty() {
+            return Err(ConfigError::Invalid(problems));
+        }
+        Ok(config)
+    }
+
+    /// Keys whose values are different in `other`.
+    pub fn diff(&self, other: &Config) -> Vec<String> {
+        let (Ok(old), Ok(new)) = (serde_json::to_value(self), serde_json::to_value(other)) else {
+            return vec![];
+        };
+        let (Some(old), Some(new)) = (old.as_object(), new.as_object()) else {
+            return vec![];
+        };
+        let mut changed = vec![];
+        for (section, values) in old {
+            let (Some(values), Some(others)) = (
+                values.as_object(),
+                new.get(section).and_then(|v| v.as_object()),
+            ) else {
+                continue;
+            };
+            for (key, value) in values {
+                if others.get(key) != Some(value) {
+                    changed.push(format!("{}.{}", section, key));
+                }
+            }
+        }
+        changed
+    }
+
+    /// Problems of the values, empty if the config is valid.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        let mut check = |key: &str, problem: Option<String>| {
+            if let Some(problem) = problem {
+                problems.push(format!("{}: {}", key, problem));
+            }
+        };
+
+        let proxy = &self.proxy;
+        match proxy.protocol_type {
+            ProxyProtocol::Unix | ProxyProtocol::UnixPacket => {
+                check("proxy.addr", required(&proxy.addr))
+            }
+            _ => check("proxy.addr", check_host_port(&proxy.addr)),
+        }
+        // the admin server binds it without resolving
+        if !proxy.admin_addr.is_empty() && proxy.admin_addr.parse::<SocketAddr>().is_err() {
+            check(
+                "proxy.admin_addr",
+                Some(format!("`{}` is not an ip:port", proxy.admin_addr)),
+            );
+        }
+        check(
+            "proxy.host_proxy",
+            optional(&proxy.host_proxy, check_host_port),
+        );
+        check(
+            "proxy.host_admin",
+            optional(&proxy.host_admin, check_host_port),
+        );
+
+        check("session.max_pipeline", positive(self.session.max_pipeline));
+        check("backend.max_pipeline", positive(self.backend.max_pipeline));
+
+        let metrics = &self.metrics;
+        check(
+            "metrics.report_server",
+            optional(&metrics.report_server, check_url),
+        );
+        check(
+            "metrics.report_influxdb_server",
+            optional(&metrics.report_influxdb_server, check_url),
+        );
+        check(
+            "metrics.report_statsd_server",
+            optional(&metrics.report_statsd_server, check_host_port),
+        );
+
+        let registry = &self.registry;
+        let addr = match registry.registry_type {
+            RegistryType::Etcd => registry
+                .addr
+                .split(',')
+                .map(|endpoint| {
+                    let endpoint = endpoint.trim();
+                    let endpoint = endpoint.split_once("://").map_or(endpoint, |(_, e)| e);
+                    check_host_port(endpoint)
+                })
+                .find(Option::is_some)
+                .flatten(),
+            RegistryType::Redis => check_host_port(&registry.addr),
+            RegistryType::File => required(&registry.addr),
+        };
+        check("registry.addr", addr);
+
+        for (i, addr) in self.sentinel.addrs.iter().enumerate() {
+            check(&format!("sentinel.addrs.{}", i), check_host_port(addr));
+        }
+        problems
+    }
+}
+
+fn requires_restart(key: &str) -> bool {
+    RESTART_KEYS.iter().any(|restart| {
+        key.strip_prefix(restart)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+    })
+}
+
+/// Override sets a key of the config, such as `backend.max_pipeline=40000`.
+/// The value is taken as a toml value, or a string if it's not one or the
+/// key is a string, so `40000` is a number while `30s` is a string parsed
+/// as a duration, and `session.auth=123456` sets the password `123456`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Override {
+    key: String,
+    raw: String,
+    value: toml::Value,
+}
+
+impl Override {
+    pub fn new(key: &str, value: &str) -> Self {
+        let raw = value.to_string();
+        let value = toml::from_str::<toml::Table>(&format!("value = {}", value))
+            .ok()
+            .and_then(|mut table| table.remove("value"))
+            .unwrap_or_else(|| toml::Value::String(raw.clone()));
+        Self {
+            key: key.to_string(),
+            raw,
+            value,
+        }
+    }
+
+    /// Overrides by the env vars such as `PIKA_PROXY_PROXY__ADDR`, a double
+    /// underscore separates the section and the key.
+    pub fn from_env() -> Vec<Self> {
+        Self::from_vars(std::env::vars())
+    }
+
+    fn from_vars<I: IntoIterator<Item = (String, String)>>(vars: I) -> Vec<Self> {
+        let mut overrides = vars
+            .into_iter()
+            .filter_map(|(name, value)| {
+                let key = name.strip_prefix(ENV_PREFIX)?;
+                Some(Self::new(&key.to_lowercase().replace("__", "."), &value))
+            })
+            .collect::<Vec<_>>();
+        // the order of env vars is unspecified
+        overrides.sort_by(|a, b| a.key.cmp(&b.key));
+        overrides
+    }
+
+    /// Sets the key in `table`, `defaults` is the default config, which
+    /// tells the keys of strings.
+    fn apply(&self, table: &mut toml::Table, defaults: &toml::Table) -> Result<()> {
+        let invalid = || ConfigError::Invalid(vec![format!("{}: can not be overridden", self.key)]);
+        let value = if lookup(defaults, &self.key).is_some_and(toml::Value::is_str) {
+            toml::Value::String(self.raw.clone())
+        } else {
+            self.value.clone()
+        };
+        let mut keys = self.key.split('.').collect::<Vec<_>>();
+        let last = keys.pop().ok_or_else(invalid)?;
+        let mut table = table;
+        for key in keys {
+            table = table
+                .entry(key)
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+                .as_table_mut()
+                .ok_or_else(invalid)?;
+        }
+        table.insert(last.to_string(), value);
+        Ok(())
+    }
+}
+
+/// Returns the value of a dotted `key` in `table`.
+fn lookup<'a>(table: &'a toml::Table, key: &str) -> Option<&'a toml::Value> {
+    match key.split_once('.') {
+        Some((section, key)) => lookup(table.get(section)?.as_table()?, key),
+        None => table.get(key),
+    }
+}
+
+impl FromStr for Override {
+    type Err = String;
+
+    /// Parses `KEY=VALUE`.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => Ok(Self::new(key.trim(), value)),
+            _ => Err(format!("`{}` is not KEY=VALUE", s)),
+        }
+    }
+}
+
+type Loader = Box<dyn Fn() -> Result<Config> + Send + Sync>;
+
+/// ConfigHandle is the running config shared by the server, sessions and
+/// backends. A reload swaps the config in it, readers load the current one
+/// without locks, and the ones which have to restart to apply it subscribe
+/// the changes.
+#[derive(Clone)]
+pub struct ConfigHandle {
+    inner: Arc<HandleInner>,
+}
+
+struct HandleInner {
+    current: ArcSwap<Config>,
+    changed: watch::Sender<()>,
+    // how the config is loaded again, none if it's not from a file
+    loader: Option<Loader>,
+    // reloads are one at a time, so none of them is lost
+    reloading: Mutex<()>,
+}
+
+impl ConfigHandle {
+    pub fn new(config: Config) -> Self {
+        Self::with_loader(config, None)
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_path_with(path, vec![])
+    }
+
+    /// Loads the config file with `overrides`, they are layered again on
+    /// every reload.
+    pub fn from_path_with<P: AsRef<Path>>(path: P, overrides: Vec<Override>) -> Result<Self> {
+        let path = PathBuf::from(path.as_ref());
+        let config = Config::from_path_with(&path, &overrides)?;
+        let loader = Box::new(move || Config::from_path_with(&path, &overrides));
+        Ok(Self::with_loader(config, Some(loader)))
+    }
+
+    fn with_loader(config: Config, loader: Option<Loader>) -> Self {
+        Self {
+            inner: Arc::new(HandleInner {
+                current: ArcSwap::from_pointee(config),
+                changed: watch::channel(()).0,
+                loader,
+                reloading: Mutex::new(()),
+            }),
+        }
+    }
+
+    pub fn load(&self) -> Guard<Arc<Config>> {
+        self.inner.current.load()
+    }
+
+    pub fn load_full(&self) -> Arc<Config> {
+        self.inner.current.load_full()
+    }
+
+    /// Notified once the config is changed.
+    pub fn subscribe(&self) -> watch::Receiver<()> {
+        self.inner.changed.subscribe()
+    }
+
+    /// Loads the config again and applies it, see `update`.
+    pub fn reload(&self) -> Result<Vec<String>> {
+        let loader = self
+            .inner
+            .loader
+            .as_ref()
+            .ok_or(ConfigError::NotReloadable)?;
+        self.update(loader()?)
+    }
+
+    /// Applies `config` and returns the keys changed. It's refused if a key
+    /// which requires a restart is changed, nothing is applied then.
+    pub fn update(&self, config: Config) -> Result<Vec<String>> {
+        let _reloading = self.inner.reloading.lock().unwrap();
+        let changed = self.load().diff(&config);
+        let restart = changed
+            .iter()
+            .filter(|key| requires_restart(key))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !restart.is_empty() {
+            return Err(ConfigError::RestartRequired(restart));
+        }
+        if !changed.is_empty() {
+            self.inner.current.store(Arc::new(config));
+            self.inner.changed.send_replace(());
+        }
+        Ok(changed)
+    }
+}
+
+impl Default for ConfigHandle {
+    fn default() -> Self {
+        Self::new(Config::default())
+    }
+}
+
+fn required(value: &str) -> Option<String> {
+    value.is_empty().then(|| "is required".to_string())
+}
+
+/// Checks `value` unless it's empty.
+fn optional(value: &str, check: fn(&str) -> Option<String>) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        check(value)
+    }
+}
+
+fn positive(value: u32) -> Option<String> {
+    (value == 0).then(|| "must be greater than 0".to_string())
+}
+
+/// The host is a name or an ip, an ipv6 is in brackets.
+fn check_host_port(addr: &str) -> Option<String> {
+    if addr.is_empty() {
+        return Some("is required".to_string());
+    }
+    if addr.parse::<SocketAddr>().is_ok() {
+        return None;
+    }
+    let valid = addr.rsplit_once(':').is_some_and(|(host, port)| {
+        port.parse::<u16>().is_ok()
+            && !host.is_empty()
+            && !host.contains(|c: char| c == ':' || c == '/' || c.is_whitespace())
+    });
+    (!valid).then(|| format!("`{}` is not a host:port", addr))
+}
+
+/// An url of http, the scheme may be omitted.
+fn check_url(url: &str) -> Option<String> {
+    let url = if url.contains("://") {
+        url.to_string()
+    } else {
+        format!("http://{}", url)
+    };
+    match url.parse::<hyper::Uri>() {
+        Ok(uri) if uri.host().is_some() => None,
+        _ => Some(format!("`{}` is not an url", url)),
     }
 }
 
@@ -205,6 +935,139 @@ mod tests {
         let config = Config::from_path(config_path).unwrap();
         assert_eq!(config.proxy.addr, "127.0.0.1:19000");
         assert_eq!(config.backend.recv_bufsize, 128 * 1024);
+        assert_eq!(config.registry.registry_type, RegistryType::File);
+        assert_eq!(
+            config.registry.reconcile_period,
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(config.metrics.report_statsd_period, Duration::from_secs(1));
+
+        let old: Config =
+            toml::from_str("[metrics]\nreport_stats_server = \"localhost:8125\"").unwrap();
+        assert_eq!(old.metrics.report_statsd_server, "localhost:8125");
+    }
+
+    #[test]
+    fn test_validate() {
+        let content = r#"
+            [proxy]
+            addr = "127.0.0.1"
+            admin_addr = "localhost:11080"
+            session_auth = "secret"
+            [session]
+            max_pipeline = 0
+            [backend]
+            max_pipeline = 1024
+            max_pipelin = 1024
+            [metrics]
+            report_statsd_server = "localhost:8125"
+            [registry]
+            registry_type = "etcd"
+            addr = "http://127.0.0.1:2379,127.0.0.1"
+            [sentinel]
+            addrs = ["[::1]:26379", "::1:26379"]
+        "#;
+        let err = Config::from_toml(content).unwrap_err();
+        let ConfigError::Invalid(problems) = err else {
+            panic!("{}", err);
+        };
+        assert_eq!(
+            problems,
+            vec![
+                "unknown key `proxy.session_auth`",
+                "unknown key `backend.max_pipelin`",
+                "proxy.addr: `127.0.0.1` is not a host:port",
+                "proxy.admin_addr: `localhost:11080` is not an ip:port",
+                "session.max_pipeline: must be greater than 0",
+                "registry.addr: `127.0.0.1` is not a host:port",
+                "sentinel.addrs.1: `::1:26379` is not a host:port",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_config_handle() {
+        let handle = ConfigHandle::new(Config::default());
+        let changed = handle.subscribe();
+        assert!(matches!(handle.reload(), Err(ConfigError::NotReloadable)));
+
+        let config = toml::from_str("[session]\nmax_pipeline = 2\nrecv_timeout = \"5s\"").unwrap();
+        assert_eq!(
+            handle.update(config).unwrap(),
+            vec!["session.max_pipeline", "session.recv_timeout"]
+        );
+        assert!(changed.has_changed().unwrap());
+        assert_eq!(handle.load().session.max_pipeline, 2);
+
+        // backend connections are sized once they are established
+        let config = toml::from_str(
+            "[proxy]\naddr = \"127.0.0.1:19001\"\n[backend]\nmax_pipeline = 4\n[registry]\nttl = \"1s\"",
+        )
+        .unwrap();
+        let err = handle.update(config).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "restart is required to change backend.max_pipeline, proxy.addr, registry.ttl"
+        );
+        assert_eq!(handle.load().session.max_pipeline, 2);
+    }
+
+    #[test]
+    fn test_format_errors() {
+        // told with the line and the reason
+        let err = Config::from_toml("[backend]\nmax_pipeline = \"abc\"").unwrap_err();
+        assert!(err.to_string().contains("line 2"), "{}", err);
+        assert!(err.to_string().contains("expected u32"), "{}", err);
+    }
+
+    #[test]
+    fn test_overrides() {
+        let vars = [
+            ("PIKA_PROXY_PROXY__ADDR", "127.0.0.1:19001"),
+            ("PIKA_PROXY_BACKEND__MAX_PIPELINE", "40000"),
+            ("PIKA_PROXY_SESSION__RECV_TIMEOUT", "5s"),
+            ("PIKA_PROXY_SESSION__AUTH", "123456"),
+            ("PATH", "/bin"),
+        ];
+        let mut overrides =
+            Override::from_vars(vars.map(|(name, value)| (name.to_string(), value.to_string())));
+        assert_eq!(overrides.len(), 4);
+        overrides.push("proxy.product_name=2024".parse().unwrap());
+        overrides.push("backend.max_pipeline=20000".parse().unwrap());
+        overrides.push("sentinel.addrs=[\"127.0.0.1:26379\"]".parse().unwrap());
+        assert!("max_pipeline".parse::<Override>().is_err());
+
+        let mut config_path = project_root::get_project_root().unwrap();
+        config_path.push("config/proxy.toml");
+        let config = Config::from_path_with(&config_path, &overrides).unwrap();
+        assert_eq!(config.proxy.addr, "127.0.0.1:19001");
+        assert_eq!(config.backend.max_pipeline, 20000);
+        assert_eq!(config.session.recv_timeout, Some(Duration::from_secs(5)));
+        assert_eq!(config.sentinel.addrs, vec!["127.0.0.1:26379"]);
+        assert_eq!(config.backend.recv_bufsize, 128 * 1024);
+        // numbers are kept as strings for the keys of strings
+        assert_eq!(config.session.auth, "123456");
+        assert_eq!(config.proxy.product_name, "2024");
+
+        let overrides = ["proxy.adr=x".parse().unwrap()];
+        let err = Config::from_path_with(&config_path, &overrides).unwrap_err();
+        assert!(err.to_string().contains("unknown key `proxy.adr`"));
+        let overrides = ["proxy.addr.port=1".parse().unwrap()];
+        assert!(Config::from_path_with(&config_path, &overrides).is_err());
+        let overrides = ["backend.max_pipeline=abc".parse().unwrap()];
+        let err = Config::from_path_with(&config_path, &overrides).unwrap_err();
+        assert!(
+            err.to_string().contains("`backend.max_pipeline`"),
+            "{}",
+            err
+        );
+
+        // the printed config is read back the same
+        let printed = toml::to_string_pretty(&config).unwrap();
+        let parsed = Config::from_toml(&printed).unwrap();
+        assert!(config.diff(&parsed).is_empty());
+        assert!(printed.contains("recv_timeout = \"5s\""));
+        assert!(printed.contains("recv_bufsize = \"128kb\""));
     }
 
     #[test]
@@ -226,7 +1089,26 @@ mod tests {
             deserialize_string_to_size(toml::Value::String("10tb".into())).unwrap()
         );
         assert!(deserialize_string_to_size(toml::Value::String("kb".into())).is_err());
-        assert!(deserialize_string_to_size(toml::Value::String("10".into())).is_err());
+        assert!(deserialize_string_to_size(toml::Value::String("10xb".into())).is_err());
+        // bare numbers are bytes
+        for (value, expected) in [
+            (toml::Value::String("10".into()), 10),
+            (toml::Value::String("0".into()), 0),
+            (toml::Value::Integer(4096), 4096),
+            (toml::Value::String("10b".into()), 10),
+            (toml::Value::String("1.5MB".into()), 3 * MB / 2),
+            (toml::Value::String("2G".into()), 2 * GB),
+        ] {
+            assert_eq!(deserialize_string_to_size(value).unwrap(), expected);
+        }
+        assert_eq!(
+            deserialize_string_to_optional_size(toml::Value::Integer(0)).unwrap(),
+            None
+        );
+        assert_eq!(
+            deserialize_string_to_optional_size(toml::Value::String("1kb".into())).unwrap(),
+            Some(KB)
+        );
     }
 
     #[test]
@@ -251,7 +1133,72 @@ mod tests {
             Duration::from_nanos(10),
             deserialize_string_to_duration(toml::Value::String("10ns".into())).unwrap()
         );
-        assert!(deserialize_string_to_size(toml::Value::String("ms".into())).is_err());
-        assert!(deserialize_string_to_size(toml::Value::String("10".into())).is_err());
+        assert!(deserialize_string_to_duration(toml::Value::String("ms".into())).is_err());
+        assert!(deserialize_string_to_duration(toml::Value::String("10".into())).is_err());
+        assert!(deserialize_string_to_duration(toml::Value::Integer(10)).is_err());
+        assert!(deserialize_string_to_duration(toml::Value::String("10w".into())).is_err());
+        for (value, expected) in [
+            (toml::Value::String("0".into()), Duration::ZERO),
+            (toml::Value::Integer(0), Duration::ZERO),
+            (toml::Value::String("2h".into()), Duration::from_secs(7200)),
+            (toml::Value::String("1d".into()), Duration::from_secs(86400)),
+            (
+                toml::Value::String("1.5s".into()),
+                Duration::from_millis(1500),
+            ),
+            (
+                toml::Value::String("0.1S".into()),
+                Duration::from_millis(100),
+            ),
+            (
+                toml::Value::String("30MS".into()),
+                Duration::from_millis(30),
+            ),
+        ] {
+            assert_eq!(deserialize_string_to_duration(value).unwrap(), expected);
+        }
+        assert_eq!(
+            deserialize_string_to_optional_duration(toml::Value::String("0s".into())).unwrap(),
+            None
+        );
+        assert_eq!(
+            deserialize_string_to_optional_duration(toml::Value::String("1m".into())).unwrap(),
+            Some(Duration::from_secs(60))
+        );
+    }
+
+    #[test]
+    fn test_output_buffer_limit() {
+        let limit = "32MB 8mb 60s".parse::<OutputBufferLimit>().unwrap();
+        assert_eq!(
+            limit,
+            OutputBufferLimit {
+                hard: Some(32 * MB),
+                soft: Some(8 * MB),
+                soft_period: Duration::from_secs(60),
+            }
+        );
+        // bare seconds as redis
+        assert_eq!(
+            "0 1kb 30".parse::<OutputBufferLimit>().unwrap().soft_period,
+            Duration::from_secs(30)
+        );
+        assert_eq!(
+            "0 0 0".parse::<OutputBufferLimit>().unwrap(),
+            OutputBufferLimit::default()
+        );
+        assert!("32mb 8mb".parse::<OutputBufferLimit>().is_err());
+        assert!("32xb 8mb 60s".parse::<OutputBufferLimit>().is_err());
+
+        let config: Config =
+            toml::from_str("[session]\noutput_buffer_limit_pubsub = \"32mb 8mb 1m\"").unwrap();
+        assert_eq!(config.session.output_buffer_limit(true), limit);
+        assert_eq!(
+            config.session.output_buffer_limit(false),
+            OutputBufferLimit::default()
+        );
+        let printed = toml::to_string(&config).unwrap();
+        assert!(printed.contains("output_buffer_limit_pubsub = \"32mb 8mb 1m\""));
+        assert!(printed.contains("output_buffer_limit_normal = \"0 0 0\""));
     }
 }
@@ -0,0 +1,60 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use serde::Serialize;
+use tracing::info;
+
+use crate::models::Slot;
+use crate::utils::time::format_utc;
+
+/// how many records are kept, the oldest ones are dropped first
+const MAX_AUDIT_RECORDS: usize = 1024;
+
+/// AuditRecord tells who changed a slot by the admin api, and how. The
+/// operator is told by the caller, the peer is the address it's from.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    pub time: String,
+    pub operator: String,
+    pub peer: String,
+    pub action: String,
+    pub slot: u64,
+    pub before: Slot,
+    pub after: Slot,
+}
+
+/// AuditLog keeps the latest changes of slots made by hand, every change is
+/// logged as well.
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    records: Mutex<VecDeque<AuditRecord>>,
+}
+
+impl AuditLog {
+    pub fn record(&self, operator: &str, peer: &str, action: &str, before: Slot, after: Slot) {
+        info!(
+            "slot-{:04} is changed by {} from {} ({}): {:?} -> {:?}",
+            after.id, operator, peer, action, before, after
+        );
+        let record = AuditRecord {
+            time: format_utc(SystemTime::now()),
+            operator: operator.to_string(),
+            peer: peer.to_string(),
+            action: action.to_string(),
+            slot: after.id,
+            before,
+            after,
+        };
+        let mut records = self.records.lock().unwrap();
+        if records.len() >= MAX_AUDIT_RECORDS {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    /// Records from the oldest to the latest.
+    pub fn records(&self) -> Vec<AuditRecord> {
+        self.records.lock().unwrap().iter().cloned().collect()
+    }
+}
@@ -1 +1,3 @@
-mod proxy_api;
+pub mod audit;
+pub mod prometheus;
+pub mod proxy_api;
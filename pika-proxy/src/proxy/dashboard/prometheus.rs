@@ -0,0 +1,193 @@
+use std::fmt::Write;
+
+use crate::proxy::server::proxy_metrics::ProxyMetrics;
+use crate::utils::histogram::Percentiles;
+
+const PREFIX: &str = "pika_proxy";
+
+type Stat = fn(&Percentiles) -> u64;
+
+/// the latency gauges, named by the first item and described by the second
+const PERCENTILES: [(&str, &str, Stat); 5] = [
+    ("p50", "Median", |p| p.p50),
+    ("p90", "The 90th percentile", |p| p.p90),
+    ("p99", "The 99th percentile", |p| p.p99),
+    ("p999", "The 99.9th percentile", |p| p.p999),
+    ("max", "The max", |p| p.max),
+];
+
+/// Encodes the metrics in the prometheus text format, all samples are
+/// labelled by `product_name`. Commands are labelled by the names of the
+/// command table or `UNKNOWN`, and backends by the addresses connected in
+/// the last window, so the labels are bounded.
+pub fn encode(metrics: &ProxyMetrics, product_name: &str, online: bool) -> String {
+    let mut out = Encoder {
+        out: String::new(),
+        product: escape(product_name),
+    };
+    let ops = metrics.ops();
+    let sessions = metrics.sessions();
+    out.metric("online", "gauge", "Whether the proxy is online.");
+    out.sample("online", "", online as u64);
+    out.metric("sessions", "gauge", "Client sessions alive.");
+    out.sample("sessions", "", sessions.alive);
+    out.metric("sessions_total", "counter", "Client sessions accepted.");
+    out.sample("sessions_total", "", sessions.total);
+    out.metric(
+        "sessions_output_buffer_evicted_total",
+        "counter",
+        "Client sessions disconnected for exceeding the output buffer limit.",
+    );
+    out.sample(
+        "sessions_output_buffer_evicted_total",
+        "",
+        sessions.output_buffer_evicted,
+    );
+    let offheap = metrics.offheap_snapshot();
+    out.metric(
+        "offheap_bytes",
+        "gauge",
+        "Bytes of requests and responses held by sessions.",
+    );
+    out.sample("offheap_bytes", "", offheap.used);
+    out.metric(
+        "oom_rejected_total",
+        "counter",
+        "Commands rejected as max_offheap_bytes is exceeded.",
+    );
+    out.sample("oom_rejected_total", "", offheap.rejected);
+    out.metric(
+        "oom_evicted_total",
+        "counter",
+        "Sessions disconnected as max_offheap_bytes is exceeded.",
+    );
+    out.sample("oom_evicted_total", "", offheap.evicted);
+    out.metric("ops_total", "counter", "Commands answered.");
+    out.sample("ops_total", "", ops.total);
+    out.metric(
+        "ops_fails_total",
+        "counter",
+        "Commands failed by the proxy.",
+    );
+    out.sample("ops_fails_total", "", ops.fails);
+    out.metric(
+        "ops_redis_errors_total",
+        "counter",
+        "Commands answered with an error reply.",
+    );
+    out.sample("ops_redis_errors_total", "", ops.redis.errors);
+    out.metric("ops_qps", "gauge", "Commands answered per second.");
+    out.sample("ops_qps", "", ops.qps);
+
+    let counters = [
+        ("command_calls_total", "Calls of a command."),
+        ("command_usecs_total", "Microseconds spent on a command."),
+        (
+            "command_fails_total",
+            "Calls of a command failed by the proxy.",
+        ),
+        (
+            "command_redis_errors_total",
+            "Calls of a command answered with an error reply.",
+        ),
+    ];
+    for (i, (name, help)) in counters.into_iter().enumerate() {
+        out.metric(name, "counter", help);
+        for cmd in &ops.cmd {
+            let value = [cmd.calls, cmd.usecs, cmd.fails, cmd.redis_errtype][i];
+            out.sample(name, &format!(",command=\"{}\"", escape(&cmd.opstr)), value);
+        }
+    }
+
+    // a gauge for each percentile, as the window has no sum for a summary
+    let latency = metrics.latency();
+    for (stat, desc, value) in PERCENTILES {
+        let name = format!("command_latency_{}_usecs", stat);
+        let help = format!("{} latency of a command in the current window.", desc);
+        out.metric(&name, "gauge", &help);
+        for (command, percentiles) in &latency.commands {
+            let labels = format!(",command=\"{}\"", escape(command));
+            out.sample(&name, &labels, value(percentiles));
+        }
+    }
+    for (stat, desc, value) in PERCENTILES {
+        let name = format!("backend_latency_{}_usecs", stat);
+        let help = format!("{} round trip of a backend in the current window.", desc);
+        out.metric(&name, "gauge", &help);
+        for (addr, percentiles) in &latency.backends {
+            let labels = format!(",addr=\"{}\"", escape(addr));
+            out.sample(&name, &labels, value(percentiles));
+        }
+    }
+    out.out
+}
+
+struct Encoder {
+    out: String,
+    product: String,
+}
+
+impl Encoder {
+    fn metric(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.out, "# HELP {}_{} {}", PREFIX, name, help);
+        let _ = writeln!(self.out, "# TYPE {}_{} {}", PREFIX, name, kind);
+    }
+
+    /// `labels` are the ones besides the product, each led by a comma.
+    fn sample(&mut self, name: &str, labels: &str, value: u64) {
+        let _ = writeln!(
+            self.out,
+            "{}_{}{{product_name=\"{}\"{}}} {}",
+            PREFIX, name, self.product, labels, value
+        );
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use redis::RedisResp;
+
+    use super::*;
+    use crate::models::get_command;
+
+    #[test]
+    fn test_encode() {
+        let metrics = ProxyMetrics::default();
+        let ms = Duration::from_millis(1);
+        metrics.record(get_command("GET"), ms, &Ok(RedisResp::ok()));
+        metrics.record(None, ms, &Ok(RedisResp::error("ERR unknown")));
+        let backend = metrics.backend_latency("127.0.0.1:6379");
+        backend.record(100);
+
+        let text = encode(&metrics, "de\"mo", true);
+        let lines = text.lines().collect::<Vec<_>>();
+        assert!(!text.contains("quantile"));
+        for expected in [
+            "# TYPE pika_proxy_ops_total counter",
+            r#"pika_proxy_online{product_name="de\"mo"} 1"#,
+            r#"pika_proxy_ops_total{product_name="de\"mo"} 2"#,
+            r#"pika_proxy_offheap_bytes{product_name="de\"mo"} 0"#,
+            r#"pika_proxy_command_calls_total{product_name="de\"mo",command="GET"} 1"#,
+            r#"pika_proxy_command_redis_errors_total{product_name="de\"mo",command="UNKNOWN"} 1"#,
+            "# TYPE pika_proxy_command_latency_p99_usecs gauge",
+            r#"pika_proxy_command_latency_p99_usecs{product_name="de\"mo",command="GET"} 1000"#,
+            r#"pika_proxy_backend_latency_max_usecs{product_name="de\"mo",addr="127.0.0.1:6379"} 100"#,
+        ] {
+            assert!(
+                lines.contains(&expected),
+                "{} is missing in\n{}",
+                expected,
+                text
+            );
+        }
+    }
+}
@@ -1,17 +1,656 @@
-use axum::{routing::get, Router};
-use std::{io::Result, sync::Arc};
+use std::net::SocketAddr;
+use std::sync::Arc;
 
-use crate::proxy::config::Config;
+use anyhow::anyhow;
+use axum::extract::{ConnectInfo, Path, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, put};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
 
-// Proxy 表示 pika-proxy 的唯一元数据, 应该是作为全局静态的
-async fn server_proxy_api(proxy: Arc<Config>) -> Result<()> {
-    // build our application with a single route
-    let app = Router::new().route("/", get(|| async { "Hello, World!" }));
+use crate::error::{Error, Result};
+use crate::models::{ProxyModel, SentinelModel, Slot, MAX_SLOT_NUM};
+use crate::proxy::config::ConfigHandle;
+use crate::proxy::dashboard::audit::{AuditLog, AuditRecord};
+use crate::proxy::dashboard::prometheus;
+use crate::proxy::registry::Registry;
+use crate::proxy::router::Router as SlotRouter;
+use crate::proxy::sentinel::Sentinel;
+use crate::proxy::server::lifecycle::{Lifecycle, ProxyState};
+use crate::proxy::server::proxy_metrics::{
+    LatencySnapshot, OpsSnapshot, ProxyMetrics, SessionsSnapshot,
+};
+use crate::proxy::server::{reload_config, shutdown_proxy};
+use crate::proxy::session::memory::OffheapSnapshot;
 
-    // run it with hyper on localhost:3000
-    axum::Server::bind(&"0.0.0.0:3000".parse().unwrap())
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+/// the header which tells who changes a slot, for the audit records. It's
+/// told by the caller and not verified, so the peer address is recorded
+/// along with it.
+const OPERATOR_HEADER: &str = "x-operator";
+
+/// Returns the token the admin api is authenticated by, the same as codis:
+/// hex of the first 16 bytes of
+/// `sha256("Codis-XAuth-[<product_name>]-[<product_auth>]-[<token>]")`.
+pub fn xauth(product_name: &str, product_auth: &str, token: &str) -> String {
+    let digest = Sha256::digest(format!(
+        "Codis-XAuth-[{}]-[{}]-[{}]",
+        product_name, product_auth, token
+    ));
+    digest[..16].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// ProxyApi is the state shared by the handlers of the admin api, which is
+/// called by codis-dashboard and codis-admin, and by the reporters.
+pub struct ProxyApi {
+    pub model: ProxyModel,
+    pub xauth: String,
+    pub router: Arc<dyn SlotRouter>,
+    pub registry: Arc<Registry>,
+    pub sentinel: Arc<Sentinel>,
+    pub lifecycle: Arc<Lifecycle>,
+    pub metrics: Arc<ProxyMetrics>,
+    pub closed: CancellationToken,
+    pub audit: AuditLog,
+    pub config: ConfigHandle,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct SentinelStats {
+    servers: Vec<String>,
+    switched: bool,
+}
+
+/// Stats is the json of `/api/proxy/stats`.
+#[derive(Serialize, Debug, Default)]
+pub struct Stats {
+    online: bool,
+    closed: bool,
+    state: String,
+    sentinels: SentinelStats,
+    ops: OpsSnapshot,
+    latency: LatencySnapshot,
+    sessions: SessionsSnapshot,
+    offheap: OffheapSnapshot,
+}
+
+/// SlotAssignment is the body to change a slot by hand, fields missing are
+/// kept as they are. The slot may be changed again by the registry, as the
+/// registry is still the source of truth.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct SlotAssignment {
+    locked: Option<bool>,
+    backend_addr: Option<String>,
+    backend_addr_group_id: Option<u64>,
+    migrate_from: Option<String>,
+    migrate_from_group_id: Option<u64>,
+    forward_method: Option<u64>,
+    replica_groups: Option<Vec<Vec<String>>>,
+}
+
+impl SlotAssignment {
+    fn apply(self, slot: &mut Slot) {
+        let Self {
+            locked,
+            backend_addr,
+            backend_addr_group_id,
+            migrate_from,
+            migrate_from_group_id,
+            forward_method,
+            replica_groups,
+        } = self;
+        slot.locked = locked.unwrap_or(slot.locked);
+        if let Some(addr) = backend_addr {
+            slot.backend_addr = addr;
+        }
+        slot.backend_add_group_id = backend_addr_group_id.unwrap_or(slot.backend_add_group_id);
+        if let Some(addr) = migrate_from {
+            slot.migrate_from = addr;
+        }
+        slot.migrate_from_group_id = migrate_from_group_id.unwrap_or(slot.migrate_from_group_id);
+        slot.forward_method = forward_method.unwrap_or(slot.forward_method);
+        if let Some(groups) = replica_groups {
+            slot.replica_groups = groups;
+        }
+    }
+}
+
+/// An error replied with a non-200 status, which codis takes as a failure.
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "Cause": self.1 }))).into_response()
+    }
+}
+
+impl From<Error> for ApiError {
+    fn from(e: Error) -> Self {
+        ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    }
+}
+
+type ApiResult<T> = std::result::Result<Json<T>, ApiError>;
+
+impl ProxyApi {
+    fn check_xauth(&self, xauth: &str) -> std::result::Result<(), ApiError> {
+        if xauth != self.xauth {
+            return Err(ApiError(StatusCode::FORBIDDEN, "invalid xauth".to_string()));
+        }
+        Ok(())
+    }
+
+    fn check_slot(&self, id: u64) -> std::result::Result<(), ApiError> {
+        if id as usize >= MAX_SLOT_NUM {
+            return Err(ApiError(
+                StatusCode::BAD_REQUEST,
+                format!("invalid slot id {}", id),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Fills the slot changed by `change` under the slot lock, and records it
+    /// with the operator and the peer address of the caller.
+    fn change_slot<F>(
+        &self,
+        headers: &HeaderMap,
+        peer: Option<SocketAddr>,
+        id: u64,
+        action: &str,
+        change: F,
+    ) -> Result<Slot>
+    where
+        F: FnOnce(&mut Slot),
+    {
+        let (before, after) = self.router.update_slot(id, Box::new(change))?;
+        let operator = headers
+            .get(OPERATOR_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("unknown");
+        let peer = peer
+            .map(|addr| addr.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        self.audit
+            .record(operator, &peer, action, before, after.clone());
+        Ok(after)
+    }
+
+    pub fn stats(&self) -> Stats {
+        let state = self.lifecycle.state();
+        Stats {
+            online: state == ProxyState::Online,
+            closed: state == ProxyState::Closing,
+            state: state.to_string(),
+            sentinels: SentinelStats {
+                servers: self.sentinel.addrs(),
+                switched: self.router.has_switched(),
+            },
+            ops: self.metrics.ops(),
+            latency: self.metrics.latency(),
+            sessions: self.metrics.sessions(),
+            offheap: self.metrics.offheap_snapshot(),
+        }
+    }
+}
+
+/// Builds the routes of the admin api.
+pub fn api_router(api: Arc<ProxyApi>) -> Router {
+    Router::new()
+        .route("/api/proxy/model", get(model))
+        .route("/metrics", get(prometheus_metrics))
+        .route("/api/proxy/xping/:xauth", get(xping))
+        .route("/api/proxy/stats/:xauth", get(stats))
+        .route("/api/proxy/slots/:xauth", get(slots))
+        .route("/api/proxy/slots/:xauth/:sid", get(slot).put(assign_slot))
+        .route("/api/proxy/slots/:xauth/:sid/lock", put(lock_slot))
+        .route("/api/proxy/slots/:xauth/:sid/unlock", put(unlock_slot))
+        .route("/api/proxy/audit/:xauth", get(audit))
+        .route("/api/proxy/start/:xauth", put(start))
+        .route("/api/proxy/shutdown/:xauth", put(shutdown))
+        .route("/api/proxy/fillslots/:xauth", put(fill_slots))
+        .route("/api/proxy/reload/:xauth", put(reload))
+        .route("/api/proxy/sentinels/:xauth", put(set_sentinels))
+        .route(
+            "/api/proxy/sentinels/:xauth/rewatch",
+            put(rewatch_sentinels),
+        )
+        .with_state(api)
+}
+
+/// Serves the admin api on `addr` until the proxy is closed, the address is
+/// bound before it returns so a bad address fails the startup.
+pub fn serve_proxy_api(addr: &str, api: Arc<ProxyApi>) -> Result<()> {
+    let addr: SocketAddr = addr
+        .parse()
+        .map_err(|e| Error::initialize(anyhow!("invalid admin_addr {}: {}", addr, e)))?;
+    let server = axum::Server::try_bind(&addr)
+        .map_err(|e| Error::initialize(anyhow!("bind admin_addr {} failed: {}", addr, e)))?;
+    let closed = api.closed.clone();
+    let app = api_router(api);
+    info!("admin api listens on {}", addr);
+    tokio::spawn(async move {
+        let server = server
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .with_graceful_shutdown(closed.cancelled_owned());
+        if let Err(e) = server.await {
+            warn!("admin api exits with error: {}", e);
+        }
+    });
     Ok(())
 }
+
+async fn model(State(api): State<Arc<ProxyApi>>) -> Json<ProxyModel> {
+    Json(api.model.clone())
+}
+
+/// Metrics in the prometheus text format, it's not authenticated so it can
+/// be scraped.
+async fn prometheus_metrics(State(api): State<Arc<ProxyApi>>) -> impl IntoResponse {
+    let text = prometheus::encode(
+        &api.metrics,
+        &api.model.product_name,
+        api.lifecycle.is_online(),
+    );
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+        text,
+    )
+}
+
+async fn xping(
+    State(api): State<Arc<ProxyApi>>,
+    Path(xauth): Path<String>,
+) -> ApiResult<&'static str> {
+    api.check_xauth(&xauth)?;
+    Ok(Json("OK"))
+}
+
+async fn stats(State(api): State<Arc<ProxyApi>>, Path(xauth): Path<String>) -> ApiResult<Stats> {
+    api.check_xauth(&xauth)?;
+    Ok(Json(api.stats()))
+}
+
+async fn slots(
+    State(api): State<Arc<ProxyApi>>,
+    Path(xauth): Path<String>,
+) -> ApiResult<Vec<Slot>> {
+    api.check_xauth(&xauth)?;
+    Ok(Json(
+        api.router
+            .get_slots()
+            .into_iter()
+            .map(|slot| *slot)
+            .collect(),
+    ))
+}
+
+async fn slot(
+    State(api): State<Arc<ProxyApi>>,
+    Path((xauth, id)): Path<(String, u64)>,
+) -> ApiResult<Slot> {
+    api.check_xauth(&xauth)?;
+    api.check_slot(id)?;
+    Ok(Json(*api.router.get_slot(id)))
+}
+
+/// Changes how a slot is routed, replies the slot changed.
+async fn assign_slot(
+    State(api): State<Arc<ProxyApi>>,
+    Path((xauth, id)): Path<(String, u64)>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Json(assignment): Json<SlotAssignment>,
+) -> ApiResult<Slot> {
+    api.check_xauth(&xauth)?;
+    api.check_slot(id)?;
+    let slot = api.change_slot(
+        &headers,
+        peer.map(|ConnectInfo(addr)| addr),
+        id,
+        "assign",
+        |slot| assignment.apply(slot),
+    )?;
+    Ok(Json(slot))
+}
+
+/// Locks a slot, requests of it are held until it's unlocked.
+async fn lock_slot(
+    State(api): State<Arc<ProxyApi>>,
+    Path((xauth, id)): Path<(String, u64)>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+) -> ApiResult<Slot> {
+    api.check_xauth(&xauth)?;
+    api.check_slot(id)?;
+    let slot = api.change_slot(
+        &headers,
+        peer.map(|ConnectInfo(addr)| addr),
+        id,
+        "lock",
+        |slot| slot.locked = true,
+    )?;
+    Ok(Json(slot))
+}
+
+async fn unlock_slot(
+    State(api): State<Arc<ProxyApi>>,
+    Path((xauth, id)): Path<(String, u64)>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+) -> ApiResult<Slot> {
+    api.check_xauth(&xauth)?;
+    api.check_slot(id)?;
+    let slot = api.change_slot(
+        &headers,
+        peer.map(|ConnectInfo(addr)| addr),
+        id,
+        "unlock",
+        |slot| slot.locked = false,
+    )?;
+    Ok(Json(slot))
+}
+
+async fn audit(
+    State(api): State<Arc<ProxyApi>>,
+    Path(xauth): Path<String>,
+) -> ApiResult<Vec<AuditRecord>> {
+    api.check_xauth(&xauth)?;
+    Ok(Json(api.audit.records()))
+}
+
+/// Brings the proxy online even if some slots are not filled yet.
+async fn start(
+    State(api): State<Arc<ProxyApi>>,
+    Path(xauth): Path<String>,
+) -> ApiResult<&'static str> {
+    api.check_xauth(&xauth)?;
+    if api.lifecycle.state() == ProxyState::Closing {
+        return Err(Error::proxy(anyhow!("proxy is closing")).into());
+    }
+    if api.lifecycle.advance(ProxyState::Online) {
+        info!("proxy is online by admin api");
+    }
+    Ok(Json("OK"))
+}
+
+async fn shutdown(
+    State(api): State<Arc<ProxyApi>>,
+    Path(xauth): Path<String>,
+) -> ApiResult<&'static str> {
+    api.check_xauth(&xauth)?;
+    shutdown_proxy(&api.lifecycle, &api.closed, &api.registry, &api.model).await?;
+    Ok(Json("OK"))
+}
+
+async fn fill_slots(
+    State(api): State<Arc<ProxyApi>>,
+    Path(xauth): Path<String>,
+    Json(slots): Json<Vec<Slot>>,
+) -> ApiResult<&'static str> {
+    api.check_xauth(&xauth)?;
+    for slot in slots {
+        api.router.fill_slot(Box::new(slot))?;
+    }
+    Ok(Json("OK"))
+}
+
+/// Reloads the config file, and replies the keys changed. It's refused if
+/// a key which requires a restart is changed.
+async fn reload(
+    State(api): State<Arc<ProxyApi>>,
+    Path(xauth): Path<String>,
+) -> ApiResult<Vec<String>> {
+    api.check_xauth(&xauth)?;
+    let changed =
+        reload_config(&api.config).map_err(|e| ApiError(StatusCode::BAD_REQUEST, e.to_string()))?;
+    Ok(Json(changed))
+}
+
+async fn set_sentinels(
+    State(api): State<Arc<ProxyApi>>,
+    Path(xauth): Path<String>,
+    Json(sentinel): Json<SentinelModel>,
+) -> ApiResult<&'static str> {
+    api.check_xauth(&xauth)?;
+    api.sentinel.watch(sentinel.servers);
+    Ok(Json("OK"))
+}
+
+async fn rewatch_sentinels(
+    State(api): State<Arc<ProxyApi>>,
+    Path(xauth): Path<String>,
+) -> ApiResult<&'static str> {
+    api.check_xauth(&xauth)?;
+    api.sentinel.watch(api.sentinel.addrs());
+    Ok(Json("OK"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::body::Body;
+    use axum::http::{Method, Request};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::proxy::backend::Backend;
+    use crate::proxy::registry::FileAdapter;
+    use crate::proxy::router::DefaultRouter;
+
+    async fn call(app: &Router, method: Method, uri: &str, body: &str) -> (StatusCode, String) {
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        let status = resp.status();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    #[test]
+    fn test_xauth() {
+        let xauth = xauth("demo", "", "token");
+        assert_eq!(xauth.len(), 32);
+        assert_ne!(xauth, super::xauth("demo", "auth", "token"));
+    }
+
+    /// Builds the api of a proxy with a file registry, the topology file is
+    /// removed when the returned guard is dropped.
+    fn test_api(name: &str, config: ConfigHandle) -> (Arc<ProxyApi>, TempFile) {
+        let path = std::env::temp_dir().join(format!("api-{}-{}.toml", name, std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let adapter = FileAdapter::new(&path, Duration::from_secs(1)).unwrap();
+        let backend = Arc::new(Backend::new(config.clone()));
+        let router: Arc<dyn SlotRouter> = Arc::new(DefaultRouter::new(config.clone(), backend));
+        let closed = CancellationToken::new();
+        let model = ProxyModel {
+            token: "token".to_string(),
+            product_name: "demo".to_string(),
+            ..Default::default()
+        };
+        let api = Arc::new(ProxyApi {
+            xauth: xauth("demo", "", &model.token),
+            model,
+            router: router.clone(),
+            registry: Arc::new(Registry::new(Box::new(adapter), "")),
+            sentinel: Arc::new(Sentinel::new(config.load_full(), router, closed.clone())),
+            lifecycle: Arc::new(Lifecycle::default()),
+            metrics: Arc::default(),
+            closed,
+            audit: AuditLog::default(),
+            config,
+        });
+        (api, TempFile(path))
+    }
+
+    struct TempFile(std::path::PathBuf);
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_proxy_api() {
+        let (api, _file) = test_api("api", ConfigHandle::default());
+        let (router, closed) = (api.router.clone(), api.closed.clone());
+        let app = api_router(api.clone());
+        let xauth = api.xauth.clone();
+
+        let (status, body) = call(&app, Method::GET, "/api/proxy/model", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains(r#""token":"token""#));
+        let (status, body) = call(&app, Method::GET, "/api/proxy/xping/bad", "").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body, r#"{"Cause":"invalid xauth"}"#);
+        let uri = format!("/api/proxy/xping/{}", xauth);
+        assert_eq!(call(&app, Method::GET, &uri, "").await.1, r#""OK""#);
+        let (status, body) = call(&app, Method::GET, "/metrics", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains(r#"pika_proxy_online{product_name="demo"} 0"#));
+
+        let uri = format!("/api/proxy/fillslots/{}", xauth);
+        let slots = r#"[{"id":3,"backend_addr":"127.0.0.1:6379","backend_addr_group_id":1}]"#;
+        assert_eq!(call(&app, Method::PUT, &uri, slots).await.0, StatusCode::OK);
+        assert_eq!(router.get_slot(3).backend_addr, "127.0.0.1:6379");
+        let uri = format!("/api/proxy/slots/{}", xauth);
+        let (_, body) = call(&app, Method::GET, &uri, "").await;
+        let slots: Vec<Slot> = serde_json::from_str(&body).unwrap();
+        assert_eq!(slots.len(), crate::models::MAX_SLOT_NUM);
+        assert_eq!(slots[3].backend_add_group_id, 1);
+
+        let uri = format!("/api/proxy/sentinels/{}", xauth);
+        let sentinels = r#"{"servers":["127.0.0.1:1"]}"#;
+        assert_eq!(
+            call(&app, Method::PUT, &uri, sentinels).await.0,
+            StatusCode::OK
+        );
+        let uri = format!("/api/proxy/start/{}", xauth);
+        assert_eq!(call(&app, Method::PUT, &uri, "").await.0, StatusCode::OK);
+        let uri = format!("/api/proxy/stats/{}", xauth);
+        let (_, body) = call(&app, Method::GET, &uri, "").await;
+        let stats: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(stats["online"], true);
+        assert_eq!(stats["sentinels"]["servers"][0], "127.0.0.1:1");
+
+        let uri = format!("/api/proxy/shutdown/{}", xauth);
+        assert_eq!(call(&app, Method::PUT, &uri, "").await.0, StatusCode::OK);
+        assert!(closed.is_cancelled());
+        let uri = format!("/api/proxy/start/{}", xauth);
+        let (status, _) = call(&app, Method::PUT, &uri, "").await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_slot_override() {
+        let (api, _file) = test_api("override", ConfigHandle::default());
+        let app = api_router(api.clone());
+        let xauth = api.xauth.clone();
+
+        let uri = format!("/api/proxy/slots/{}/1024", xauth);
+        let (status, _) = call(&app, Method::GET, &uri, "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let uri = format!("/api/proxy/slots/{}/7", xauth);
+        let body = r#"{"backend_addr":"127.0.0.1:6380","backend_addr_group_id":2,"replica_groups":[["127.0.0.1:6381"]]}"#;
+        let req = Request::builder()
+            .method(Method::PUT)
+            .uri(&uri)
+            .header("content-type", "application/json")
+            .header(OPERATOR_HEADER, "alice")
+            .extension(ConnectInfo("10.0.0.1:5000".parse::<SocketAddr>().unwrap()))
+            .body(Body::from(body))
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let (status, _) = call(&app, Method::PUT, &uri, r#"{"unknown":1}"#).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let lock = format!("/api/proxy/slots/{}/7/lock", xauth);
+        assert_eq!(call(&app, Method::PUT, &lock, "").await.0, StatusCode::OK);
+        let (_, body) = call(&app, Method::GET, &uri, "").await;
+        let slot: Slot = serde_json::from_str(&body).unwrap();
+        assert!(slot.locked);
+        assert_eq!(slot.backend_addr, "127.0.0.1:6380");
+        assert_eq!(slot.backend_add_group_id, 2);
+        assert_eq!(slot.replica_groups, vec![vec!["127.0.0.1:6381"]]);
+        let unlock = format!("/api/proxy/slots/{}/7/unlock", xauth);
+        assert_eq!(call(&app, Method::PUT, &unlock, "").await.0, StatusCode::OK);
+        assert!(!api.router.get_slot(7).locked);
+
+        let uri = format!("/api/proxy/audit/{}", xauth);
+        let (_, body) = call(&app, Method::GET, &uri, "").await;
+        let records: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let records = records.as_array().unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0]["operator"], "alice");
+        assert_eq!(records[0]["peer"], "10.0.0.1:5000");
+        assert_eq!(records[0]["action"], "assign");
+        assert_eq!(records[0]["before"]["backend_addr"], "");
+        assert_eq!(records[1]["operator"], "unknown");
+        assert_eq!(records[1]["peer"], "unknown");
+        assert_eq!(records[2]["after"]["locked"], false);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_concurrent_slot_changes() {
+        let (api, _file) = test_api("concurrent", ConfigHandle::default());
+        let tasks = (0..16)
+            .map(|i| {
+                let api = api.clone();
+                tokio::task::spawn_blocking(move || {
+                    api.change_slot(&HeaderMap::new(), None, 9, "assign", |slot| {
+                        slot.replica_groups
+                            .push(vec![format!("127.0.0.1:{}", 7000 + i)])
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+        // no change is lost
+        assert_eq!(api.router.get_slot(9).replica_groups.len(), 16);
+    }
+
+    #[tokio::test]
+    async fn test_reload() {
+        let path = std::env::temp_dir().join(format!("api-reload-{}.toml", std::process::id()));
+        let _file = TempFile(path.clone());
+        let config = |max_pipeline: u32, addr: &str| {
+            format!(
+                "[proxy]\naddr = \"{}\"\n[session]\nmax_pipeline = {}\n[backend]\nmax_pipeline = 1\n[registry]\naddr = \"topology.toml\"\n",
+                addr, max_pipeline
+            )
+        };
+        std::fs::write(&path, config(1, "127.0.0.1:19000")).unwrap();
+        let handle = ConfigHandle::from_path(&path).unwrap();
+        let (api, _topology) = test_api("reload", handle.clone());
+        let app = api_router(api.clone());
+        let uri = format!("/api/proxy/reload/{}", api.xauth);
+
+        std::fs::write(&path, config(2, "127.0.0.1:19000")).unwrap();
+        let (status, body) = call(&app, Method::PUT, &uri, "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"["session.max_pipeline"]"#);
+        assert_eq!(handle.load().session.max_pipeline, 2);
+
+        std::fs::write(&path, config(3, "127.0.0.1:19001")).unwrap();
+        let (status, body) = call(&app, Method::PUT, &uri, "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("restart is required to change proxy.addr"));
+        assert_eq!(handle.load().session.max_pipeline, 2);
+    }
+}
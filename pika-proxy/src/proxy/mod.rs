@@ -2,6 +2,8 @@ pub mod backend;
 pub mod config;
 pub mod dashboard;
 pub mod registry;
+pub mod reporter;
 pub mod router;
+pub mod sentinel;
 pub mod server;
 pub mod session;
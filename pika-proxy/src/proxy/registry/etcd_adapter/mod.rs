@@ -1 +1,237 @@
+use std::time::Duration;
 
+use anyhow::anyhow;
+use async_trait::async_trait;
+use etcd_client::{Client, ConnectOptions, GetOptions, PutOptions, WatchOptions};
+use serde::de::DeserializeOwned;
+use tokio::sync::mpsc::{channel, Receiver};
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use crate::error::{Error, Result};
+use crate::models::{CodisPaths, Group, ProxyModel, SlotMapping};
+use crate::proxy::registry::registry_adapter::{
+    decode, topology_change, RegistryAdapter, TopologyChange,
+};
+
+/// EtcdAdapter stores the codis models in etcd, the proxy is registered
+/// with a lease which is kept alive by heartbeats.
+pub struct EtcdAdapter {
+    client: Client,
+    paths: CodisPaths,
+    ttl: Duration,
+    // the proxy registered and its lease
+    registered: Mutex<Option<(ProxyModel, i64)>>,
+}
+
+impl EtcdAdapter {
+    /// Connects to etcd, `auth` is `user:password` or empty.
+    pub async fn connect(
+        endpoints: &[String],
+        auth: &str,
+        timeout: Duration,
+        product_name: &str,
+        ttl: Duration,
+    ) -> Result<Self> {
+        let mut options = ConnectOptions::new();
+        if !timeout.is_zero() {
+            options = options.with_connect_timeout(timeout).with_timeout(timeout);
+        }
+        if let Some((user, password)) = auth.split_once(':') {
+            options = options.with_user(user, password);
+        }
+        let client = Client::connect(endpoints, Some(options))
+            .await
+            .map_err(Error::server)?;
+        Ok(Self {
+            client,
+            paths: CodisPaths::new(product_name),
+            ttl: ttl.max(Duration::from_secs(1)),
+            registered: Mutex::new(None),
+        })
+    }
+
+    /// Puts the proxy model with a new lease, and returns the lease.
+    async fn put_proxy(&self, proxy: &ProxyModel) -> Result<i64> {
+        let mut client = self.client.clone();
+        let lease = client
+            .lease_grant(self.ttl.as_secs() as i64, None)
+            .await
+            .map_err(Error::server)?
+            .id();
+        let value = serde_json::to_vec(proxy).map_err(Error::server)?;
+        let options = PutOptions::new().with_lease(lease);
+        client
+            .put(self.paths.proxy_path(&proxy.token), value, Some(options))
+            .await
+            .map_err(Error::server)?;
+        Ok(lease)
+    }
+
+    async fn load_dir<T: DeserializeOwned>(&self, dir: String) -> Result<Vec<T>> {
+        let options = GetOptions::new().with_prefix();
+        let resp = self
+            .client
+            .clone()
+            .get(format!("{}/", dir), Some(options))
+            .await
+            .map_err(Error::server)?;
+        resp.kvs()
+            .iter()
+            .map(|kv| decode(kv.key_str().unwrap_or_default(), kv.value()))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl RegistryAdapter for EtcdAdapter {
+    async fn register(&self, proxy: &ProxyModel) -> Result<()> {
+        let mut registered = self.registered.lock().await;
+        let lease = self.put_proxy(proxy).await?;
+        *registered = Some((proxy.clone(), lease));
+        Ok(())
+    }
+
+    async fn heartbeat(&self) -> Result<()> {
+        let mut registered = self.registered.lock().await;
+        let Some((proxy, lease)) = registered.as_mut() else {
+            return Err(Error::server(anyhow!("proxy is not registered")));
+        };
+        let (mut keeper, mut stream) = self
+            .client
+            .clone()
+            .lease_keep_alive(*lease)
+            .await
+            .map_err(Error::server)?;
+        keeper.keep_alive().await.map_err(Error::server)?;
+        match stream.message().await.map_err(Error::server)? {
+            Some(resp) if resp.ttl() > 0 => Ok(()),
+            _ => {
+                warn!("lease of proxy {} is expired, register again", proxy.token);
+                *lease = self.put_proxy(proxy).await?;
+                Ok(())
+            }
+        }
+    }
+
+    async fn unregister(&self) -> Result<()> {
+        let Some((proxy, lease)) = self.registered.lock().await.take() else {
+            return Ok(());
+        };
+        let mut client = self.client.clone();
+        client
+            .delete(self.paths.proxy_path(&proxy.token), None)
+            .await
+            .map_err(Error::server)?;
+        if let Err(e) = client.lease_revoke(lease).await {
+            debug!("revoke lease {} failed: {}", lease, e);
+        }
+        Ok(())
+    }
+
+    async fn load_slots(&self) -> Result<Vec<SlotMapping>> {
+        let mut slots: Vec<SlotMapping> = self.load_dir(self.paths.slot_dir()).await?;
+        slots.sort_by_key(|slot| slot.id);
+        Ok(slots)
+    }
+
+    async fn load_groups(&self) -> Result<Vec<Group>> {
+        let mut groups: Vec<Group> = self.load_dir(self.paths.group_dir()).await?;
+        groups.sort_by_key(|group| group.id);
+        Ok(groups)
+    }
+
+    async fn watch(&self) -> Result<Receiver<TopologyChange>> {
+        let options = WatchOptions::new().with_prefix();
+        let (watcher, mut stream) = self
+            .client
+            .clone()
+            .watch(format!("{}/", self.paths.product_dir()), Some(options))
+            .await
+            .map_err(Error::server)?;
+        let (tx, rx) = channel(16);
+        let paths = self.paths.clone();
+        tokio::spawn(async move {
+            // the watch is cancelled once the watcher is dropped
+            let _watcher = watcher;
+            loop {
+                let resp = tokio::select! {
+                    resp = stream.message() => resp,
+                    _ = tx.closed() => return,
+                };
+                let resp = match resp {
+                    Ok(Some(resp)) => resp,
+                    Ok(None) => return,
+                    Err(e) => {
+                        warn!("watch etcd failed: {}", e);
+                        return;
+                    }
+                };
+                let mut changes = resp
+                    .events()
+                    .iter()
+                    .filter_map(|event| event.kv()?.key_str().ok())
+                    .filter_map(|key| topology_change(&paths, key))
+                    .collect::<Vec<_>>();
+                changes.dedup();
+                for change in changes {
+                    if tx.send(change).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(rx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs against a local etcd, such as `etcd --listen-client-urls
+    /// http://127.0.0.1:2379`.
+    #[tokio::test]
+    #[ignore = "requires a local etcd"]
+    async fn test_etcd_adapter() {
+        let endpoints = vec!["127.0.0.1:2379".to_string()];
+        let timeout = Duration::from_secs(3);
+        let ttl = Duration::from_secs(5);
+        let product = format!("pika-proxy-test-{}", std::process::id());
+        let adapter = EtcdAdapter::connect(&endpoints, "", timeout, &product, ttl)
+            .await
+            .unwrap();
+        let mut changes = adapter.watch().await.unwrap();
+
+        let paths = CodisPaths::new(&product);
+        let slot = SlotMapping {
+            id: 1,
+            group_id: 2,
+            ..Default::default()
+        };
+        let mut client = adapter.client.clone();
+        let value = serde_json::to_vec(&slot).unwrap();
+        client.put(paths.slot_path(1), value, None).await.unwrap();
+        assert_eq!(changes.recv().await, Some(TopologyChange::Slots));
+        assert_eq!(adapter.load_slots().await.unwrap(), vec![slot]);
+
+        let proxy = ProxyModel {
+            token: "token".to_string(),
+            product_name: product.clone(),
+            ..Default::default()
+        };
+        adapter.register(&proxy).await.unwrap();
+        adapter.heartbeat().await.unwrap();
+        let resp = client.get(paths.proxy_path("token"), None).await.unwrap();
+        assert_eq!(resp.kvs().len(), 1);
+        adapter.unregister().await.unwrap();
+        let resp = client.get(paths.proxy_path("token"), None).await.unwrap();
+        assert!(resp.kvs().is_empty());
+
+        let options = etcd_client::DeleteOptions::new().with_prefix();
+        client
+            .delete(paths.product_dir(), Some(options))
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,285 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use serde::Deserialize;
+use tokio::sync::mpsc::{channel, Receiver};
+use tracing::warn;
+
+use crate::error::{Error, Result};
+use crate::models::{Group, GroupServer, ProxyModel, SlotMapping, MAX_SLOT_NUM};
+use crate::proxy::registry::registry_adapter::{RegistryAdapter, TopologyChange};
+
+/// A group of the topology file, replicas serve reads.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+struct FileGroup {
+    id: u64,
+    master: String,
+    replicas: Vec<String>,
+}
+
+/// Slots `begin..=end` are served by `group_id`.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+struct FileSlots {
+    begin: u64,
+    end: u64,
+    group_id: u64,
+}
+
+/// TopologyFile is the content of a topology file, such as:
+///
+/// ```toml
+/// [[groups]]
+/// id = 1
+/// master = "127.0.0.1:6379"
+/// replicas = ["127.0.0.1:6380"]
+///
+/// [[slots]]
+/// begin = 0
+/// end = 1023
+/// group_id = 1
+/// ```
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+struct TopologyFile {
+    groups: Vec<FileGroup>,
+    slots: Vec<FileSlots>,
+}
+
+impl TopologyFile {
+    /// Parses a json file if the extension is `.json`, or a toml file.
+    fn parse(path: &Path, content: &str) -> Result<Self> {
+        let file: TopologyFile = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(content).map_err(Error::initialize)?
+        } else {
+            toml::from_str(content).map_err(Error::initialize)?
+        };
+        for slots in &file.slots {
+            if slots.begin > slots.end || slots.end as usize >= MAX_SLOT_NUM {
+                return Err(Error::initialize(anyhow!(
+                    "invalid slots {}-{} in {}",
+                    slots.begin,
+                    slots.end,
+                    path.display()
+                )));
+            }
+        }
+        Ok(file)
+    }
+
+    fn groups(&self) -> Vec<Group> {
+        let mut groups = self
+            .groups
+            .iter()
+            .map(|group| {
+                let master = GroupServer {
+                    addr: group.master.clone(),
+                    ..Default::default()
+                };
+                let replicas = group.replicas.iter().map(|addr| GroupServer {
+                    addr: addr.clone(),
+                    replica_group: true,
+                    ..Default::default()
+                });
+                Group {
+                    id: group.id,
+                    servers: std::iter::once(master).chain(replicas).collect(),
+                    ..Default::default()
+                }
+            })
+            .collect::<Vec<_>>();
+        groups.sort_by_key(|group| group.id);
+        groups
+    }
+
+    /// The later ranges take precedence if they overlap.
+    fn slots(&self) -> Vec<SlotMapping> {
+        let mut slots = vec![None; MAX_SLOT_NUM];
+        for range in &self.slots {
+            for id in range.begin..=range.end {
+                slots[id as usize] = Some(SlotMapping {
+                    id,
+                    group_id: range.group_id,
+                    ..Default::default()
+                });
+            }
+        }
+        slots.into_iter().flatten().collect()
+    }
+}
+
+/// FileAdapter reads the topology from a static file, for deployments
+/// without etcd or redis. The file is polled, and the parts of the topology
+/// changed are watched once it's modified. Proxies are not registered.
+pub struct FileAdapter {
+    path: PathBuf,
+    poll_period: Duration,
+}
+
+impl FileAdapter {
+    /// Checks the file can be loaded, and polls it every `poll_period` when
+    /// it's watched.
+    pub fn new<P: Into<PathBuf>>(path: P, poll_period: Duration) -> Result<Self> {
+        let adapter = Self {
+            path: path.into(),
+            poll_period: poll_period.max(Duration::from_millis(10)),
+        };
+        adapter.load()?;
+        Ok(adapter)
+    }
+
+    fn load(&self) -> Result<TopologyFile> {
+        let content = std::fs::read_to_string(&self.path).map_err(|e| {
+            Error::initialize(anyhow!("read {} failed: {}", self.path.display(), e))
+        })?;
+        TopologyFile::parse(&self.path, &content)
+    }
+}
+
+#[async_trait]
+impl RegistryAdapter for FileAdapter {
+    async fn register(&self, _proxy: &ProxyModel) -> Result<()> {
+        Ok(())
+    }
+
+    async fn heartbeat(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn unregister(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn load_slots(&self) -> Result<Vec<SlotMapping>> {
+        Ok(self.load()?.slots())
+    }
+
+    async fn load_groups(&self) -> Result<Vec<Group>> {
+        Ok(self.load()?.groups())
+    }
+
+    async fn watch(&self) -> Result<Receiver<TopologyChange>> {
+        let mut last = self.load()?;
+        let adapter = Self {
+            path: self.path.clone(),
+            poll_period: self.poll_period,
+        };
+        let (tx, rx) = channel(16);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(adapter.poll_period);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = tx.closed() => return,
+                }
+                // a broken file is ignored until it's fixed, the topology
+                // loaded before is kept
+                let file = match adapter.load() {
+                    Ok(file) => file,
+                    Err(e) => {
+                        warn!("load topology file failed: {}", e);
+                        continue;
+                    }
+                };
+                let mut changes = vec![];
+                if file.groups != last.groups {
+                    changes.push(TopologyChange::Groups);
+                }
+                if file.slots != last.slots {
+                    changes.push(TopologyChange::Slots);
+                }
+                last = file;
+                for change in changes {
+                    if tx.send(change).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(rx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::proxy::backend::Backend;
+    use crate::proxy::config::ConfigHandle;
+    use crate::proxy::registry::Registry;
+    use crate::proxy::router::{DefaultRouter, Router};
+
+    const TOPOLOGY: &str = r#"
+        [[groups]]
+        id = 1
+        master = "127.0.0.1:6379"
+        replicas = ["127.0.0.1:6380"]
+
+        [[groups]]
+        id = 2
+        master = "127.0.0.1:6381"
+
+        [[slots]]
+        begin = 0
+        end = 511
+        group_id = 1
+
+        [[slots]]
+        begin = 512
+        end = 1023
+        group_id = 2
+    "#;
+
+    #[test]
+    fn test_parse_topology() {
+        let path = Path::new("topology.toml");
+        let file = TopologyFile::parse(path, TOPOLOGY).unwrap();
+        let slots = file.slots();
+        assert_eq!(slots.len(), MAX_SLOT_NUM);
+        assert_eq!(slots[511].group_id, 1);
+        assert_eq!(slots[512].group_id, 2);
+        assert_eq!(
+            file.groups()[0].replica_groups(""),
+            vec![vec!["127.0.0.1:6380"]]
+        );
+
+        let json =
+            r#"{"groups":[{"id":1,"master":"a:1"}],"slots":[{"begin":3,"end":4,"group_id":1}]}"#;
+        let file = TopologyFile::parse(Path::new("topology.json"), json).unwrap();
+        assert_eq!(file.slots().len(), 2);
+
+        let invalid = "[[slots]]\nbegin = 0\nend = 1024\n";
+        assert!(TopologyFile::parse(path, invalid).is_err());
+        assert!(TopologyFile::parse(path, "[[group]]\nid = 1\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_file_adapter() {
+        let path = std::env::temp_dir().join(format!("topology-{}.toml", std::process::id()));
+        std::fs::write(&path, TOPOLOGY).unwrap();
+        let adapter = FileAdapter::new(&path, Duration::from_millis(10)).unwrap();
+        let mut changes = adapter.watch().await.unwrap();
+        let registry = Registry::new(Box::new(adapter), "");
+
+        let config = ConfigHandle::default();
+        let backend = Arc::new(Backend::new(config.clone()));
+        let router = DefaultRouter::new(config, backend);
+        registry.apply(&router).await.unwrap();
+        assert_eq!(router.get_slot(0).backend_addr, "127.0.0.1:6379");
+        assert_eq!(router.get_slot(1023).backend_addr, "127.0.0.1:6381");
+
+        let moved = TOPOLOGY
+            .replace("end = 511", "end = 0")
+            .replace("begin = 512", "begin = 1");
+        std::fs::write(&path, moved).unwrap();
+        assert_eq!(changes.recv().await, Some(TopologyChange::Slots));
+        registry.apply(&router).await.unwrap();
+        assert_eq!(router.get_slot(0).backend_addr, "127.0.0.1:6379");
+        assert_eq!(router.get_slot(1).backend_addr, "127.0.0.1:6381");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
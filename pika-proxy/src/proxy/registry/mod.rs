@@ -1,9 +1,309 @@
-use registry_adapter::RegistryAdapter;
+use std::collections::HashMap;
+use std::future::pending;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub use etcd_adapter::EtcdAdapter;
+pub use file_adapter::FileAdapter;
+pub use redis_adapter::RedisAdapter;
+pub use registry_adapter::{RegistryAdapter, TopologyChange};
+
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::Mutex;
+use tokio::time::{interval_at, Instant, Interval};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::error::Result;
+use crate::models::{Slot, MAX_SLOT_NUM};
+use crate::proxy::router::Router;
 
 mod etcd_adapter;
+mod file_adapter;
 mod redis_adapter;
 mod registry_adapter;
 
+/// a failed watch is retried after this, doubled on every failure
+const WATCH_RETRY_MIN: Duration = Duration::from_millis(100);
+const WATCH_RETRY_MAX: Duration = Duration::from_secs(10);
+
 pub struct Registry {
     adapter: Box<dyn RegistryAdapter>,
+    datacenter: String,
+    // held while a topology is applied, so an older one never overwrites a
+    // newer one
+    applying: Mutex<()>,
+}
+
+impl Registry {
+    pub fn new(adapter: Box<dyn RegistryAdapter>, datacenter: &str) -> Self {
+        Self {
+            adapter,
+            datacenter: datacenter.to_string(),
+            applying: Mutex::new(()),
+        }
+    }
+
+    pub fn adapter(&self) -> &dyn RegistryAdapter {
+        self.adapter.as_ref()
+    }
+
+    /// Loads the topology and fills the slots of `router` which differ from
+    /// it, including the ones whose group has a new master, which is not a
+    /// switch of sentinel. Slots without a mapping are left not ready.
+    pub async fn apply(&self, router: &dyn Router) -> Result<()> {
+        let groups = self.adapter.load_groups().await?;
+        let groups = groups
+            .into_iter()
+            .map(|group| (group.id, group))
+            .collect::<HashMap<_, _>>();
+        let mappings = self.adapter.load_slots().await?;
+
+        let _applying = self.applying.lock().await;
+        let mut slots = (0..MAX_SLOT_NUM as u64)
+            .map(|id| Slot {
+                id,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        for mapping in mappings {
+            if let Some(slot) = slots.get_mut(mapping.id as usize) {
+                *slot = mapping.to_slot(&groups, &self.datacenter);
+            }
+        }
+        let mut filled = 0;
+        for slot in slots {
+            if *router.get_slot(slot.id) != slot {
+                router.fill_slot(Box::new(slot))?;
+                filled += 1;
+            }
+        }
+        if filled > 0 {
+            info!("fill {} slots from registry", filled);
+        }
+        Ok(())
+    }
+
+    /// Spawns a task which applies the topology to `router` whenever the
+    /// registry tells it's changed, and every `reconcile_period` in case a
+    /// change is missed, until `cancel` is cancelled. A failed watch is
+    /// retried with backoff, whether or not it's reconciled.
+    pub fn spawn_watcher(
+        self: Arc<Self>,
+        router: Arc<dyn Router>,
+        reconcile_period: Option<Duration>,
+        cancel: CancellationToken,
+    ) {
+        tokio::spawn(async move {
+            let mut reconcile =
+                reconcile_period.map(|period| interval_at(Instant::now() + period, period));
+            let mut changes = None;
+            let mut retry = WATCH_RETRY_MIN;
+            loop {
+                if changes.is_none() {
+                    match self.adapter.watch().await {
+                        Ok(rx) => {
+                            changes = Some(rx);
+                            retry = WATCH_RETRY_MIN;
+                            // changes made before the watch are not told
+                            if let Err(e) = self.apply(router.as_ref()).await {
+                                warn!("apply topology failed: {}", e);
+                            }
+                        }
+                        Err(e) => warn!("watch registry failed: {}", e),
+                    }
+                }
+                tokio::select! {
+                    change = recv(&mut changes) => match change {
+                        Some(change) => {
+                            // changes queued are covered by one apply
+                            let rx = changes.as_mut().unwrap();
+                            while rx.try_recv().is_ok() {}
+                            debug!("registry {:?} changed", change);
+                        }
+                        None => {
+                            warn!("watch of registry is broken, watch again");
+                            changes = None;
+                        }
+                    },
+                    _ = tick(&mut reconcile) => {}
+                    _ = tokio::time::sleep(retry), if changes.is_none() => {
+                        retry = (retry * 2).min(WATCH_RETRY_MAX);
+                        continue;
+                    }
+                    _ = cancel.cancelled() => return,
+                }
+                if let Err(e) = self.apply(router.as_ref()).await {
+                    warn!("apply topology failed: {}", e);
+                }
+            }
+        });
+    }
+}
+
+async fn recv(changes: &mut Option<Receiver<TopologyChange>>) -> Option<TopologyChange> {
+    match changes {
+        Some(rx) => rx.recv().await,
+        // watched again after the retry delay
+        None => pending().await,
+    }
+}
+
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::error::Error;
+    use crate::models::{Group, ProxyModel, SlotMapping};
+    use crate::proxy::backend::Backend;
+    use crate::proxy::config::ConfigHandle;
+    use crate::proxy::router::DefaultRouter;
+
+    const TOPOLOGY: &str = r#"
+        [[groups]]
+        id = 1
+        master = "127.0.0.1:6379"
+
+        [[groups]]
+        id = 2
+        master = "127.0.0.1:6380"
+
+        [[slots]]
+        begin = 0
+        end = 1023
+        group_id = 1
+    "#;
+
+    #[tokio::test]
+    async fn test_registry_watcher() {
+        let path = std::env::temp_dir().join(format!("watcher-{}.toml", std::process::id()));
+        std::fs::write(&path, TOPOLOGY).unwrap();
+        let adapter = FileAdapter::new(&path, Duration::from_millis(10)).unwrap();
+        let registry = Arc::new(Registry::new(Box::new(adapter), ""));
+        let config = ConfigHandle::default();
+        let backend = Arc::new(Backend::new(config.clone()));
+        let router: Arc<dyn Router> = Arc::new(DefaultRouter::new(config, backend));
+        registry.apply(router.as_ref()).await.unwrap();
+        assert_eq!(router.get_slot(1).backend_addr, "127.0.0.1:6379");
+
+        let cancel = CancellationToken::new();
+        let period = Duration::from_secs(60);
+        registry
+            .clone()
+            .spawn_watcher(router.clone(), Some(period), cancel.clone());
+        let wait = |id: u64, addr: &'static str| {
+            let router = router.clone();
+            async move {
+                while router.get_slot(id).backend_addr != addr {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            }
+        };
+
+        // a slot moved to another group is filled
+        let moved = TOPOLOGY.replace("end = 1023", "end = 1022")
+            + "[[slots]]\nbegin = 1023\nend = 1023\ngroup_id = 2\n";
+        std::fs::write(&path, &moved).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), wait(1023, "127.0.0.1:6380"))
+            .await
+            .unwrap();
+        assert!(!router.has_switched());
+
+        // a new master of a group is filled, it's not switched by sentinel
+        std::fs::write(&path, moved.replace("6379", "6381")).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), wait(1, "127.0.0.1:6381"))
+            .await
+            .unwrap();
+        assert!(!router.has_switched());
+        assert_eq!(router.get_slot(1023).backend_addr, "127.0.0.1:6380");
+
+        cancel.cancel();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// FlakyAdapter fails the first watch.
+    struct FlakyAdapter {
+        inner: FileAdapter,
+        failed: AtomicBool,
+    }
+
+    #[async_trait]
+    impl RegistryAdapter for FlakyAdapter {
+        async fn register(&self, proxy: &ProxyModel) -> Result<()> {
+            self.inner.register(proxy).await
+        }
+
+        async fn heartbeat(&self) -> Result<()> {
+            self.inner.heartbeat().await
+        }
+
+        async fn unregister(&self) -> Result<()> {
+            self.inner.unregister().await
+        }
+
+        async fn load_slots(&self) -> Result<Vec<SlotMapping>> {
+            self.inner.load_slots().await
+        }
+
+        async fn load_groups(&self) -> Result<Vec<Group>> {
+            self.inner.load_groups().await
+        }
+
+        async fn watch(&self) -> Result<Receiver<TopologyChange>> {
+            if !self.failed.swap(true, Ordering::SeqCst) {
+                return Err(Error::network(anyhow!("refused")));
+            }
+            self.inner.watch().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_watch_retried_without_reconcile() {
+        let path = std::env::temp_dir().join(format!("flaky-{}.toml", std::process::id()));
+        std::fs::write(&path, TOPOLOGY).unwrap();
+        let adapter = FlakyAdapter {
+            inner: FileAdapter::new(&path, Duration::from_millis(10)).unwrap(),
+            failed: Default::default(),
+        };
+        let registry = Arc::new(Registry::new(Box::new(adapter), ""));
+        let config = ConfigHandle::default();
+        let backend = Arc::new(Backend::new(config.clone()));
+        let router: Arc<dyn Router> = Arc::new(DefaultRouter::new(config, backend));
+        let cancel = CancellationToken::new();
+        registry
+            .clone()
+            .spawn_watcher(router.clone(), None, cancel.clone());
+
+        let wait = |addr: &'static str| {
+            let router = router.clone();
+            async move {
+                while router.get_slot(1).backend_addr != addr {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), wait("127.0.0.1:6379"))
+            .await
+            .unwrap();
+        std::fs::write(&path, TOPOLOGY.replace("group_id = 1", "group_id = 2")).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), wait("127.0.0.1:6380"))
+            .await
+            .unwrap();
+
+        cancel.cancel();
+        std::fs::remove_file(&path).unwrap();
+    }
 }
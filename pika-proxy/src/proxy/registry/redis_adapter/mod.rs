@@ -1 +1,332 @@
+use std::time::Duration;
 
+use anyhow::anyhow;
+use async_trait::async_trait;
+use redis::{Frame, RedisCmd, RedisResp};
+use serde::de::DeserializeOwned;
+use tokio::sync::mpsc::{channel, Receiver};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::error::{Error, Result};
+use crate::models::{CodisPaths, Group, ProxyModel, SlotMapping};
+use crate::proxy::registry::registry_adapter::{decode, RegistryAdapter, TopologyChange};
+use crate::utils::redis::RedisConnection;
+use crate::utils::time::with_timeout;
+
+/// RedisAdapter stores the codis models in a plain redis or pika server:
+///
+/// | key                               | type   | content                    |
+/// |-----------------------------------|--------|----------------------------|
+/// | `/codis3/<product>/slots`         | hash   | slot id -> `SlotMapping`   |
+/// | `/codis3/<product>/group`         | hash   | group id -> `Group`        |
+/// | `/codis3/<product>/proxy`         | hash   | token -> `ProxyModel`      |
+/// | `/codis3/<product>/proxy/proxy-x` | string | liveness of proxy x, TTL'd |
+///
+/// Changes of the slots and groups are watched by keyspace notifications of
+/// the hashes (`notify-keyspace-events Kh`), or by the key of the modified
+/// hash published to `/codis3/<product>/topology` for servers without them.
+pub struct RedisAdapter {
+    addr: String,
+    auth: String,
+    timeout: Duration,
+    paths: CodisPaths,
+    ttl: Duration,
+    conn: Mutex<Option<RedisConnection>>,
+    registered: Mutex<Option<ProxyModel>>,
+}
+
+impl RedisAdapter {
+    /// Connects to the redis server at `addr`, `auth` is `password`,
+    /// `user:password` or empty.
+    pub async fn connect(
+        addr: &str,
+        auth: &str,
+        timeout: Duration,
+        product_name: &str,
+        ttl: Duration,
+    ) -> Result<Self> {
+        let conn = RedisConnection::open(addr, auth, timeout).await?;
+        Ok(Self {
+            addr: addr.to_string(),
+            auth: auth.to_string(),
+            timeout,
+            paths: CodisPaths::new(product_name),
+            ttl: ttl.max(Duration::from_millis(1)),
+            conn: Mutex::new(Some(conn)),
+            registered: Mutex::new(None),
+        })
+    }
+
+    /// Runs a command, the connection is reopened on the next call if it's
+    /// broken.
+    async fn call(&self, args: &[&str]) -> Result<RedisResp> {
+        let cmd = RedisCmd::new(args.iter().map(|arg| arg.to_string()));
+        let mut conn = self.conn.lock().await;
+        if conn.is_none() {
+            *conn = Some(RedisConnection::open(&self.addr, &self.auth, self.timeout).await?);
+        }
+        let resp = match conn.as_mut().unwrap().call(&cmd, self.timeout).await {
+            Ok(resp) => resp,
+            Err(e) => {
+                *conn = None;
+                return Err(e);
+            }
+        };
+        match resp.error_message() {
+            Some(msg) => Err(Error::server(anyhow!(
+                "registry {} rejected {}: {}",
+                self.addr,
+                cmd.name(),
+                msg
+            ))),
+            None => Ok(resp),
+        }
+    }
+
+    async fn put_proxy(&self, proxy: &ProxyModel) -> Result<()> {
+        let value = serde_json::to_string(proxy).map_err(Error::server)?;
+        let ttl = self.ttl.as_millis().to_string();
+        self.call(&["HSET", &self.paths.proxy_dir(), &proxy.token, &value])
+            .await?;
+        let alive = self.paths.proxy_path(&proxy.token);
+        self.call(&["SET", &alive, &proxy.token, "PX", &ttl])
+            .await?;
+        Ok(())
+    }
+
+    async fn load_hash<T: DeserializeOwned>(&self, key: String) -> Result<Vec<T>> {
+        let resp = self.call(&["HVALS", &key]).await?;
+        let Frame::Array(values) = resp.frame() else {
+            return Err(Error::protocol(anyhow!("array reply is expected")));
+        };
+        values
+            .iter()
+            .map(|value| match value {
+                Frame::BulkString(value) => decode(&key, value),
+                _ => Err(Error::protocol(anyhow!("bulk reply is expected"))),
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl RegistryAdapter for RedisAdapter {
+    async fn register(&self, proxy: &ProxyModel) -> Result<()> {
+        let mut registered = self.registered.lock().await;
+        self.put_proxy(proxy).await?;
+        *registered = Some(proxy.clone());
+        Ok(())
+    }
+
+    async fn heartbeat(&self) -> Result<()> {
+        let registered = self.registered.lock().await;
+        let Some(proxy) = registered.as_ref() else {
+            return Err(Error::server(anyhow!("proxy is not registered")));
+        };
+        let alive = self.paths.proxy_path(&proxy.token);
+        let ttl = self.ttl.as_millis().to_string();
+        // XX only refreshes a key which is not expired yet
+        let resp = self
+            .call(&["SET", &alive, &proxy.token, "PX", &ttl, "XX"])
+            .await?;
+        if resp.frame() == &Frame::Null {
+            warn!("proxy {} is expired, register again", proxy.token);
+            self.put_proxy(proxy).await?;
+        }
+        Ok(())
+    }
+
+    async fn unregister(&self) -> Result<()> {
+        let Some(proxy) = self.registered.lock().await.take() else {
+            return Ok(());
+        };
+        self.call(&["DEL", &self.paths.proxy_path(&proxy.token)])
+            .await?;
+        self.call(&["HDEL", &self.paths.proxy_dir(), &proxy.token])
+            .await?;
+        Ok(())
+    }
+
+    async fn load_slots(&self) -> Result<Vec<SlotMapping>> {
+        let mut slots: Vec<SlotMapping> = self.load_hash(self.paths.slot_dir()).await?;
+        slots.sort_by_key(|slot| slot.id);
+        Ok(slots)
+    }
+
+    async fn load_groups(&self) -> Result<Vec<Group>> {
+        let mut groups: Vec<Group> = self.load_hash(self.paths.group_dir()).await?;
+        groups.sort_by_key(|group| group.id);
+        Ok(groups)
+    }
+
+    async fn watch(&self) -> Result<Receiver<TopologyChange>> {
+        let mut conn = RedisConnection::open(&self.addr, &self.auth, self.timeout).await?;
+        let paths = self.paths.clone();
+        let channels = [
+            keyspace_channel(&paths.slot_dir()),
+            keyspace_channel(&paths.group_dir()),
+            topology_channel(&paths),
+        ];
+        let mut cmd = vec!["SUBSCRIBE".to_string()];
+        cmd.extend(channels.iter().cloned());
+        conn.requester.send_request(&RedisCmd::new(cmd)).await?;
+        // changes made after watch returns are not missed once all the
+        // channels are subscribed
+        for _ in &channels {
+            let reader = &mut conn.reader;
+            let resp =
+                with_timeout(self.timeout, async { Ok(reader.read_response().await?) }).await?;
+            if let Some(msg) = resp.error_message() {
+                return Err(Error::server(anyhow!(
+                    "registry {} rejected SUBSCRIBE: {}",
+                    self.addr,
+                    msg
+                )));
+            }
+        }
+        let (tx, rx) = channel(16);
+        tokio::spawn(async move {
+            loop {
+                let resp = tokio::select! {
+                    resp = conn.reader.read_response() => resp,
+                    _ = tx.closed() => return,
+                };
+                let resp = match resp {
+                    Ok(resp) => resp,
+                    Err(e) => {
+                        warn!("watch redis registry failed: {}", e);
+                        return;
+                    }
+                };
+                let Some(change) = message_change(&paths, &resp) else {
+                    continue;
+                };
+                if tx.send(change).await.is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(rx)
+    }
+}
+
+fn keyspace_channel(key: &str) -> String {
+    format!("__keyspace@0__:{}", key)
+}
+
+fn topology_channel(paths: &CodisPaths) -> String {
+    format!("{}/topology", paths.product_dir())
+}
+
+/// Returns the part of the topology changed by a pushed message, which is a
+/// keyspace notification of a hash, or a hash key published to the topology
+/// channel.
+fn message_change(paths: &CodisPaths, resp: &RedisResp) -> Option<TopologyChange> {
+    let Frame::Array(items) = resp.frame() else {
+        return None;
+    };
+    let [Frame::BulkString(kind), Frame::BulkString(channel), Frame::BulkString(payload)] =
+        items.as_slice()
+    else {
+        return None;
+    };
+    if !kind.eq_ignore_ascii_case(b"message") {
+        return None;
+    }
+    let channel = std::str::from_utf8(channel).ok()?;
+    let key = match channel.strip_prefix("__keyspace@0__:") {
+        Some(key) => key,
+        None if channel == topology_channel(paths) => std::str::from_utf8(payload).ok()?,
+        None => return None,
+    };
+    if key == paths.slot_dir() {
+        Some(TopologyChange::Slots)
+    } else if key == paths.group_dir() {
+        Some(TopologyChange::Groups)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(channel: &str, payload: &str) -> RedisResp {
+        RedisResp::array(vec![
+            RedisResp::bulk("message"),
+            RedisResp::bulk(channel.to_string()),
+            RedisResp::bulk(payload.to_string()),
+        ])
+    }
+
+    #[test]
+    fn test_message_change() {
+        let paths = CodisPaths::new("demo");
+        let keyspace = keyspace_channel(&paths.slot_dir());
+        assert_eq!(
+            message_change(&paths, &message(&keyspace, "hset")),
+            Some(TopologyChange::Slots)
+        );
+        let topology = topology_channel(&paths);
+        assert_eq!(
+            message_change(&paths, &message(&topology, &paths.group_dir())),
+            Some(TopologyChange::Groups)
+        );
+        assert_eq!(
+            message_change(&paths, &message(&topology, &paths.proxy_dir())),
+            None
+        );
+        assert_eq!(message_change(&paths, &message("other", "x")), None);
+    }
+
+    /// Runs against a local redis, such as `redis-server --port 6379`.
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_redis_adapter() {
+        let timeout = Duration::from_secs(3);
+        let ttl = Duration::from_secs(5);
+        let product = format!("pika-proxy-test-{}", std::process::id());
+        let adapter = RedisAdapter::connect("127.0.0.1:6379", "", timeout, &product, ttl)
+            .await
+            .unwrap();
+        let mut changes = adapter.watch().await.unwrap();
+
+        let paths = CodisPaths::new(&product);
+        let slot = SlotMapping {
+            id: 1,
+            group_id: 2,
+            ..Default::default()
+        };
+        let value = serde_json::to_string(&slot).unwrap();
+        adapter
+            .call(&["HSET", &paths.slot_dir(), "1", &value])
+            .await
+            .unwrap();
+        let topology = topology_channel(&paths);
+        adapter
+            .call(&["PUBLISH", &topology, &paths.slot_dir()])
+            .await
+            .unwrap();
+        assert_eq!(changes.recv().await, Some(TopologyChange::Slots));
+        assert_eq!(adapter.load_slots().await.unwrap(), vec![slot]);
+
+        let proxy = ProxyModel {
+            token: "token".to_string(),
+            product_name: product.clone(),
+            ..Default::default()
+        };
+        adapter.register(&proxy).await.unwrap();
+        let alive = paths.proxy_path("token");
+        adapter.call(&["DEL", &alive]).await.unwrap();
+        adapter.heartbeat().await.unwrap();
+        let resp = adapter.call(&["EXISTS", &alive]).await.unwrap();
+        assert_eq!(resp, RedisResp::integer(1));
+        adapter.unregister().await.unwrap();
+        let resp = adapter.call(&["HLEN", &paths.proxy_dir()]).await.unwrap();
+        assert_eq!(resp, RedisResp::integer(0));
+
+        adapter.call(&["DEL", &paths.slot_dir()]).await.unwrap();
+    }
+}
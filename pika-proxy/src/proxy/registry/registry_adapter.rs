@@ -1,4 +1,79 @@
+use anyhow::anyhow;
 use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use tokio::sync::mpsc::Receiver;
 
+use crate::error::{Error, Result};
+use crate::models::{CodisPaths, Group, ProxyModel, SlotMapping};
+
+/// TopologyChange tells which part of the topology is modified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopologyChange {
+    Slots,
+    Groups,
+}
+
+/// RegistryAdapter is the store of the codis models, such as etcd.
 #[async_trait]
-pub trait RegistryAdapter {}
+pub trait RegistryAdapter: Send + Sync {
+    /// Registers this proxy, the registration expires unless `heartbeat` is
+    /// called in time.
+    async fn register(&self, proxy: &ProxyModel) -> Result<()>;
+
+    /// Keeps the registration alive, the proxy is registered again if it has
+    /// expired.
+    async fn heartbeat(&self) -> Result<()>;
+
+    async fn unregister(&self) -> Result<()>;
+
+    /// Loads all slot mappings, sorted by id.
+    async fn load_slots(&self) -> Result<Vec<SlotMapping>>;
+
+    /// Loads all groups, sorted by id.
+    async fn load_groups(&self) -> Result<Vec<Group>>;
+
+    /// Watches the slot mappings and groups, the watch stops once the
+    /// receiver is dropped, or the receiver is closed if it's broken.
+    async fn watch(&self) -> Result<Receiver<TopologyChange>>;
+}
+
+/// Decodes a codis model stored as json at `key`.
+pub(super) fn decode<T: DeserializeOwned>(key: &str, value: &[u8]) -> Result<T> {
+    serde_json::from_slice(value)
+        .map_err(|e| Error::server(anyhow!("invalid model at {}: {}", key, e)))
+}
+
+/// Returns the part of the topology stored at `key`.
+pub(super) fn topology_change(paths: &CodisPaths, key: &str) -> Option<TopologyChange> {
+    if key.starts_with(&format!("{}/", paths.slot_dir())) {
+        Some(TopologyChange::Slots)
+    } else if key.starts_with(&format!("{}/", paths.group_dir())) {
+        Some(TopologyChange::Groups)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topology_change() {
+        let paths = CodisPaths::new("demo");
+        assert_eq!(
+            topology_change(&paths, &paths.slot_path(1)),
+            Some(TopologyChange::Slots)
+        );
+        assert_eq!(
+            topology_change(&paths, &paths.group_path(1)),
+            Some(TopologyChange::Groups)
+        );
+        assert_eq!(topology_change(&paths, &paths.proxy_path("t")), None);
+        assert_eq!(topology_change(&paths, "/codis3/demo/slotsx/a"), None);
+
+        let slot: SlotMapping = decode("k", br#"{"id":1,"group_id":2}"#).unwrap();
+        assert_eq!(slot.group_id, 2);
+        assert!(decode::<SlotMapping>("k", b"{").is_err());
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use hyper::Client;
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use super::{encode_query, http_url, post, REPORT_TIMEOUT};
+use crate::proxy::server::proxy_metrics::ProxyMetrics;
+use crate::utils::histogram::Percentiles;
+use crate::utils::time::with_timeout;
+
+/// batches kept while influxdb is unavailable, the oldest ones are dropped
+const MAX_PENDING_BATCHES: usize = 60;
+
+/// InfluxdbOptions are the `report_influxdb_*` of `[metrics]`, `tags` are
+/// added to every point.
+#[derive(Debug, Clone, Default)]
+pub struct InfluxdbOptions {
+    pub server: String,
+    pub period: Duration,
+    pub username: String,
+    pub password: String,
+    pub database: String,
+    pub tags: Vec<(String, String)>,
+}
+
+impl InfluxdbOptions {
+    fn write_url(&self) -> String {
+        let mut url = format!(
+            "{}/write?db={}",
+            http_url(self.server.trim_end_matches('/')),
+            encode_query(&self.database)
+        );
+        if !self.username.is_empty() {
+            let _ = write!(
+                url,
+                "&u={}&p={}",
+                encode_query(&self.username),
+                encode_query(&self.password)
+            );
+        }
+        url
+    }
+}
+
+/// Writes the metrics to influxdb every period in the line protocol. A
+/// batch failed to be written is retried together with the next one, so
+/// points are kept with the time they were taken.
+pub fn spawn_influxdb_reporter(
+    options: InfluxdbOptions,
+    metrics: Arc<ProxyMetrics>,
+    closed: CancellationToken,
+) {
+    let url = options.write_url();
+    let timeout = options.period.min(REPORT_TIMEOUT);
+    info!(
+        "report metrics to influxdb {} every {:?}",
+        options.server, options.period
+    );
+    tokio::spawn(async move {
+        let client = Client::new();
+        let mut interval = tokio::time::interval(options.period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let mut pending = VecDeque::new();
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = closed.cancelled() => return,
+            }
+            if pending.len() >= MAX_PENDING_BATCHES {
+                pending.pop_front();
+            }
+            pending.push_back(lines(&metrics, &options.tags, SystemTime::now()));
+            let body = pending.iter().map(String::as_str).collect::<String>();
+            let write = post(&client, &url, "text/plain; charset=utf-8", body);
+            let written = tokio::select! {
+                written = with_timeout(timeout, write) => written,
+                _ = closed.cancelled() => return,
+            };
+            match written {
+                Ok(()) => pending.clear(),
+                Err(e) => warn!(
+                    "write {} batches to influxdb {} failed: {}",
+                    pending.len(),
+                    options.server,
+                    e
+                ),
+            }
+        }
+    });
+}
+
+/// Encodes a snapshot of the metrics, the proxy is measured as `pika_proxy`,
+/// commands as `pika_proxy_command`, and backends as `pika_proxy_backend`.
+fn lines(metrics: &ProxyMetrics, tags: &[(String, String)], now: SystemTime) -> String {
+    let timestamp = now
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let tags = tags
+        .iter()
+        .map(|(key, value)| format!(",{}={}", escape(key), escape(value)))
+        .collect::<String>();
+    let ops = metrics.ops();
+    let sessions = metrics.sessions();
+    let offheap = metrics.offheap_snapshot();
+    let latency = metrics.latency();
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "pika_proxy{} ops_total={}i,ops_fails={}i,ops_redis_errors={}i,ops_qps={}i,sessions_total={}i,sessions_alive={}i,sessions_output_buffer_evicted={}i,offheap_bytes={}i,oom_rejected={}i,oom_evicted={}i {}",
+        tags, ops.total, ops.fails, ops.redis.errors, ops.qps, sessions.total, sessions.alive, sessions.output_buffer_evicted, offheap.used, offheap.rejected, offheap.evicted, timestamp
+    );
+    for cmd in &ops.cmd {
+        let percentiles = latency
+            .commands
+            .get(&cmd.opstr)
+            .copied()
+            .unwrap_or_default();
+        let _ = writeln!(
+            out,
+            "pika_proxy_command{},command={} calls={}i,usecs={}i,usecs_percall={}i,fails={}i,redis_errors={}i,{} {}",
+            tags,
+            escape(&cmd.opstr),
+            cmd.calls,
+            cmd.usecs,
+            cmd.usecs_percall,
+            cmd.fails,
+            cmd.redis_errtype,
+            fields(&percentiles),
+            timestamp
+        );
+    }
+    for (addr, percentiles) in &latency.backends {
+        let _ = writeln!(
+            out,
+            "pika_proxy_backend{},addr={} {} {}",
+            tags,
+            escape(addr),
+            fields(percentiles),
+            timestamp
+        );
+    }
+    out
+}
+
+fn fields(percentiles: &Percentiles) -> String {
+    format!(
+        "p50={}i,p90={}i,p99={}i,p999={}i,max={}i",
+        percentiles.p50, percentiles.p90, percentiles.p99, percentiles.p999, percentiles.max
+    )
+}
+
+/// Escapes a tag key or value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::extract::Query;
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use axum::Router;
+    use redis::RedisResp;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::models::get_command;
+
+    #[test]
+    fn test_lines() {
+        let metrics = ProxyMetrics::default();
+        let ms = Duration::from_millis(1);
+        metrics.record(get_command("GET"), ms, &Ok(RedisResp::ok()));
+        metrics.backend_latency("127.0.0.1:6379").record(100);
+        let tags = vec![("product_name".to_string(), "my demo".to_string())];
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1);
+        let text = lines(&metrics, &tags, now);
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[0],
+            r"pika_proxy,product_name=my\ demo ops_total=1i,ops_fails=0i,ops_redis_errors=0i,ops_qps=0i,sessions_total=0i,sessions_alive=0i,sessions_output_buffer_evicted=0i,offheap_bytes=0i,oom_rejected=0i,oom_evicted=0i 1000000000"
+        );
+        assert_eq!(
+            lines[1],
+            r"pika_proxy_command,product_name=my\ demo,command=GET calls=1i,usecs=1000i,usecs_percall=1000i,fails=0i,redis_errors=0i,p50=1000i,p90=1000i,p99=1000i,p999=1000i,max=1000i 1000000000"
+        );
+        assert!(lines[2].starts_with(
+            r"pika_proxy_backend,product_name=my\ demo,addr=127.0.0.1:6379 p50=100i,"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_influxdb_reporter() {
+        // the first write fails, so it's retried with the second one
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let writes = Arc::new(AtomicUsize::new(0));
+        let app = Router::new().route(
+            "/write",
+            post(
+                move |Query(query): Query<HashMap<String, String>>, body: String| async move {
+                    if writes.fetch_add(1, Ordering::SeqCst) == 0 {
+                        return StatusCode::INTERNAL_SERVER_ERROR;
+                    }
+                    tx.send((query, body)).unwrap();
+                    StatusCode::NO_CONTENT
+                },
+            ),
+        );
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let options = InfluxdbOptions {
+            server: addr.to_string(),
+            period: Duration::from_millis(50),
+            username: "user".to_string(),
+            password: "p&ss".to_string(),
+            database: "proxy".to_string(),
+            tags: vec![("product_name".to_string(), "demo".to_string())],
+        };
+        let closed = CancellationToken::new();
+        spawn_influxdb_reporter(options, Arc::default(), closed.clone());
+        let (query, body) = rx.recv().await.unwrap();
+        closed.cancel();
+        assert_eq!(query["db"], "proxy");
+        assert_eq!(query["u"], "user");
+        assert_eq!(query["p"], "p&ss");
+        let points = body
+            .lines()
+            .filter(|line| line.starts_with("pika_proxy,product_name=demo "))
+            .collect::<Vec<_>>();
+        assert_eq!(points.len(), 2);
+        assert_ne!(points[0], points[1]);
+    }
+}
@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use hyper::Client;
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use super::{http_url, post, REPORT_TIMEOUT};
+use crate::utils::time::with_timeout;
+
+/// Posts the json of `snapshot` to `server` every `period`. Reports are
+/// sent one by one, and the ticks missed by a slow server are skipped
+/// rather than piled up.
+pub fn spawn_json_reporter<F>(
+    server: &str,
+    period: Duration,
+    snapshot: F,
+    closed: CancellationToken,
+) where
+    F: Fn() -> serde_json::Value + Send + 'static,
+{
+    let url = http_url(server);
+    let timeout = period.min(REPORT_TIMEOUT);
+    info!("report json metrics to {} every {:?}", url, period);
+    tokio::spawn(async move {
+        let client = Client::new();
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = closed.cancelled() => return,
+            }
+            let body = snapshot().to_string();
+            let report = tokio::select! {
+                report = with_timeout(timeout, post(&client, &url, "application/json", body)) => report,
+                _ = closed.cancelled() => return,
+            };
+            if let Err(e) = report {
+                warn!("report json metrics to {} failed: {}", url, e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    use axum::routing::post;
+    use axum::Router;
+    use serde_json::json;
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_json_reporter() {
+        // the receiver is slower than the period
+        let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+        let app = Router::new().route(
+            "/report",
+            post(move |body: String| async move {
+                tx.send(body).unwrap();
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                "OK"
+            }),
+        );
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let reports = Arc::new(AtomicU64::new(0));
+        let snapshot = {
+            let reports = reports.clone();
+            move || json!({ "n": reports.fetch_add(1, Ordering::SeqCst) })
+        };
+        let closed = CancellationToken::new();
+        let url = format!("{}/report", addr);
+        spawn_json_reporter(&url, Duration::from_millis(10), snapshot, closed.clone());
+        for n in 0..3 {
+            let body = rx.recv().await.unwrap();
+            assert_eq!(body, json!({ "n": n }).to_string());
+        }
+        closed.cancel();
+        // one report at a time, so the slow receiver is not piled up
+        assert!(reports.load(Ordering::SeqCst) <= 4);
+    }
+}
@@ -0,0 +1,125 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use hyper::client::HttpConnector;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Client, Method, Request};
+use serde_json::json;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use crate::error::{Error, Result};
+use crate::proxy::config::{ConfigHandle, MetricsConfig};
+use crate::proxy::dashboard::proxy_api::ProxyApi;
+
+mod influxdb;
+mod json;
+mod statsd;
+
+pub use influxdb::{spawn_influxdb_reporter, InfluxdbOptions};
+pub use json::spawn_json_reporter;
+pub use statsd::spawn_statsd_reporter;
+
+/// a report is given up after this, or the period if it's shorter
+const REPORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Spawns the reporters configured in `[metrics]`, they stop once `closed`
+/// is cancelled. They are restarted once `[metrics]` is reloaded.
+pub fn spawn_reporters(config: ConfigHandle, api: Arc<ProxyApi>, closed: CancellationToken) {
+    tokio::spawn(async move {
+        let mut changed = config.subscribe();
+        loop {
+            let metrics = config.load().metrics.clone();
+            let running = closed.child_token();
+            start_reporters(&metrics, api.clone(), running.clone());
+            while config.load().metrics == metrics {
+                tokio::select! {
+                    reloaded = changed.changed() => if reloaded.is_err() {
+                        return;
+                    },
+                    _ = closed.cancelled() => return,
+                }
+            }
+            info!("restart reporters for the config reloaded");
+            running.cancel();
+        }
+    });
+}
+
+fn start_reporters(metrics: &MetricsConfig, api: Arc<ProxyApi>, closed: CancellationToken) {
+    if !metrics.report_influxdb_server.is_empty() && !metrics.report_influxdb_period.is_zero() {
+        let options = InfluxdbOptions {
+            server: metrics.report_influxdb_server.clone(),
+            period: metrics.report_influxdb_period,
+            username: metrics.report_influxdb_username.clone(),
+            password: metrics.report_influxdb_password.clone(),
+            database: metrics.report_influxdb_database.clone(),
+            tags: vec![
+                ("product_name".to_string(), api.model.product_name.clone()),
+                ("token".to_string(), api.model.token.clone()),
+                ("proxy_addr".to_string(), api.model.proxy_addr.clone()),
+            ],
+        };
+        spawn_influxdb_reporter(options, api.metrics.clone(), closed.clone());
+    }
+    if !metrics.report_statsd_server.is_empty() && !metrics.report_statsd_period.is_zero() {
+        spawn_statsd_reporter(
+            &metrics.report_statsd_server,
+            metrics.report_statsd_period,
+            &metrics.report_statsd_prefix,
+            api.metrics.clone(),
+            closed.clone(),
+        );
+    }
+    if !metrics.report_server.is_empty() && !metrics.report_period.is_zero() {
+        let snapshot = move || json!({ "model": &api.model, "stats": api.stats() });
+        spawn_json_reporter(
+            &metrics.report_server,
+            metrics.report_period,
+            snapshot,
+            closed,
+        );
+    }
+}
+
+/// Servers may be configured without the scheme, such as `localhost:8086`.
+fn http_url(server: &str) -> String {
+    if server.contains("://") {
+        server.to_string()
+    } else {
+        format!("http://{}", server)
+    }
+}
+
+/// Percent-encodes a query parameter.
+fn encode_query(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+async fn post(
+    client: &Client<HttpConnector>,
+    url: &str,
+    content_type: &str,
+    body: String,
+) -> Result<()> {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(url)
+        .header(CONTENT_TYPE, content_type)
+        .body(Body::from(body))
+        .map_err(Error::network)?;
+    let response = client.request(request).await.map_err(Error::network)?;
+    if !response.status().is_success() {
+        return Err(Error::server(anyhow!("status {}", response.status())));
+    }
+    Ok(())
+}
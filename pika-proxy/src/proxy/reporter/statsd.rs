@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use tokio::net::UdpSocket;
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::error::{Error, Result};
+use crate::proxy::server::proxy_metrics::ProxyMetrics;
+
+/// packets are kept below the mtu of most networks
+const MAX_PACKET_SIZE: usize = 1432;
+
+/// Sends the metrics to statsd over udp every `period`. Totals are sent as
+/// counters of the increase since the last report, and the others as
+/// gauges, all named under `prefix`.
+pub fn spawn_statsd_reporter(
+    server: &str,
+    period: Duration,
+    prefix: &str,
+    metrics: Arc<ProxyMetrics>,
+    closed: CancellationToken,
+) {
+    let server = server.to_string();
+    let mut statsd = Statsd::new(prefix);
+    info!("report metrics to statsd {} every {:?}", server, period);
+    tokio::spawn(async move {
+        let mut socket = None;
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = closed.cancelled() => return,
+            }
+            // counters are taken even if they can't be sent, or the next
+            // report would count them twice
+            let packets = statsd.packets(&metrics);
+            if socket.is_none() {
+                match connect(&server).await {
+                    Ok(connected) => socket = Some(connected),
+                    Err(e) => {
+                        warn!("connect to statsd {} failed: {}", server, e);
+                        continue;
+                    }
+                }
+            }
+            for packet in packets {
+                if let Err(e) = socket.as_ref().unwrap().send(packet.as_bytes()).await {
+                    warn!("report metrics to statsd {} failed: {}", server, e);
+                    // resolved again by the next report
+                    socket = None;
+                    break;
+                }
+            }
+        }
+    });
+}
+
+async fn connect(server: &str) -> Result<UdpSocket> {
+    let addr = tokio::net::lookup_host(server)
+        .await
+        .map_err(Error::network)?
+        .next()
+        .ok_or_else(|| Error::network(anyhow!("{} is not resolved", server)))?;
+    let local = if addr.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(local).await.map_err(Error::network)?;
+    socket.connect(addr).await.map_err(Error::network)?;
+    Ok(socket)
+}
+
+/// Statsd remembers the totals reported, so the counters are increases.
+struct Statsd {
+    prefix: String,
+    last: HashMap<String, u64>,
+}
+
+impl Statsd {
+    fn new(prefix: &str) -> Self {
+        let prefix = prefix.trim_end_matches('.');
+        Self {
+            prefix: if prefix.is_empty() {
+                String::new()
+            } else {
+                format!("{}.", prefix)
+            },
+            last: HashMap::new(),
+        }
+    }
+
+    fn packets(&mut self, metrics: &ProxyMetrics) -> Vec<String> {
+        let ops = metrics.ops();
+        let sessions = metrics.sessions();
+        let offheap = metrics.offheap_snapshot();
+        let latency = metrics.latency();
+        let mut lines = vec![];
+        let mut counter = |name: String, total: u64| {
+            let last = self.last.insert(name.clone(), total).unwrap_or_default();
+            let line = format!("{}{}:{}|c", self.prefix, name, total.saturating_sub(last));
+            lines.push(line);
+        };
+        counter("ops.total".to_string(), ops.total);
+        counter("ops.fails".to_string(), ops.fails);
+        counter("ops.redis_errors".to_string(), ops.redis.errors);
+        counter("sessions.total".to_string(), sessions.total);
+        counter(
+            "sessions.output_buffer_evicted".to_string(),
+            sessions.output_buffer_evicted,
+        );
+        counter("offheap.rejected".to_string(), offheap.rejected);
+        counter("offheap.evicted".to_string(), offheap.evicted);
+        for cmd in &ops.cmd {
+            counter(format!("cmd.{}.calls", cmd.opstr), cmd.calls);
+            counter(format!("cmd.{}.fails", cmd.opstr), cmd.fails);
+            counter(format!("cmd.{}.redis_errors", cmd.opstr), cmd.redis_errtype);
+        }
+
+        let mut gauge =
+            |name: String, value: u64| lines.push(format!("{}{}:{}|g", self.prefix, name, value));
+        gauge("ops.qps".to_string(), ops.qps);
+        gauge("sessions.alive".to_string(), sessions.alive);
+        gauge("offheap.bytes".to_string(), offheap.used);
+        let groups =
+            latency
+                .commands
+                .iter()
+                .map(|(command, percentiles)| (format!("cmd.{}", command), percentiles))
+                .chain(latency.backends.iter().map(|(addr, percentiles)| {
+                    (format!("backend.{}", sanitize(addr)), percentiles)
+                }));
+        for (name, percentiles) in groups {
+            gauge(format!("{}.p50_usecs", name), percentiles.p50);
+            gauge(format!("{}.p99_usecs", name), percentiles.p99);
+            gauge(format!("{}.p999_usecs", name), percentiles.p999);
+            gauge(format!("{}.max_usecs", name), percentiles.max);
+        }
+        pack(lines)
+    }
+}
+
+/// Addresses are named with `_` for the characters special to statsd.
+fn sanitize(name: &str) -> String {
+    name.replace(['.', ':', '|', '@', '/'], "_")
+}
+
+/// Joins lines into packets by newlines.
+fn pack(lines: Vec<String>) -> Vec<String> {
+    let mut packets: Vec<String> = vec![];
+    for line in lines {
+        match packets.last_mut() {
+            Some(packet) if packet.len() + 1 + line.len() <= MAX_PACKET_SIZE => {
+                packet.push('\n');
+                packet.push_str(&line);
+            }
+            _ => packets.push(line),
+        }
+    }
+    packets
+}
+
+#[cfg(test)]
+mod tests {
+    use redis::RedisResp;
+
+    use super::*;
+    use crate::models::get_command;
+
+    #[test]
+    fn test_pack() {
+        let lines = (0..100)
+            .map(|i| format!("{:030}:1|c", i))
+            .collect::<Vec<_>>();
+        let packets = pack(lines);
+        assert!(packets.len() > 1);
+        assert!(packets.iter().all(|packet| packet.len() <= MAX_PACKET_SIZE));
+        assert_eq!(
+            packets.iter().map(|p| p.lines().count()).sum::<usize>(),
+            100
+        );
+    }
+
+    #[tokio::test]
+    async fn test_statsd_reporter() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = receiver.local_addr().unwrap().to_string();
+        let metrics = Arc::new(ProxyMetrics::default());
+        let ms = Duration::from_millis(1);
+        metrics.record(get_command("GET"), ms, &Ok(RedisResp::ok()));
+        let _backend = metrics.backend_latency("127.0.0.1:6379");
+        let closed = CancellationToken::new();
+        let period = Duration::from_millis(20);
+        spawn_statsd_reporter(&addr, period, "demo.", metrics.clone(), closed.clone());
+
+        let mut buf = vec![0; MAX_PACKET_SIZE];
+        let n = receiver.recv(&mut buf).await.unwrap();
+        let packet = String::from_utf8_lossy(&buf[..n]).into_owned();
+        let lines = packet.lines().collect::<Vec<_>>();
+        for expected in [
+            "demo.ops.total:1|c",
+            "demo.cmd.GET.calls:1|c",
+            "demo.sessions.alive:0|g",
+            "demo.cmd.GET.max_usecs:1000|g",
+            "demo.backend.127_0_0_1_6379.max_usecs:0|g",
+        ] {
+            assert!(
+                lines.contains(&expected),
+                "{} is missing in\n{}",
+                expected,
+                packet
+            );
+        }
+        // counters are increases since the last report
+        let n = receiver.recv(&mut buf).await.unwrap();
+        let packet = String::from_utf8_lossy(&buf[..n]).into_owned();
+        assert!(packet.lines().any(|line| line == "demo.ops.total:0|c"));
+        closed.cancel();
+    }
+}
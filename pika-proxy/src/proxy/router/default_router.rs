@@ -1 +1,320 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
 
+use anyhow::anyhow;
+use redis::RedisCmd;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tracing::warn;
+
+use crate::error::{Error, Result};
+use crate::models::{Request, Slot, MAX_SLOT_NUM};
+use crate::proxy::backend::Backend;
+use crate::proxy::config::ConfigHandle;
+use crate::proxy::router::Router;
+use crate::utils::redis::InfoCache;
+
+/// timeout in milliseconds for migrating a key before forwarding a request
+const MIGRATE_TIMEOUT_MS: &str = "3000";
+
+struct SlotState {
+    model: Slot,
+    switched: bool,
+    // requests received while the slot is locked, forwarded once unlocked
+    pending: Vec<Request>,
+    // forwards requests one by one after their keys are migrated
+    migrator: Option<UnboundedSender<Request>>,
+}
+
+/// DefaultRouter routes requests by the codis slot model.
+pub struct DefaultRouter {
+    config: ConfigHandle,
+    backend: Arc<Backend>,
+    slots: Vec<Mutex<SlotState>>,
+}
+
+impl DefaultRouter {
+    pub fn new(config: ConfigHandle, backend: Arc<Backend>) -> Self {
+        let slots = (0..MAX_SLOT_NUM as u64)
+            .map(|id| {
+                Mutex::new(SlotState {
+                    model: Slot {
+                        id,
+                        ..Default::default()
+                    },
+                    switched: false,
+                    pending: vec![],
+                    migrator: None,
+                })
+            })
+            .collect();
+        Self {
+            config,
+            backend,
+            slots,
+        }
+    }
+
+    fn slot(&self, id: u64) -> Result<MutexGuard<'_, SlotState>> {
+        let slot = self
+            .slots
+            .get(id as usize)
+            .ok_or_else(|| Error::proxy(anyhow!("invalid slot id {}", id)))?;
+        Ok(slot.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    fn backend_addrs(model: &Slot) -> impl Iterator<Item = (&str, bool)> {
+        [model.backend_addr.as_str(), model.migrate_from.as_str()]
+            .into_iter()
+            .map(|addr| (addr, false))
+            .chain(
+                model
+                    .replica_groups
+                    .iter()
+                    .flatten()
+                    .map(|addr| (addr.as_str(), true)),
+            )
+            .filter(|(addr, _)| !addr.is_empty())
+    }
+
+    fn _fill_slot(&self, model: Slot, switched: bool) -> Result<()> {
+        if model.id as usize >= MAX_SLOT_NUM {
+            return Err(Error::proxy(anyhow!("invalid slot id {}", model.id)));
+        }
+        let mut slot = self.slot(model.id)?;
+        self.install(&mut slot, model, switched)
+    }
+
+    fn install(&self, slot: &mut SlotState, mut model: Slot, switched: bool) -> Result<()> {
+        let config = self.config.load();
+        if config.backend.primary_only {
+            model.replica_groups.clear();
+        }
+        let backend = &config.backend;
+        // retain before release, so unchanged backends keep their connections
+        for (addr, replica) in Self::backend_addrs(&model) {
+            let parallel = if replica {
+                backend.replica_parallel
+            } else {
+                backend.primary_parallel
+            };
+            self.backend.retain(addr, parallel)?;
+        }
+
+        let old = std::mem::replace(&mut slot.model, model);
+        for (addr, _) in Self::backend_addrs(&old) {
+            self.backend.release(addr);
+        }
+        slot.switched = switched;
+        slot.migrator = (!slot.model.migrate_from.is_empty()).then(|| {
+            spawn_migrator(
+                self.backend.clone(),
+                slot.model.migrate_from.clone(),
+                slot.model.backend_addr.clone(),
+            )
+        });
+        if !slot.model.locked {
+            for request in std::mem::take(&mut slot.pending) {
+                if let Err(e) = self.forward(slot, request) {
+                    warn!("forward pending request failed: {}", e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn forward(&self, slot: &SlotState, request: Request) -> Result<()> {
+        let model = &slot.model;
+        if model.backend_addr.is_empty() {
+            return Err(Error::proxy(anyhow!("slot-{:04} is not ready", model.id)));
+        }
+        if let Some(migrator) = &slot.migrator {
+            if request.hash_key().is_some() {
+                return migrator
+                    .send(request)
+                    .map_err(|_| Error::proxy(anyhow!("slot-{:04} migrator is closed", model.id)));
+            }
+        }
+        if !self.config.load().backend.primary_only && request.is_read_only() {
+            if let Some(group) = model.replica_groups.iter().find(|g| !g.is_empty()) {
+                let addr = &group[request.id() as usize % group.len()];
+                return self.backend.forward(addr, request);
+            }
+        }
+        self.backend.forward(&model.backend_addr, request)
+    }
+}
+
+impl Router for DefaultRouter {
+    fn get_slots(&self) -> Vec<Box<Slot>> {
+        (0..MAX_SLOT_NUM as u64)
+            .map(|id| self.get_slot(id))
+            .collect()
+    }
+
+    fn get_slot(&self, id: u64) -> Box<Slot> {
+        match self.slot(id) {
+            Ok(slot) => Box::new(slot.model.clone()),
+            Err(_) => Box::default(),
+        }
+    }
+
+    fn has_switched(&self) -> bool {
+        self.slots
+            .iter()
+            .any(|slot| slot.lock().unwrap_or_else(|e| e.into_inner()).switched)
+    }
+
+    fn fill_slot(&self, model: Box<Slot>) -> Result<()> {
+        self._fill_slot(*model, false)
+    }
+
+    fn update_slot(
+        &self,
+        id: u64,
+        update: Box<dyn FnOnce(&mut Slot) + '_>,
+    ) -> Result<(Slot, Slot)> {
+        let mut slot = self.slot(id)?;
+        let before = slot.model.clone();
+        let mut after = before.clone();
+        update(&mut after);
+        after.id = id;
+        self.install(&mut slot, after, false)?;
+        Ok((before, slot.model.clone()))
+    }
+
+    fn switch_masters(&self, masters: &HashMap<u64, String>, cache: &InfoCache) -> Result<()> {
+        for id in 0..MAX_SLOT_NUM as u64 {
+            self._try_switch_master(id, masters, cache);
+        }
+        Ok(())
+    }
+
+    fn dispatch(&self, request: Request) -> Result<()> {
+        let id = request.slot();
+        self._dispatch_slot(request, id)
+    }
+
+    fn _dispatch_slot(&self, request: Request, id: u64) -> Result<()> {
+        let mut slot = self.slot(id)?;
+        if slot.model.locked {
+            slot.pending.push(request);
+            return Ok(());
+        }
+        self.forward(&slot, request)
+    }
+
+    fn _dispatch_addr(&self, request: Request, addr: &str) -> bool {
+        self.backend.contains(addr) && self.backend.forward(addr, request).is_ok()
+    }
+
+    fn _try_switch_master(&self, id: u64, masters: &HashMap<u64, String>, cache: &InfoCache) {
+        // compared and changed under the lock, so a slot filled meanwhile
+        // isn't overwritten by a stale model
+        let Ok(mut slot) = self.slot(id) else {
+            return;
+        };
+        let mut model = slot.model.clone();
+        let mut switched = false;
+        if let Some(addr) = masters.get(&model.backend_add_group_id) {
+            if !cache.is_same_server(addr, &model.backend_addr) {
+                model.backend_addr = addr.clone();
+                switched = true;
+            }
+        }
+        if let Some(addr) = masters.get(&model.migrate_from_group_id) {
+            if !model.migrate_from.is_empty() && !cache.is_same_server(addr, &model.migrate_from) {
+                model.migrate_from = addr.clone();
+                switched = true;
+            }
+        }
+        if switched {
+            if let Err(e) = self.install(&mut slot, model, true) {
+                warn!("switch master of slot-{:04} failed: {}", id, e);
+            }
+        }
+    }
+}
+
+/// Spawns a task which migrates the key of each request from `from` to `to`
+/// before forwarding it, requests are handled one by one to keep the order.
+fn spawn_migrator(backend: Arc<Backend>, from: String, to: String) -> UnboundedSender<Request> {
+    let (tx, mut rx) = unbounded_channel::<Request>();
+    tokio::spawn(async move {
+        while let Some(request) = rx.recv().await {
+            if let Err(e) = migrate_key(&backend, &from, &to, &request).await {
+                request.respond(Err(e));
+                continue;
+            }
+            if let Err(e) = backend.forward(&to, request) {
+                warn!("forward migrated request to {} failed: {}", to, e);
+            }
+        }
+    });
+    tx
+}
+
+async fn migrate_key(backend: &Backend, from: &str, to: &str, request: &Request) -> Result<()> {
+    let Some(key) = request.hash_key() else {
+        return Ok(());
+    };
+    let (host, port) = to
+        .rsplit_once(':')
+        .ok_or_else(|| Error::proxy(anyhow!("invalid backend address {}", to)))?;
+    let cmd = RedisCmd::new([
+        b"SLOTSMGRTTAGONE".to_vec(),
+        host.as_bytes().to_vec(),
+        port.as_bytes().to_vec(),
+        MIGRATE_TIMEOUT_MS.as_bytes().to_vec(),
+        key.to_vec(),
+    ]);
+    let (migrate, pending) = Request::new(cmd, request.id(), request.database());
+    backend.forward(from, migrate)?;
+    let resp = pending.wait().await?;
+    if let Some(msg) = resp.redis().error_message() {
+        return Err(Error::server(anyhow!(
+            "migrate key from {} to {} failed: {}",
+            from,
+            to,
+            msg
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::redis::Info;
+
+    #[tokio::test]
+    async fn test_switch_masters() {
+        let config = ConfigHandle::new(Default::default());
+        let backend = Arc::new(Backend::new(config.clone()));
+        let router = DefaultRouter::new(config, backend);
+        for id in 0..MAX_SLOT_NUM as u64 {
+            let slot = Slot {
+                id,
+                backend_addr: "127.0.0.1:1".to_string(),
+                backend_add_group_id: id % 2,
+                ..Default::default()
+            };
+            router.fill_slot(Box::new(slot)).unwrap();
+        }
+
+        // another address of the same server isn't switched to
+        let mut cache = InfoCache::default();
+        let info = Info::parse("run_id:abc\r\nrole:master\r\n");
+        cache.insert("127.0.0.1:1", info.clone());
+        cache.insert("localhost:1", info);
+        let masters = HashMap::from([(1, "localhost:1".to_string())]);
+        router.switch_masters(&masters, &cache).unwrap();
+        assert_eq!(router.get_slot(1).backend_addr, "127.0.0.1:1");
+        assert!(!router.has_switched());
+
+        let masters = HashMap::from([(1, "127.0.0.1:2".to_string())]);
+        router.switch_masters(&masters, &cache).unwrap();
+        assert_eq!(router.get_slot(1).backend_addr, "127.0.0.1:2");
+        assert_eq!(router.get_slot(0).backend_addr, "127.0.0.1:1");
+        assert!(router.has_switched());
+    }
+}
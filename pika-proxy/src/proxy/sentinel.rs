@@ -0,0 +1,302 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use redis::{Frame, RedisCmd, RedisResp};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::error::{Error, Result};
+use crate::proxy::config::Config;
+use crate::proxy::router::Router;
+use crate::utils::redis::{InfoCache, RedisConnection};
+
+const SWITCH_MASTER_CHANNEL: &str = "+switch-master";
+/// how long to wait before a broken sentinel is subscribed again
+const RETRY_PERIOD: Duration = Duration::from_secs(1);
+
+/// Sentinel subscribes `+switch-master` of the sentinels, and switches the
+/// masters of the groups failed over, which are monitored as
+/// `<product_name>-<group_id>` the same as codis. A new master is switched
+/// to only if it tells it's a master by INFO.
+pub struct Sentinel {
+    config: Arc<Config>,
+    router: Arc<dyn Router>,
+    closed: CancellationToken,
+    // the sentinels watched, and the token to stop watching them
+    watching: Mutex<(Vec<String>, CancellationToken)>,
+}
+
+impl Sentinel {
+    pub fn new(config: Arc<Config>, router: Arc<dyn Router>, closed: CancellationToken) -> Self {
+        Self {
+            config,
+            router,
+            watching: Mutex::new((vec![], closed.child_token())),
+            closed,
+        }
+    }
+
+    /// Sentinels watched now.
+    pub fn addrs(&self) -> Vec<String> {
+        self.watching.lock().unwrap().0.clone()
+    }
+
+    /// Watches `addrs` instead of the sentinels watched before.
+    pub fn watch(&self, addrs: Vec<String>) {
+        let cancel = self.closed.child_token();
+        let mut watching = self.watching.lock().unwrap();
+        watching.1.cancel();
+        for addr in &addrs {
+            let watcher = Watcher {
+                addr: addr.clone(),
+                config: self.config.clone(),
+                router: self.router.clone(),
+            };
+            let cancel = cancel.clone();
+            tokio::spawn(async move { watcher.run(cancel).await });
+        }
+        if !addrs.is_empty() {
+            info!("watch sentinels {:?}", addrs);
+        }
+        *watching = (addrs, cancel);
+    }
+}
+
+struct Watcher {
+    addr: String,
+    config: Arc<Config>,
+    router: Arc<dyn Router>,
+}
+
+impl Watcher {
+    async fn run(&self, cancel: CancellationToken) {
+        loop {
+            tokio::select! {
+                result = self.subscribe() => if let Err(e) = result {
+                    warn!("watch sentinel {} failed: {}", self.addr, e);
+                },
+                _ = cancel.cancelled() => return,
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(RETRY_PERIOD) => {}
+                _ = cancel.cancelled() => return,
+            }
+        }
+    }
+
+    /// Switches to the masters known by the sentinel, then to the ones
+    /// failed over until the connection is broken.
+    async fn subscribe(&self) -> Result<()> {
+        let sentinel = &self.config.sentinel;
+        let product_name = &self.config.proxy.product_name;
+        let mut conn = RedisConnection::open(&self.addr, &sentinel.auth, sentinel.timeout).await?;
+        let cmd = RedisCmd::new(["SENTINEL", "MASTERS"]);
+        let resp = conn.call(&cmd, sentinel.timeout).await?;
+        self.switch(masters_of(product_name, &resp)).await;
+
+        let cmd = RedisCmd::new(["SUBSCRIBE", SWITCH_MASTER_CHANNEL]);
+        conn.requester.send_request(&cmd).await?;
+        loop {
+            let resp = conn.reader.read_response().await?;
+            if let Some(msg) = resp.error_message() {
+                return Err(Error::server(anyhow!("{}", msg)));
+            }
+            if let Some((group_id, addr)) = switched_master(product_name, &resp) {
+                info!(
+                    "sentinel {} switched group-{} to {}",
+                    self.addr, group_id, addr
+                );
+                self.switch(HashMap::from([(group_id, addr)])).await;
+            }
+        }
+    }
+
+    async fn switch(&self, masters: HashMap<u64, String>) {
+        if masters.is_empty() {
+            return;
+        }
+        // the current masters too, so one known by another address is kept
+        let current = self
+            .router
+            .get_slots()
+            .into_iter()
+            .filter(|slot| masters.contains_key(&slot.backend_add_group_id))
+            .map(|slot| slot.backend_addr)
+            .filter(|addr| !addr.is_empty())
+            .collect::<HashSet<_>>();
+        let addrs = masters.values().chain(current.iter()).map(String::as_str);
+        let (auth, timeout) = (
+            &self.config.proxy.product_auth,
+            self.config.sentinel.timeout,
+        );
+        let cache = InfoCache::load(addrs, auth, timeout).await;
+        let masters = masters
+            .into_iter()
+            .filter(|(group_id, addr)| {
+                let is_master = cache.is_master(addr);
+                if !is_master {
+                    warn!("{} of group-{} is not a master, skip it", addr, group_id);
+                }
+                is_master
+            })
+            .collect::<HashMap<_, _>>();
+        if let Err(e) = self.router.switch_masters(&masters, &cache) {
+            warn!("switch masters failed: {}", e);
+        }
+    }
+}
+
+/// Returns the group of a master monitored by sentinel, the name is
+/// `<product_name>-<group_id>`.
+fn group_of(product_name: &str, name: &str) -> Option<u64> {
+    name.strip_prefix(product_name)?
+        .strip_prefix('-')?
+        .parse()
+        .ok()
+}
+
+/// Parses a `+switch-master` message, whose payload is
+/// `<name> <old-ip> <old-port> <new-ip> <new-port>`.
+fn switched_master(product_name: &str, resp: &RedisResp) -> Option<(u64, String)> {
+    let Frame::Array(items) = resp.frame() else {
+        return None;
+    };
+    let [Frame::BulkString(kind), Frame::BulkString(channel), Frame::BulkString(payload)] =
+        items.as_slice()
+    else {
+        return None;
+    };
+    if !kind.eq_ignore_ascii_case(b"message") || channel != SWITCH_MASTER_CHANNEL {
+        return None;
+    }
+    let payload = String::from_utf8_lossy(payload);
+    let [name, _, _, ip, port] = payload.split_whitespace().collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    Some((group_of(product_name, name)?, format!("{}:{}", ip, port)))
+}
+
+/// Parses the reply of `SENTINEL MASTERS`, an array of field-value arrays.
+fn masters_of(product_name: &str, resp: &RedisResp) -> HashMap<u64, String> {
+    let Frame::Array(masters) = resp.frame() else {
+        return HashMap::new();
+    };
+    masters
+        .iter()
+        .filter_map(|master| {
+            let Frame::Array(fields) = master else {
+                return None;
+            };
+            let fields = fields
+                .chunks(2)
+                .filter_map(|pair| match pair {
+                    [Frame::BulkString(key), Frame::BulkString(value)] => Some((
+                        String::from_utf8_lossy(key).into_owned(),
+                        String::from_utf8_lossy(value).into_owned(),
+                    )),
+                    _ => None,
+                })
+                .collect::<HashMap<_, _>>();
+            let group_id = group_of(product_name, fields.get("name")?)?;
+            Some((
+                group_id,
+                format!("{}:{}", fields.get("ip")?, fields.get("port")?),
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Slot, MAX_SLOT_NUM};
+    use crate::proxy::backend::Backend;
+    use crate::proxy::config::ConfigHandle;
+    use crate::proxy::router::DefaultRouter;
+    use crate::utils::testing::{FakeBackend, TestClient};
+
+    fn bulks(items: &[&str]) -> RedisResp {
+        RedisResp::array(
+            items
+                .iter()
+                .map(|item| RedisResp::bulk(item.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_parse_sentinel_replies() {
+        assert_eq!(group_of("demo", "demo-12"), Some(12));
+        assert_eq!(group_of("demo", "demo2-12"), None);
+        let message = bulks(&[
+            "message",
+            "+switch-master",
+            "demo-1 10.0.0.1 6379 10.0.0.2 6380",
+        ]);
+        assert_eq!(
+            switched_master("demo", &message),
+            Some((1, "10.0.0.2:6380".to_string()))
+        );
+        assert_eq!(switched_master("other", &message), None);
+        let masters = RedisResp::array(vec![
+            bulks(&["name", "demo-1", "ip", "10.0.0.1", "port", "6379"]),
+            bulks(&["name", "other-2", "ip", "10.0.0.3", "port", "6379"]),
+        ]);
+        assert_eq!(
+            masters_of("demo", &masters),
+            HashMap::from([(1, "10.0.0.1:6379".to_string())])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sentinel_switch_master() {
+        let sentinel = FakeBackend::start().await;
+        let master = FakeBackend::start().await;
+        let mut config = Config::default();
+        config.proxy.product_name = "demo".to_string();
+        config.sentinel.timeout = Duration::from_secs(3);
+        let config = ConfigHandle::new(config);
+        let backend = Arc::new(Backend::new(config.clone()));
+        let router: Arc<dyn Router> = Arc::new(DefaultRouter::new(config.clone(), backend));
+        for id in 0..MAX_SLOT_NUM as u64 {
+            let slot = Slot {
+                id,
+                backend_addr: "127.0.0.1:1".to_string(),
+                backend_add_group_id: id % 2,
+                ..Default::default()
+            };
+            router.fill_slot(Box::new(slot)).unwrap();
+        }
+        let closed = CancellationToken::new();
+        let watcher = Sentinel::new(config.load_full(), router.clone(), closed.clone());
+        watcher.watch(vec![sentinel.addr.clone()]);
+        while !sentinel
+            .commands()
+            .iter()
+            .any(|(_, cmd)| cmd == "SUBSCRIBE +switch-master")
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let conn = tokio::net::TcpStream::connect(&sentinel.addr)
+            .await
+            .unwrap();
+        let mut client = TestClient::new(conn);
+        let (ip, port) = master.addr.split_once(':').unwrap();
+        let payload = format!("demo-1 127.0.0.1 1 {} {}", ip, port);
+        client.call(&["PUBLISH", "+switch-master", &payload]).await;
+        let switched = async {
+            while router.get_slot(1).backend_addr != master.addr {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), switched)
+            .await
+            .unwrap();
+        assert_eq!(router.get_slot(0).backend_addr, "127.0.0.1:1");
+        assert!(router.has_switched());
+        closed.cancel();
+    }
+}
@@ -0,0 +1,92 @@
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// State of a proxy, it only moves forward:
+/// starting → waiting-for-slots → online → closing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum ProxyState {
+    /// registering to the registry
+    Starting,
+    /// registered, but some slots are not filled yet
+    WaitingForSlots,
+    /// all slots are filled, clients are served
+    Online,
+    /// unregistered, new clients are rejected
+    Closing,
+}
+
+impl ProxyState {
+    fn from_u8(state: u8) -> Self {
+        match state {
+            0 => ProxyState::Starting,
+            1 => ProxyState::WaitingForSlots,
+            2 => ProxyState::Online,
+            _ => ProxyState::Closing,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProxyState::Starting => "starting",
+            ProxyState::WaitingForSlots => "waiting-for-slots",
+            ProxyState::Online => "online",
+            ProxyState::Closing => "closing",
+        }
+    }
+}
+
+impl Display for ProxyState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Lifecycle holds the state of a proxy shared by its tasks.
+#[derive(Debug)]
+pub struct Lifecycle {
+    state: AtomicU8,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self {
+            state: AtomicU8::new(ProxyState::Starting as u8),
+        }
+    }
+}
+
+impl Lifecycle {
+    pub fn state(&self) -> ProxyState {
+        ProxyState::from_u8(self.state.load(Ordering::SeqCst))
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.state() == ProxyState::Online
+    }
+
+    /// Moves to `state` if it's after the current one, returns false if the
+    /// proxy is already there or beyond.
+    pub fn advance(&self, state: ProxyState) -> bool {
+        let state = state as u8;
+        self.state.fetch_max(state, Ordering::SeqCst) < state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lifecycle() {
+        let lifecycle = Lifecycle::default();
+        assert_eq!(lifecycle.state(), ProxyState::Starting);
+        assert!(lifecycle.advance(ProxyState::WaitingForSlots));
+        assert!(!lifecycle.advance(ProxyState::WaitingForSlots));
+        assert!(lifecycle.advance(ProxyState::Online));
+        assert!(lifecycle.is_online());
+        assert!(lifecycle.advance(ProxyState::Closing));
+        assert!(!lifecycle.advance(ProxyState::Online));
+        assert_eq!(lifecycle.state().to_string(), "closing");
+    }
+}
@@ -1,85 +1,526 @@
+pub mod lifecycle;
 pub mod proxy_metrics;
 
-use anyhow::{anyhow, bail};
+use std::path::Path;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
+use anyhow::anyhow;
+use socket2::{SockRef, TcpKeepalive};
+use tokio::io::AsyncWriteExt;
 use tokio::net::{TcpListener, TcpStream};
-use tracing::info;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
 
-use super::config::Config;
+use super::config::{Config, ConfigHandle, Override, RegistryType};
+use crate::defer;
 use crate::error::{Error, Result};
+use crate::models::ProxyModel;
 use crate::proxy::backend::Backend;
-use crate::proxy::registry::Registry;
-use crate::proxy::router::Router;
+use crate::proxy::dashboard::audit::AuditLog;
+use crate::proxy::dashboard::proxy_api::{serve_proxy_api, xauth, ProxyApi};
+use crate::proxy::registry::{EtcdAdapter, FileAdapter, RedisAdapter, Registry, RegistryAdapter};
+use crate::proxy::reporter::spawn_reporters;
+use crate::proxy::router::{DefaultRouter, Router};
+use crate::proxy::sentinel::Sentinel;
 use crate::proxy::session::client_session::{ClientSession, ClientSessionOption};
+use crate::utils::time::format_utc;
+use lifecycle::{Lifecycle, ProxyState};
 use proxy_metrics::ProxyMetrics;
 
+/// how often the topology file of a file registry is checked
+const FILE_POLL_PERIOD: Duration = Duration::from_secs(1);
+/// how often the slots are checked before the proxy is online
+const ONLINE_CHECK_PERIOD: Duration = Duration::from_millis(500);
+
 pub struct ProxyServer {
     router: Arc<dyn Router>,
-    config: Arc<Config>,
+    config: ConfigHandle,
     backend: Arc<Backend>,
+    registry: Arc<Registry>,
+    sentinel: Arc<Sentinel>,
     proxy_metrics: Arc<ProxyMetrics>,
+    lifecycle: Arc<Lifecycle>,
+    // how the proxy is registered
+    model: ProxyModel,
+    // cancelled when the proxy is shut down
+    closed: CancellationToken,
 }
 
-pub(crate) struct ProxyOptions {
-    pub(crate) config_path: String,
+pub struct ProxyOptions {
+    pub config_path: String,
+    // layered over the config file in order
+    pub overrides: Vec<Override>,
 }
 
 impl ProxyServer {
-    pub async fn server_client(&mut self, conn: TcpStream) -> Result<()> {
+    pub async fn server_client(&mut self, mut conn: TcpStream) -> Result<()> {
+        if !self.lifecycle.is_online() {
+            conn.write_all(b"-ERR proxy is not online\r\n")
+                .await
+                .map_err(Error::network)?;
+            return Ok(());
+        }
+        let (max_clients, keepalive) = {
+            let config = self.config.load();
+            (config.proxy.max_clients, config.session.keepalive_period)
+        };
+        let metrics = self.proxy_metrics.clone();
+        if max_clients != 0 && metrics.current_connections.load(Ordering::SeqCst) >= max_clients {
+            conn.write_all(b"-ERR max number of clients reached\r\n")
+                .await
+                .map_err(Error::network)?;
+            return Ok(());
+        }
+        if let Some(keepalive) = keepalive {
+            SockRef::from(&conn)
+                .set_tcp_keepalive(&TcpKeepalive::new().with_time(keepalive))
+                .map_err(Error::network)?;
+        }
+        metrics.session_opened();
+
         let option = ClientSessionOption {
             router: self.router.clone(),
             config: self.config.clone(),
+            backend: self.backend.clone(),
+            metrics: metrics.clone(),
         };
         let session = ClientSession::new(option);
-        session.serve_client(conn)?;
-        unimplemented!()
+        tokio::spawn(async move {
+            defer! {
+                metrics.session_closed();
+            }
+            if let Err(e) = session.serve_client(conn).await {
+                debug!("client session exits with error: {}", e);
+            }
+        });
+        Ok(())
     }
 
-    pub(crate) fn new(option: &ProxyOptions) -> Result<Self> {
-        let config = Arc::new(Config::from_path(&option.config_path)?);
-        let registry = Self::initialize_registry(config.clone())?;
-        let router = Self::initialize_router(config.clone(), registry.clone())?;
-        let backend = Self::initialize_backend(config.clone(), router.clone(), registry)?;
+    pub async fn new(option: &ProxyOptions) -> Result<Self> {
+        let handle = ConfigHandle::from_path_with(&option.config_path, option.overrides.clone())?;
+        // the ones read only at startup, they can't be changed by a reload
+        let config = handle.load_full();
+        let lifecycle = Arc::new(Lifecycle::default());
+        let proxy_metrics = Arc::<ProxyMetrics>::default();
+        let backend = Self::initialize_backend(handle.clone(), proxy_metrics.clone())?;
+        let router = Self::initialize_router(handle.clone(), backend.clone())?;
+        let registry = Self::initialize_registry(config.clone(), &option.config_path).await?;
+
+        let model = Self::proxy_model(&config);
+        registry.adapter().register(&model).await?;
+        info!("proxy {} is registered", model.token);
+        let closed = CancellationToken::new();
+        Self::spawn_heartbeat(registry.clone(), config.registry.ttl, closed.clone());
+
+        lifecycle.advance(ProxyState::WaitingForSlots);
+        // slots are not ready until the topology is applied, by the watcher
+        // if the registry is unavailable now
+        if let Err(e) = registry.apply(router.as_ref()).await {
+            warn!("apply topology failed: {}", e);
+        }
+        registry.clone().spawn_watcher(
+            router.clone(),
+            config.registry.reconcile_period,
+            closed.clone(),
+        );
+        Self::spawn_online_waiter(router.clone(), lifecycle.clone(), closed.clone());
+        let sentinel = Arc::new(Sentinel::new(
+            config.clone(),
+            router.clone(),
+            closed.clone(),
+        ));
+        sentinel.watch(config.sentinel.addrs.clone());
+        proxy_metrics.clone().spawn_qps_sampler(closed.clone());
+        proxy_metrics
+            .clone()
+            .spawn_latency_resetter(handle.clone(), closed.clone());
+        let api = Arc::new(ProxyApi {
+            xauth: xauth(
+                &config.proxy.product_name,
+                &config.proxy.product_auth,
+                &model.token,
+            ),
+            model: model.clone(),
+            router: router.clone(),
+            registry: registry.clone(),
+            sentinel: sentinel.clone(),
+            lifecycle: lifecycle.clone(),
+            metrics: proxy_metrics.clone(),
+            closed: closed.clone(),
+            audit: AuditLog::default(),
+            config: handle.clone(),
+        });
+        if !config.proxy.admin_addr.is_empty() {
+            serve_proxy_api(&config.proxy.admin_addr, api.clone())?;
+        }
+        spawn_reporters(handle.clone(), api, closed.clone());
+        spawn_hangup_reloader(handle.clone(), closed.clone());
         Ok(ProxyServer {
             router,
             backend,
-            config,
-            proxy_metrics: Arc::<ProxyMetrics>::default(),
+            config: handle,
+            registry,
+            sentinel,
+            proxy_metrics,
+            lifecycle,
+            model,
+            closed,
         })
     }
 
-    fn initialize_router(config: Arc<Config>, registry: Arc<Registry>) -> Result<Arc<dyn Router>> {
-        unimplemented!()
+    pub fn state(&self) -> ProxyState {
+        self.lifecycle.state()
     }
 
-    fn initialize_backend(
-        config: Arc<Config>,
+    pub fn model(&self) -> &ProxyModel {
+        &self.model
+    }
+
+    pub fn sentinel(&self) -> &Arc<Sentinel> {
+        &self.sentinel
+    }
+
+    /// Unregisters the proxy and stops accepting clients, sessions served
+    /// already are not closed.
+    pub async fn shutdown(&self) -> Result<()> {
+        shutdown_proxy(&self.lifecycle, &self.closed, &self.registry, &self.model).await
+    }
+
+    /// Builds the model the proxy is registered as, the same as codis.
+    fn proxy_model(config: &Config) -> ProxyModel {
+        let proxy = &config.proxy;
+        let or = |host: &str, addr: &str| {
+            if host.is_empty() {
+                addr.to_string()
+            } else {
+                host.to_string()
+            }
+        };
+        let proxy_addr = or(&proxy.host_proxy, &proxy.addr);
+        let admin_addr = or(&proxy.host_admin, &proxy.admin_addr);
+        let now = SystemTime::now();
+        let nanos = now
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let seed = format!(
+            "{}-{}-{}-{}",
+            proxy.product_name, proxy_addr, admin_addr, nanos
+        );
+        ProxyModel {
+            token: format!("{:x}", md5::compute(seed)),
+            start_time: format_utc(now),
+            admin_addr,
+            proto_type: proxy.protocol_type.as_str().to_string(),
+            proxy_addr,
+            product_name: proxy.product_name.clone(),
+            pid: std::process::id(),
+            pwd: std::env::current_dir()
+                .map(|dir| dir.display().to_string())
+                .unwrap_or_default(),
+            sys: format!("{} {}", std::env::consts::OS, std::env::consts::ARCH),
+            hostname: hostname(),
+            datacenter: proxy.data_center.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Keeps the registration alive until `closed` is cancelled.
+    fn spawn_heartbeat(registry: Arc<Registry>, ttl: Duration, closed: CancellationToken) {
+        let period = (ttl / 3).max(Duration::from_millis(100));
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = closed.cancelled() => return,
+                }
+                if let Err(e) = registry.adapter().heartbeat().await {
+                    warn!("heartbeat to registry failed: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Brings the proxy online once all slots are filled.
+    fn spawn_online_waiter(
         router: Arc<dyn Router>,
-        registry: Arc<Registry>,
+        lifecycle: Arc<Lifecycle>,
+        closed: CancellationToken,
+    ) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(ONLINE_CHECK_PERIOD);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = closed.cancelled() => return,
+                }
+                let waiting = router
+                    .get_slots()
+                    .iter()
+                    .filter(|slot| slot.backend_addr.is_empty())
+                    .count();
+                if waiting == 0 {
+                    if lifecycle.advance(ProxyState::Online) {
+                        info!("proxy is online");
+                    }
+                    return;
+                }
+                debug!("proxy is waiting for {} slots", waiting);
+            }
+        });
+    }
+
+    fn initialize_router(config: ConfigHandle, backend: Arc<Backend>) -> Result<Arc<dyn Router>> {
+        Ok(Arc::new(DefaultRouter::new(config, backend)))
+    }
+
+    fn initialize_backend(
+        config: ConfigHandle,
+        metrics: Arc<ProxyMetrics>,
     ) -> Result<Arc<Backend>> {
-        unimplemented!()
+        Ok(Arc::new(Backend::with_metrics(config, metrics)))
     }
 
-    fn initialize_registry(config: Arc<Config>) -> Result<Arc<Registry>> {
-        unimplemented!()
+    async fn initialize_registry(config: Arc<Config>, config_path: &str) -> Result<Arc<Registry>> {
+        let registry = &config.registry;
+        if registry.addr.is_empty() {
+            return Err(Error::initialize(anyhow!("registry addr is empty")));
+        }
+        let product_name = &config.proxy.product_name;
+        let adapter: Box<dyn RegistryAdapter> = match registry.registry_type {
+            RegistryType::Etcd => {
+                let endpoints = registry
+                    .addr
+                    .split(',')
+                    .map(|addr| addr.trim().to_string())
+                    .collect::<Vec<_>>();
+                let adapter = EtcdAdapter::connect(
+                    &endpoints,
+                    &registry.auth,
+                    registry.timeout,
+                    product_name,
+                    registry.ttl,
+                )
+                .await?;
+                Box::new(adapter)
+            }
+            RegistryType::Redis => {
+                let adapter = RedisAdapter::connect(
+                    &registry.addr,
+                    &registry.auth,
+                    registry.timeout,
+                    product_name,
+                    registry.ttl,
+                )
+                .await?;
+                Box::new(adapter)
+            }
+            RegistryType::File => {
+                // relative to the directory of the config file
+                let dir = Path::new(config_path).parent().unwrap_or(Path::new(""));
+                Box::new(FileAdapter::new(
+                    dir.join(&registry.addr),
+                    FILE_POLL_PERIOD,
+                )?)
+            }
+        };
+        info!("registry {:?} at {}", registry.registry_type, registry.addr);
+        Ok(Arc::new(Registry::new(adapter, &config.proxy.data_center)))
     }
 
     pub async fn serve_proxy(&mut self) -> Result<()> {
         // 这里需要一条启动 log
-        info!("listen will on {:?}", self.config.proxy.addr);
-        let listener = TcpListener::bind(&self.config.proxy.addr)
-            .await
-            .map_err(Error::server)?;
-        while let Ok((conn, addr)) = listener.accept().await {
+        let addr = self.config.load().proxy.addr.clone();
+        info!("listen will on {:?}", addr);
+        let listener = TcpListener::bind(&addr).await.map_err(Error::server)?;
+        loop {
+            let (conn, addr) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(_) => break,
+                },
+                _ = self.closed.cancelled() => break,
+                _ = shutdown_signal() => {
+                    self.shutdown().await?;
+                    break;
+                }
+            };
             tracing::debug!("new client connection from {}", addr);
-            self.proxy_metrics
-                .current_connections
-                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-            self.server_client(conn).await?;
+            if let Err(e) = self.server_client(conn).await {
+                warn!("serve client {} failed: {}", addr, e);
+            }
         }
         Ok(())
     }
 }
 
-//实际的挂起函数
+/// Moves the proxy to closing, then stops its tasks and unregisters it, it's
+/// shared by [`ProxyServer::shutdown`] and the admin api.
+pub(crate) async fn shutdown_proxy(
+    lifecycle: &Lifecycle,
+    closed: &CancellationToken,
+    registry: &Registry,
+    model: &ProxyModel,
+) -> Result<()> {
+    if !lifecycle.advance(ProxyState::Closing) {
+        return Ok(());
+    }
+    info!("proxy {} is closing", model.token);
+    closed.cancel();
+    registry.adapter().unregister().await
+}
+
+/// Reloads the config file and applies it, it's shared by SIGHUP and the
+/// admin api. The keys changed are returned.
+pub(crate) fn reload_config(config: &ConfigHandle) -> Result<Vec<String>> {
+    match config.reload() {
+        Ok(changed) => {
+            info!("config is reloaded, changed: {:?}", changed);
+            Ok(changed)
+        }
+        Err(e) => {
+            warn!("reload config failed: {}", e);
+            Err(Error::proxy(e))
+        }
+    }
+}
+
+/// Reloads the config on every SIGHUP until `closed` is cancelled.
+fn spawn_hangup_reloader(config: ConfigHandle, closed: CancellationToken) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                warn!("listen to SIGHUP failed: {}", e);
+                return;
+            }
+        };
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = hangup.recv() => {}
+                    _ = closed.cancelled() => return,
+                }
+                let _ = reload_config(&config);
+            }
+        });
+    }
+    #[cfg(not(unix))]
+    let _ = (config, closed);
+}
+
+/// Resolves on ctrl-c, or SIGTERM on unix.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(terminate) => terminate,
+            Err(_) => return std::future::pending().await,
+        };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|name| name.trim().to_string())
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::{tcp_pair, FakeBackend, TestClient};
+
+    async fn start_proxy(dir: &Path, slots: &str, backend: &str) -> ProxyServer {
+        std::fs::create_dir_all(dir).unwrap();
+        let config = r#"
+            [proxy]
+            product_name = "test"
+            addr = "127.0.0.1:19000"
+            admin_addr = ""
+            [session]
+            max_pipeline = 1024
+            [backend]
+            max_pipeline = 1024
+            [registry]
+            registry_type = "file"
+            addr = "topology.toml"
+            ttl = "1s"
+        "#;
+        let topology = format!(
+            "[[groups]]\nid = 1\nmaster = \"{}\"\n[[slots]]\n{}\ngroup_id = 1\n",
+            backend, slots
+        );
+        std::fs::write(dir.join("proxy.toml"), config).unwrap();
+        std::fs::write(dir.join("topology.toml"), topology).unwrap();
+        let option = ProxyOptions {
+            config_path: dir.join("proxy.toml").display().to_string(),
+            overrides: vec![],
+        };
+        ProxyServer::new(&option).await.unwrap()
+    }
+
+    async fn wait_state(proxy: &ProxyServer, state: ProxyState) {
+        let wait = async {
+            while proxy.state() != state {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), wait)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_proxy_lifecycle() {
+        let backend = FakeBackend::start().await;
+        let dir = std::env::temp_dir().join(format!("proxy-{}", std::process::id()));
+
+        let mut proxy = start_proxy(&dir, "begin = 0\nend = 1022", &backend.addr).await;
+        assert_eq!(proxy.model().product_name, "test");
+        assert_eq!(proxy.model().proxy_addr, "127.0.0.1:19000");
+        assert_eq!(proxy.model().token.len(), 32);
+        tokio::time::sleep(ONLINE_CHECK_PERIOD).await;
+        assert_eq!(proxy.state(), ProxyState::WaitingForSlots);
+        let (client, server) = tcp_pair().await;
+        proxy.server_client(server).await.unwrap();
+        let mut client = TestClient::new(client);
+        let resp = client.recv().await.unwrap();
+        assert_eq!(resp.error_message(), Some("ERR proxy is not online"));
+        proxy.shutdown().await.unwrap();
+
+        let mut proxy = start_proxy(&dir, "begin = 0\nend = 1023", &backend.addr).await;
+        wait_state(&proxy, ProxyState::Online).await;
+        let (client, server) = tcp_pair().await;
+        proxy.server_client(server).await.unwrap();
+        let mut client = TestClient::new(client);
+        assert_eq!(
+            client.call(&["GET", "k"]).await,
+            redis::RedisResp::bulk("GET k")
+        );
+        let ops = proxy.proxy_metrics.ops();
+        assert_eq!((ops.total, ops.cmd[0].opstr.as_str()), (1, "GET"));
+        assert_eq!(proxy.proxy_metrics.sessions().total, 1);
+        let latency = proxy.proxy_metrics.latency();
+        assert_eq!(latency.commands["GET"].count, 1);
+        assert!(latency.backends[&backend.addr].count >= 1);
+        proxy.shutdown().await.unwrap();
+        assert_eq!(proxy.state(), ProxyState::Closing);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
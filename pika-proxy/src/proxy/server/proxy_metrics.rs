@@ -1,5 +1,407 @@
-use std::sync::atomic::AtomicU32;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use redis::RedisResp;
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+
+use crate::error::Result;
+use crate::models::{commands, Command};
+use crate::proxy::config::ConfigHandle;
+use crate::proxy::session::memory::{Offheap, OffheapSnapshot};
+use crate::utils::histogram::{Histogram, Percentiles};
+
+/// how often the ops are sampled for the qps
+const QPS_SAMPLE_PERIOD: Duration = Duration::from_secs(1);
+/// the qps is averaged over the samples of this window
+const QPS_WINDOW: Duration = Duration::from_secs(5);
+/// commands unknown to the proxy are counted together as this one
+const UNKNOWN_COMMAND: &str = "UNKNOWN";
+
+/// CommandStats counts the calls of one command.
 #[derive(Debug, Default)]
+struct CommandStats {
+    calls: AtomicU64,
+    usecs: AtomicU64,
+    fails: AtomicU64,
+    redis_errors: AtomicU64,
+    // end-to-end latency in microseconds
+    latency: Histogram,
+}
+
+/// ProxyMetrics is updated by all sessions without locks, the counters of a
+/// command are indexed by its position in the command table, and the last
+/// one is for the unknown commands. Latencies are kept in histograms which
+/// are reset every window.
+#[derive(Debug)]
 pub struct ProxyMetrics {
     pub current_connections: AtomicU32,
+    pub total_connections: AtomicU64,
+    // sessions disconnected for exceeding the output buffer limit
+    output_buffer_evicted: AtomicU64,
+    ops: AtomicU64,
+    fails: AtomicU64,
+    redis_errors: AtomicU64,
+    commands: Vec<CommandStats>,
+    qps: AtomicU64,
+    // (when, ops) sampled in the qps window, only touched by the sampler
+    samples: Mutex<VecDeque<(Instant, u64)>>,
+    // round-trip latency in microseconds of each backend address
+    backends: DashMap<String, Arc<Histogram>>,
+    offheap: Arc<Offheap>,
+}
+
+impl Default for ProxyMetrics {
+    fn default() -> Self {
+        Self {
+            current_connections: AtomicU32::new(0),
+            total_connections: AtomicU64::new(0),
+            output_buffer_evicted: AtomicU64::new(0),
+            ops: AtomicU64::new(0),
+            fails: AtomicU64::new(0),
+            redis_errors: AtomicU64::new(0),
+            commands: (0..=commands().len())
+                .map(|_| CommandStats::default())
+                .collect(),
+            qps: AtomicU64::new(0),
+            samples: Mutex::new(VecDeque::new()),
+            backends: DashMap::new(),
+            offheap: Arc::default(),
+        }
+    }
+}
+
+/// Stats of a command in codis `/api/proxy/stats`.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandSnapshot {
+    pub opstr: String,
+    pub calls: u64,
+    pub usecs: u64,
+    pub usecs_percall: u64,
+    pub fails: u64,
+    pub redis_errtype: u64,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct RedisErrors {
+    pub errors: u64,
+}
+
+/// `ops` in codis `/api/proxy/stats`.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct OpsSnapshot {
+    pub total: u64,
+    pub fails: u64,
+    pub redis: RedisErrors,
+    pub qps: u64,
+    pub cmd: Vec<CommandSnapshot>,
+}
+
+/// Latency percentiles in microseconds, by command and by backend address.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct LatencySnapshot {
+    pub commands: BTreeMap<String, Percentiles>,
+    pub backends: BTreeMap<String, Percentiles>,
+}
+
+/// `sessions` in codis `/api/proxy/stats`.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionsSnapshot {
+    pub total: u64,
+    pub alive: u64,
+    pub output_buffer_evicted: u64,
+}
+
+impl ProxyMetrics {
+    /// Counts a command answered after `elapsed`, a failure of the proxy or
+    /// an error reply of redis is counted as well.
+    pub fn record(
+        &self,
+        command: Option<&'static Command>,
+        elapsed: Duration,
+        result: &Result<RedisResp>,
+    ) {
+        let index = command.map_or(commands().len(), Command::index);
+        let stats = &self.commands[index];
+        let usecs = elapsed.as_micros() as u64;
+        self.ops.fetch_add(1, Ordering::Relaxed);
+        stats.calls.fetch_add(1, Ordering::Relaxed);
+        stats.usecs.fetch_add(usecs, Ordering::Relaxed);
+        stats.latency.record(usecs);
+        match result {
+            Err(_) => {
+                self.fails.fetch_add(1, Ordering::Relaxed);
+                stats.fails.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(resp) if resp.error_message().is_some() => {
+                self.redis_errors.fetch_add(1, Ordering::Relaxed);
+                stats.redis_errors.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(_) => {}
+        }
+    }
+
+    /// Histogram of the round trips to backend `addr`.
+    pub fn backend_latency(&self, addr: &str) -> Arc<Histogram> {
+        if let Some(histogram) = self.backends.get(addr) {
+            return histogram.clone();
+        }
+        self.backends.entry(addr.to_string()).or_default().clone()
+    }
+
+    /// Starts a new window of the latency histograms, the ones of backends
+    /// not connected any more are dropped.
+    pub fn reset_latency(&self) {
+        for stats in &self.commands {
+            stats.latency.reset();
+        }
+        self.backends
+            .retain(|_, histogram| Arc::strong_count(histogram) > 1);
+        for histogram in self.backends.iter() {
+            histogram.reset();
+        }
+    }
+
+    /// Resets the latency histograms every `metrics.latency_window` until
+    /// `closed` is cancelled, they are never reset if the window is disabled. A
+    /// new window is started once the window is reloaded.
+    pub fn spawn_latency_resetter(
+        self: Arc<Self>,
+        config: ConfigHandle,
+        closed: CancellationToken,
+    ) {
+        tokio::spawn(async move {
+            let mut changed = config.subscribe();
+            let mut window = config.load().metrics.latency_window;
+            let next = |window: Option<Duration>| {
+                window.map(|window| tokio::time::Instant::now() + window)
+            };
+            let mut deadline = next(window);
+            loop {
+                let reset = async {
+                    match deadline {
+                        Some(deadline) => tokio::time::sleep_until(deadline).await,
+                        None => std::future::pending::<()>().await,
+                    }
+                };
+                tokio::select! {
+                    _ = reset => {
+                        self.reset_latency();
+                        deadline = next(window);
+                    }
+                    reloaded = changed.changed() => {
+                        if reloaded.is_err() {
+                            return;
+                        }
+                        let reloaded = config.load().metrics.latency_window;
+                        if reloaded != window {
+                            window = reloaded;
+                            deadline = next(window);
+                        }
+                    }
+                    _ = closed.cancelled() => return,
+                }
+            }
+        });
+    }
+
+    pub fn session_opened(&self) {
+        self.current_connections.fetch_add(1, Ordering::SeqCst);
+        self.total_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn session_closed(&self) {
+        self.current_connections.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    pub fn output_buffer_evicted(&self) {
+        self.output_buffer_evicted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Samples the ops, and updates the qps over the window.
+    pub fn sample_qps(&self) {
+        let now = Instant::now();
+        let ops = self.ops.load(Ordering::Relaxed);
+        let mut samples = self.samples.lock().unwrap();
+        samples.push_back((now, ops));
+        while samples
+            .front()
+            .is_some_and(|(when, _)| now.duration_since(*when) > QPS_WINDOW)
+        {
+            samples.pop_front();
+        }
+        let (first, first_ops) = samples[0];
+        let secs = now.duration_since(first).as_secs_f64();
+        let qps = if secs > 0.0 {
+            ((ops - first_ops) as f64 / secs).round() as u64
+        } else {
+            0
+        };
+        self.qps.store(qps, Ordering::Relaxed);
+    }
+
+    /// Samples the qps every second until `closed` is cancelled.
+    pub fn spawn_qps_sampler(self: Arc<Self>, closed: CancellationToken) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(QPS_SAMPLE_PERIOD);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => self.sample_qps(),
+                    _ = closed.cancelled() => return,
+                }
+            }
+        });
+    }
+
+    /// Commands called so far are listed by name.
+    pub fn ops(&self) -> OpsSnapshot {
+        let names = commands()
+            .iter()
+            .map(|command| command.name)
+            .chain([UNKNOWN_COMMAND]);
+        let mut cmd = names
+            .zip(&self.commands)
+            .filter_map(|(name, stats)| {
+                let calls = stats.calls.load(Ordering::Relaxed);
+                if calls == 0 {
+                    return None;
+                }
+                let usecs = stats.usecs.load(Ordering::Relaxed);
+                Some(CommandSnapshot {
+                    opstr: name.to_string(),
+                    calls,
+                    usecs,
+                    usecs_percall: usecs / calls,
+                    fails: stats.fails.load(Ordering::Relaxed),
+                    redis_errtype: stats.redis_errors.load(Ordering::Relaxed),
+                })
+            })
+            .collect::<Vec<_>>();
+        cmd.sort_by(|a, b| a.opstr.cmp(&b.opstr));
+        OpsSnapshot {
+            total: self.ops.load(Ordering::Relaxed),
+            fails: self.fails.load(Ordering::Relaxed),
+            redis: RedisErrors {
+                errors: self.redis_errors.load(Ordering::Relaxed),
+            },
+            qps: self.qps.load(Ordering::Relaxed),
+            cmd,
+        }
+    }
+
+    /// Latencies of the current window, commands not called in the window
+    /// are skipped.
+    pub fn latency(&self) -> LatencySnapshot {
+        let names = commands()
+            .iter()
+            .map(|command| command.name)
+            .chain([UNKNOWN_COMMAND]);
+        let commands = names
+            .zip(&self.commands)
+            .map(|(name, stats)| (name.to_string(), stats.latency.percentiles()))
+            .filter(|(_, percentiles)| percentiles.count > 0)
+            .collect();
+        let backends = self
+            .backends
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().percentiles()))
+            .collect();
+        LatencySnapshot { commands, backends }
+    }
+
+    /// Memory held by the requests and responses of all sessions.
+    pub fn offheap(&self) -> &Arc<Offheap> {
+        &self.offheap
+    }
+
+    pub fn offheap_snapshot(&self) -> OffheapSnapshot {
+        self.offheap.snapshot()
+    }
+
+    pub fn sessions(&self) -> SessionsSnapshot {
+        SessionsSnapshot {
+            total: self.total_connections.load(Ordering::Relaxed),
+            alive: self.current_connections.load(Ordering::SeqCst) as u64,
+            output_buffer_evicted: self.output_buffer_evicted.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::*;
+    use crate::error::Error;
+    use crate::models::get_command;
+
+    #[test]
+    fn test_proxy_metrics() {
+        let metrics = ProxyMetrics::default();
+        let get = get_command("GET");
+        let ms = Duration::from_millis(1);
+        metrics.record(get, ms, &Ok(RedisResp::bulk("v")));
+        metrics.record(get, ms * 3, &Ok(RedisResp::error("ERR wrong")));
+        metrics.record(None, ms, &Err(Error::proxy(anyhow!("broken"))));
+        metrics.session_opened();
+        metrics.session_opened();
+        metrics.session_closed();
+        metrics.output_buffer_evicted();
+
+        let ops = metrics.ops();
+        assert_eq!((ops.total, ops.fails, ops.redis.errors), (3, 1, 1));
+        assert_eq!(
+            ops.cmd,
+            vec![
+                CommandSnapshot {
+                    opstr: "GET".to_string(),
+                    calls: 2,
+                    usecs: 4000,
+                    usecs_percall: 2000,
+                    fails: 0,
+                    redis_errtype: 1,
+                },
+                CommandSnapshot {
+                    opstr: UNKNOWN_COMMAND.to_string(),
+                    calls: 1,
+                    usecs: 1000,
+                    usecs_percall: 1000,
+                    fails: 1,
+                    redis_errtype: 0,
+                },
+            ]
+        );
+        assert_eq!(
+            metrics.sessions(),
+            SessionsSnapshot {
+                total: 2,
+                alive: 1,
+                output_buffer_evicted: 1,
+            }
+        );
+
+        metrics.sample_qps();
+        std::thread::sleep(Duration::from_millis(100));
+        for _ in 0..10 {
+            metrics.record(get, ms, &Ok(RedisResp::ok()));
+        }
+        metrics.sample_qps();
+        assert!(metrics.ops().qps > 10);
+
+        let backend = metrics.backend_latency("127.0.0.1:6379");
+        backend.record(300);
+        metrics.backend_latency("127.0.0.1:6380").record(300);
+        let latency = metrics.latency();
+        assert_eq!(latency.commands["GET"].count, 12);
+        assert_eq!(latency.commands["GET"].max, 3000);
+        assert_eq!(latency.backends["127.0.0.1:6379"].p50, 300);
+        metrics.reset_latency();
+        let latency = metrics.latency();
+        assert!(latency.commands.is_empty());
+        assert_eq!(latency.backends["127.0.0.1:6379"].count, 0);
+        assert!(!latency.backends.contains_key("127.0.0.1:6380"));
+    }
 }
@@ -0,0 +1,104 @@
+use std::future::pending;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use redis::RedisResp;
+use tokio_util::sync::CancellationToken;
+
+use crate::error::{Error, Result};
+use crate::models::Request;
+use crate::proxy::backend::Backend;
+use crate::proxy::config::ConfigHandle;
+use crate::proxy::router::Router;
+use crate::proxy::session::CROSSSLOT_ERROR;
+
+/// extra time given to backend to reply a timed out blocking command itself
+const BLOCK_GRACE: Duration = Duration::from_secs(1);
+
+/// Blocking runs blocking commands of a session, each on a connection taken
+/// from the backend's blocking pool, so the shared connections never wait
+/// for them.
+pub struct Blocking {
+    router: Arc<dyn Router>,
+    backend: Arc<Backend>,
+    config: ConfigHandle,
+    // cancelled once the client is gone, the running commands are dropped
+    closed: CancellationToken,
+}
+
+impl Blocking {
+    pub fn new(router: Arc<dyn Router>, backend: Arc<Backend>, config: ConfigHandle) -> Self {
+        Self {
+            router,
+            backend,
+            config,
+            closed: CancellationToken::new(),
+        }
+    }
+
+    /// Drops the running commands and closes their connections.
+    pub fn close(&self) {
+        self.closed.cancel();
+    }
+
+    /// How long proxy waits for the response, `None` means forever.
+    fn wait_limit(&self, request: &Request) -> Option<Duration> {
+        let max = self.config.load().backend.blocking_timeout;
+        match (request.block_timeout(), max) {
+            (timeout, max) if timeout.is_zero() => max,
+            (timeout, None) => Some(timeout + BLOCK_GRACE),
+            (timeout, Some(max)) => Some((timeout + BLOCK_GRACE).min(max)),
+        }
+    }
+
+    pub fn execute(&self, request: Request) -> Result<()> {
+        if request.is_cross_slot() {
+            request.respond(Ok(RedisResp::error(CROSSSLOT_ERROR)));
+            return Ok(());
+        }
+        let id = request.slot();
+        let slot = self.router.get_slot(id);
+        if slot.backend_addr.is_empty() {
+            return Err(Error::proxy(anyhow!("slot-{:04} is not ready", id)));
+        }
+        if slot.locked || !slot.migrate_from.is_empty() {
+            return Err(Error::proxy(anyhow!(
+                "slot-{:04} is migrating, try again later",
+                id
+            )));
+        }
+
+        let backend = self.backend.clone();
+        let closed = self.closed.clone();
+        let limit = self.wait_limit(&request);
+        tokio::spawn(async move {
+            let mut conn = match backend
+                .take_blocking(&slot.backend_addr, request.database())
+                .await
+            {
+                Ok(conn) => conn,
+                Err(e) => return request.respond(Err(e)),
+            };
+            let result = tokio::select! {
+                result = conn.call(request.redis()) => result,
+                // the connection is still blocked, so it is closed rather
+                // than put back
+                _ = sleep(limit) => return request.respond(Ok(RedisResp::null())),
+                _ = closed.cancelled() => return,
+            };
+            if result.is_ok() {
+                backend.put_blocking(conn);
+            }
+            request.respond(result);
+        });
+        Ok(())
+    }
+}
+
+async fn sleep(limit: Option<Duration>) {
+    match limit {
+        Some(limit) => tokio::time::sleep(limit).await,
+        None => pending().await,
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use redis::{Frame, RedisResp};
+
+use crate::error::{Error, Result};
+use crate::models::{PendingResponse, Request};
+use crate::proxy::router::Router;
+
+/// How the replies of all masters are merged into one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Merge {
+    /// the first error, or the reply of the first master
+    First,
+    /// integers of the array replies are ANDed one by one, like SCRIPT EXISTS
+    And,
+}
+
+fn merge_of(name: &str, sub: Option<&str>) -> Option<Merge> {
+    match (name, sub) {
+        ("SCRIPT", Some("LOAD" | "FLUSH")) | ("FUNCTION", Some("LOAD")) => Some(Merge::First),
+        ("SCRIPT", Some("EXISTS")) => Some(Merge::And),
+        _ => None,
+    }
+}
+
+/// Addresses of all masters, including the ones which slots are migrated
+/// from.
+fn masters(router: &dyn Router) -> BTreeSet<String> {
+    router
+        .get_slots()
+        .into_iter()
+        .flat_map(|slot| [slot.backend_addr, slot.migrate_from])
+        .filter(|addr| !addr.is_empty())
+        .collect()
+}
+
+/// Sends SCRIPT LOAD/FLUSH/EXISTS or FUNCTION LOAD to all masters, so a
+/// script can be run by EVALSHA on whichever master owns its keys.
+pub fn broadcast(router: &Arc<dyn Router>, request: Request) -> Result<()> {
+    let sub = request
+        .redis()
+        .arg(1)
+        .map(|sub| String::from_utf8_lossy(sub).to_uppercase());
+    let Some(merge) = merge_of(request.name(), sub.as_deref()) else {
+        let name = match sub {
+            Some(sub) => format!("{} {}", request.name(), sub),
+            None => request.name().to_string(),
+        };
+        let msg = format!("ERR command '{}' is not allowed", name);
+        request.respond(Ok(RedisResp::error(msg)));
+        return Ok(());
+    };
+    let addrs = masters(router.as_ref());
+    if addrs.is_empty() {
+        return Err(Error::proxy(anyhow!("no backend is available")));
+    }
+    let pendings = addrs
+        .iter()
+        .map(|addr| {
+            let (id, database) = (request.id(), request.database());
+            let (sub, pending) = Request::new(request.redis().clone(), id, database);
+            if router._dispatch_addr(sub, addr) {
+                pending
+            } else {
+                let e = Error::proxy(anyhow!("backend {} is unavailable", addr));
+                PendingResponse::ready(id, Err(e))
+            }
+        })
+        .collect::<Vec<_>>();
+    tokio::spawn(async move {
+        let mut resps = Vec::with_capacity(pendings.len());
+        for pending in pendings {
+            match pending.wait().await {
+                Ok(response) => resps.push(response.into_redis()),
+                Err(e) => return request.respond(Err(e)),
+            }
+        }
+        request.respond(merge_replies(merge, resps));
+    });
+    Ok(())
+}
+
+fn merge_replies(merge: Merge, resps: Vec<RedisResp>) -> Result<RedisResp> {
+    if let Some(error) = resps.iter().find(|resp| resp.is_error()) {
+        return Ok(error.clone());
+    }
+    let mut resps = resps.into_iter();
+    let first = resps
+        .next()
+        .ok_or_else(|| Error::proxy(anyhow!("no reply from backends")))?;
+    if merge == Merge::First {
+        return Ok(first);
+    }
+    let mut merged = integers(&first)?;
+    for resp in resps {
+        let exists = integers(&resp)?;
+        if exists.len() != merged.len() {
+            return Err(Error::protocol(anyhow!("replies of backends don't match")));
+        }
+        for (merged, exists) in merged.iter_mut().zip(exists) {
+            *merged = (*merged != 0 && exists != 0) as i64;
+        }
+    }
+    Ok(RedisResp::array(
+        merged.into_iter().map(RedisResp::integer).collect(),
+    ))
+}
+
+fn integers(resp: &RedisResp) -> Result<Vec<i64>> {
+    let Frame::Array(items) = resp.frame() else {
+        return Err(Error::protocol(anyhow!("array reply is expected")));
+    };
+    items
+        .iter()
+        .map(|item| match item {
+            Frame::Integer(i) => Ok(*i),
+            _ => Err(Error::protocol(anyhow!("integer reply is expected"))),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exists(items: &[i64]) -> RedisResp {
+        RedisResp::array(items.iter().copied().map(RedisResp::integer).collect())
+    }
+
+    #[test]
+    fn test_merge_replies() {
+        let merged = merge_replies(Merge::And, vec![exists(&[1, 1, 0]), exists(&[1, 0, 1])]);
+        assert_eq!(merged.unwrap(), exists(&[1, 0, 0]));
+        let merged = merge_replies(
+            Merge::First,
+            vec![RedisResp::bulk("sha"), RedisResp::error("ERR oops")],
+        );
+        assert_eq!(merged.unwrap(), RedisResp::error("ERR oops"));
+        assert!(merge_replies(Merge::And, vec![exists(&[1]), exists(&[1, 1])]).is_err());
+    }
+}
@@ -1,5 +1,10 @@
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 
+use anyhow::anyhow;
+use redis::error::RedisError;
+use redis::{RedisRequestReader, RedisResp, RedisResponder};
+use tokio::sync::mpsc::error::TryRecvError;
 use tokio::task::JoinHandle;
 use tokio::{
     net::{
@@ -8,24 +13,44 @@ use tokio::{
     },
     sync::mpsc::{Receiver, Sender},
 };
+use tracing::{debug, warn};
 
-use crate::models::Response;
-
-use crate::error::Result;
-use crate::{
-    error::Error,
-    proxy::{config::Config, router::Router},
-};
+use crate::error::{Error, Result};
+use crate::models::{PendingResponse, Request, Response, PUBSUB_SLOT};
+use crate::proxy::backend::Backend;
+use crate::proxy::server::proxy_metrics::ProxyMetrics;
+use crate::proxy::session::blocking::Blocking;
+use crate::proxy::session::broadcast::broadcast;
+use crate::proxy::session::memory::{SessionMemory, OOM_ERROR};
+use crate::proxy::session::output::OutputBuffer;
+use crate::proxy::session::pubsub::Subscription;
+use crate::proxy::session::transaction::Transaction;
+use crate::proxy::session::CROSSSLOT_ERROR;
+use crate::proxy::{config::ConfigHandle, router::Router};
+use crate::utils::time::with_timeout;
 
 // session 对应的是一个 client 的连接
 pub struct ClientSession {
     router: Arc<dyn Router>,
-    config: Arc<Config>,
+    config: ConfigHandle,
+    backend: Arc<Backend>,
+    metrics: Arc<ProxyMetrics>,
+    database: u32,
+    authorized: bool,
+    quit: bool,
+    transaction: Transaction,
+    blocking: Blocking,
+    // the session is in subscribed mode if it's some
+    subscription: Option<Subscription>,
+    // whether it's subscribed, told to the writer for the output buffer limit
+    subscribed: Arc<AtomicBool>,
 }
 
 pub struct ClientSessionOption {
     pub router: Arc<dyn Router>,
-    pub config: Arc<Config>,
+    pub config: ConfigHandle,
+    pub backend: Arc<Backend>,
+    pub metrics: Arc<ProxyMetrics>,
 }
 
 impl ClientSession {
@@ -33,56 +58,679 @@ impl ClientSession {
         Self {
             router: option.router.clone(),
             config: option.config.clone(),
+            backend: option.backend.clone(),
+            metrics: option.metrics,
+            database: 0,
+            authorized: option.config.load().session.auth.is_empty(),
+            quit: false,
+            transaction: Transaction::new(option.router.clone(), option.backend.clone()),
+            blocking: Blocking::new(option.router, option.backend, option.config),
+            subscription: None,
+            subscribed: Arc::default(),
         }
     }
 
     fn spawn_writer_task(
         &self,
         writer: OwnedWriteHalf,
-        mut response_channel: Receiver<Response>,
+        mut response_channel: Receiver<PendingResponse>,
+        memory: Arc<SessionMemory>,
+        addr: String,
     ) -> JoinHandle<u64> {
+        let send_bufsize = self.config.load().session.send_bufsize as usize;
+        let config = self.config.clone();
+        let metrics = self.metrics.clone();
+        let subscribed = self.subscribed.clone();
         tokio::spawn(async move {
-            let max_id = 0u64;
-            while let Some(response) = response_channel.recv().await {
-                // let mut writer = writer.clone();
-                // let bytes = response.to_bytes();
-                // writer.write_all(&bytes).await?;
-                // writer.flush().await?;
+            let evicted = memory.evicted().clone();
+            // loaded on every use, so a reload applies to the session at once
+            let send_timeout = || config.load().session.send_timeout;
+            let output_limit = || {
+                let subscribed = subscribed.load(Ordering::Relaxed);
+                config.load().session.output_buffer_limit(subscribed)
+            };
+            let exceeded = |e: Error| {
+                warn!("disconnect client {}: {}", addr, e);
+                metrics.output_buffer_evicted();
+            };
+            // replies are counted by the memory once they are completed, so
+            // the ones queued behind a stalled flush are checked as well
+            let output = Mutex::new(OutputBuffer::default());
+            let check_output = || {
+                let mut output = output.lock().unwrap();
+                let (len, limit) = (memory.output(), output_limit());
+                output.check(len, &limit)?;
+                Ok::<_, Error>(output.soft_deadline(len, &limit))
+            };
+            let mut max_id = 0u64;
+            let mut responder = RedisResponder::with_capacity(writer, send_bufsize);
+            // bytes of the requests and responses, and of the responses
+            // alone, fed but not flushed yet
+            let (mut held, mut fed) = (0u64, 0u64);
+            let write = async {
+                let mut next = response_channel.recv().await;
+                while let Some(pending) = next {
+                    max_id = max_id.max(pending.id());
+                    let op = pending.op();
+                    let request_len = pending.request_len();
+                    let (result, counted) = pending.wait_counted().await;
+                    let result = result.map(Response::into_redis);
+                    if let Some((command, started)) = op {
+                        metrics.record(command, started.elapsed(), &result);
+                    }
+                    let resp = match result {
+                        Ok(resp) => resp,
+                        Err(e)
+                            if config.load().session.break_on_failure && e.is_backend_failure() =>
+                        {
+                            debug!("close session on backend failure: {}", e);
+                            break;
+                        }
+                        Err(e) => e.to_resp(),
+                    };
+                    held += request_len + counted;
+                    fed += counted;
+                    if let Err(e) = check_output() {
+                        exceeded(e);
+                        return max_id;
+                    }
+                    responder.feed_response(&resp);
+                    // flush only when there is no more response ready, so the
+                    // responses of a pipeline are written together
+                    next = match response_channel.try_recv() {
+                        Ok(pending) => Some(pending),
+                        Err(TryRecvError::Empty) => {
+                            let flushed = with_timeout(send_timeout(), async {
+                                Ok(responder.flush().await?)
+                            })
+                            .await;
+                            if let Err(e) = flushed {
+                                debug!("write response failed: {}", e);
+                                return max_id;
+                            }
+                            memory.release(held);
+                            memory.written(fed);
+                            (held, fed) = (0, 0);
+                            response_channel.recv().await
+                        }
+                        Err(TryRecvError::Disconnected) => None,
+                    };
+                }
+                // responses before a failure are still written
+                let _ =
+                    with_timeout(send_timeout(), async { Ok(responder.shutdown().await?) }).await;
+                max_id
+            };
+            // the client may not read fast enough, checked whenever replies
+            // are completed or written, or the soft period is over
+            let watch = async {
+                loop {
+                    let changed = memory.output_changed();
+                    match check_output() {
+                        Err(e) => return e,
+                        Ok(Some(deadline)) => tokio::select! {
+                            _ = changed => {}
+                            _ = tokio::time::sleep_until(deadline) => {}
+                        },
+                        Ok(None) => changed.await,
+                    }
+                }
+            };
+            tokio::select! {
+                max_id = write => max_id,
+                e = watch => {
+                    exceeded(e);
+                    0
+                }
+                // the connection is closed once the writer is dropped, the
+                // pending responses are dropped as well
+                _ = evicted.cancelled() => 0,
             }
-            max_id
         })
     }
 
-    fn spawn_reader_task(
-        &self,
+    async fn read_requests(
+        &mut self,
         client_reader: OwnedReadHalf,
-        mut response_channel: Sender<Response>,
-    ) -> JoinHandle<u64> {
-        tokio::spawn(async move {
-            let mut max_id = 0u64;
-            let mut request_reader = redis::RedisRequestReader::new(client_reader);
-            loop {
-                match request_reader.read_request().await {
-                    Ok(request) => {
-                        //max_id = request.get_id();
-                        //let mut response = Response::new();
-                        //self.router.dispatch(&request, &mut response);
-                        // response_channel.send(response).await?;
+        response_channel: Sender<PendingResponse>,
+        memory: &Arc<SessionMemory>,
+    ) -> Result<u64> {
+        let recv_bufsize = self.config.load().session.recv_bufsize as usize;
+        let mut request_reader = RedisRequestReader::with_capacity(client_reader, recv_bufsize);
+        let mut max_id = 0u64;
+        while !self.quit {
+            let (recv_timeout, max_offheap_bytes) = {
+                let config = self.config.load();
+                (config.session.recv_timeout, config.proxy.max_offheap_bytes)
+            };
+            let read = tokio::select! {
+                read = with_timeout(recv_timeout, async {
+                    Ok(request_reader.read_request().await)
+                }) => read?,
+                // the writer exits on a failure, stop reading as well
+                _ = response_channel.closed() => break,
+                _ = memory.evicted().cancelled() => break,
+            };
+            let cmd = match read {
+                Ok(cmd) => cmd,
+                Err(RedisError::NoMoreData) => break,
+                Err(e) => return Err(e.into()),
+            };
+            max_id += 1;
+            let (request, pending) = Request::metered(cmd, max_id, self.database, memory.clone());
+            let offheap = self.metrics.offheap();
+            // QUIT is still served, so the client may leave gracefully
+            let pending = if request.name() != "QUIT" && !offheap.admit(max_offheap_bytes) {
+                pending.replace(Ok(RedisResp::error(OOM_ERROR)))
+            } else {
+                let handled = self
+                    .handle_subscribed(request, &response_channel, memory)
+                    .await;
+                self.subscribed
+                    .store(self.subscription.is_some(), Ordering::Relaxed);
+                match handled {
+                    Ok(Some(request)) => match self.handle_request(request) {
+                        Ok(()) => pending,
+                        Err(e) => pending.replace(Err(e)),
+                    },
+                    // replies are pushed by the subscription
+                    Ok(None) => continue,
+                    Err(e) => pending.replace(Err(e)),
+                }
+            };
+            memory.charge(pending.request_len());
+            if response_channel.send(pending).await.is_err() {
+                break;
+            }
+        }
+        Ok(max_id)
+    }
+
+    /// Handles the request if the session is or is going to be subscribed,
+    /// otherwise gives it back.
+    async fn handle_subscribed(
+        &mut self,
+        request: Request,
+        responses: &Sender<PendingResponse>,
+        memory: &Arc<SessionMemory>,
+    ) -> Result<Option<Request>> {
+        if !self.authorized || self.transaction.in_multi() {
+            return Ok(Some(request));
+        }
+        let subscribing = is_subscription(request.name());
+        if self.subscription.is_none() {
+            if !subscribing {
+                return Ok(Some(request));
+            }
+            let addr = self.router.get_slot(PUBSUB_SLOT).backend_addr;
+            if addr.is_empty() {
+                return Err(Error::proxy(anyhow!(
+                    "slot-{:04} is not ready",
+                    PUBSUB_SLOT
+                )));
+            }
+            self.subscription = Some(Subscription::open(
+                self.backend.clone(),
+                addr,
+                responses.clone(),
+                memory.clone(),
+            ));
+        }
+        let Some(subscription) = &self.subscription else {
+            return Ok(Some(request));
+        };
+        if subscribing || request.name() == "PING" {
+            if let Err(e) = subscription.send(request) {
+                self.subscription = None;
+                return Err(e);
+            }
+            return Ok(None);
+        }
+        if request.name() == "QUIT" {
+            return Ok(Some(request));
+        }
+        match subscription.subscriptions().await {
+            Ok(count) if count > 0 => {
+                let msg = format!(
+                    "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT are allowed in this context",
+                    request.name().to_lowercase()
+                );
+                let pending = PendingResponse::metered(
+                    request.id(),
+                    Ok(RedisResp::error(msg)),
+                    memory.clone(),
+                );
+                let _ = responses.send(pending).await;
+                Ok(None)
+            }
+            // all unsubscribed, back to the normal mode
+            _ => {
+                self.subscription = None;
+                Ok(Some(request))
+            }
+        }
+    }
+
+    fn handle_request(&mut self, request: Request) -> Result<()> {
+        if !self.authorized && !matches!(request.name(), "AUTH" | "QUIT") {
+            request.respond(Ok(RedisResp::error("NOAUTH Authentication required.")));
+            return Ok(());
+        }
+        match request.name() {
+            "QUIT" => {
+                self.quit = true;
+                request.respond(Ok(RedisResp::ok()));
+                Ok(())
+            }
+            "AUTH" => self.handle_auth(request),
+            "MULTI" => self.transaction.multi(request),
+            "EXEC" => self.transaction.exec(request),
+            "DISCARD" => self.transaction.discard(request),
+            "WATCH" => self.transaction.watch(request),
+            "UNWATCH" => self.transaction.unwatch(request),
+            _ if self.transaction.in_multi() => self.transaction.queue(request),
+            "SELECT" => self.handle_select(request),
+            "PING" if request.redis().len() == 1 => {
+                request.respond(Ok(RedisResp::simple("PONG")));
+                Ok(())
+            }
+            _ => {
+                if let Some(command) = request.command() {
+                    if command.is_not_allowed() {
+                        let msg = format!("ERR command '{}' is not allowed", request.name());
+                        request.respond(Ok(RedisResp::error(msg)));
+                        return Ok(());
                     }
-                    Err(e) => {
-                        // error!("read request error: {:?}", e);
+                    if command.is_same_slot() && request.is_cross_slot() {
+                        request.respond(Ok(RedisResp::error(CROSSSLOT_ERROR)));
+                        return Ok(());
                     }
+                    if command.is_broadcast() {
+                        return broadcast(&self.router, request);
+                    }
+                }
+                if request.is_blocking() {
+                    return self.blocking.execute(request);
+                }
+                if request.command().is_some_and(|c| c.is_pubsub()) {
+                    return self.router._dispatch_slot(request, PUBSUB_SLOT);
                 }
+                self.router.dispatch(request)
             }
-            max_id
-        })
+        }
     }
 
-    pub(crate) fn serve_client(&self, conn: TcpStream) -> Result<()> {
-        let (client_reader, client_writer) = conn.into_split();
-        let (writer_sender, writer_receiver) = tokio::sync::mpsc::channel(1024);
-        self.spawn_reader_task(client_reader, writer_sender);
-        self.spawn_writer_task(client_writer, writer_receiver);
+    fn handle_auth(&mut self, request: Request) -> Result<()> {
+        let resp = match request.redis().args() {
+            [_, password] => {
+                let auth = &self.config.load().session.auth;
+                if auth.is_empty() {
+                    RedisResp::error("ERR Client sent AUTH, but no password is set")
+                } else if password[..] == *auth.as_bytes() {
+                    self.authorized = true;
+                    RedisResp::ok()
+                } else {
+                    self.authorized = false;
+                    RedisResp::error("ERR invalid password")
+                }
+            }
+            _ => RedisResp::error("ERR wrong number of arguments for 'auth' command"),
+        };
+        request.respond(Ok(resp));
         Ok(())
     }
+
+    fn handle_select(&mut self, request: Request) -> Result<()> {
+        let database = match request.redis().args() {
+            [_, db] => std::str::from_utf8(db)
+                .ok()
+                .and_then(|db| db.parse::<u32>().ok()),
+            _ => {
+                let msg = "ERR wrong number of arguments for 'select' command";
+                request.respond(Ok(RedisResp::error(msg)));
+                return Ok(());
+            }
+        };
+        let resp = match database {
+            Some(db) if db < self.config.load().backend.number_databases.max(1) => {
+                self.database = db;
+                RedisResp::ok()
+            }
+            _ => RedisResp::error("ERR invalid DB index"),
+        };
+        request.respond(Ok(resp));
+        Ok(())
+    }
+
+    /// Serves the client until it quits or the connection is broken.
+    pub(crate) async fn serve_client(mut self, conn: TcpStream) -> Result<()> {
+        let addr = conn
+            .peer_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_default();
+        let memory = self.metrics.offheap().register(&addr);
+        let (client_reader, client_writer) = conn.into_split();
+        let max_pipeline = self.config.load().session.max_pipeline.max(1) as usize;
+        let (writer_sender, writer_receiver) = tokio::sync::mpsc::channel(max_pipeline);
+        let writer = self.spawn_writer_task(client_writer, writer_receiver, memory.clone(), addr);
+        let result = self
+            .read_requests(client_reader, writer_sender, &memory)
+            .await;
+        if !self.quit {
+            // the client is gone, don't keep backend blocked for it
+            self.blocking.close();
+        }
+        // the subscription holds a sender of the writer
+        self.subscription = None;
+        // the writer exits after all pending responses are written
+        let _ = writer.await;
+        result.map(|_| ())
+    }
+}
+
+fn is_subscription(name: &str) -> bool {
+    matches!(
+        name,
+        "SUBSCRIBE" | "PSUBSCRIBE" | "SSUBSCRIBE" | "UNSUBSCRIBE" | "PUNSUBSCRIBE" | "SUNSUBSCRIBE"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use redis::RedisResp;
+
+    use crate::proxy::config::Config;
+    use crate::proxy::server::proxy_metrics::ProxyMetrics;
+    use crate::proxy::session::memory::OOM_ERROR;
+    use crate::utils::testing::{
+        start_cluster_session, start_session, start_session_with_metrics, FakeBackend,
+    };
+
+    #[tokio::test]
+    async fn test_transaction() {
+        let server = FakeBackend::start().await;
+        let mut client = start_session(Config::default(), &server.addr).await;
+
+        assert_eq!(
+            client.call(&["WATCH", "{a}x"]).await,
+            RedisResp::bulk("WATCH {a}x")
+        );
+        assert_eq!(
+            client.call(&["GET", "{a}y"]).await,
+            RedisResp::bulk("GET {a}y")
+        );
+        assert_eq!(client.call(&["MULTI"]).await, RedisResp::ok());
+        let queued = RedisResp::simple("QUEUED");
+        assert_eq!(client.call(&["SET", "{a}x", "1"]).await, queued);
+        assert_eq!(client.call(&["INCR", "{a}z"]).await, queued);
+        assert_eq!(
+            client.call(&["EXEC"]).await,
+            RedisResp::array(vec![
+                RedisResp::bulk("SET {a}x 1"),
+                RedisResp::bulk("INCR {a}z"),
+            ])
+        );
+
+        let commands = server.commands();
+        let conn = |line: &str| commands.iter().find(|(_, cmd)| cmd == line).unwrap().0;
+        for line in ["MULTI", "SET {a}x 1", "INCR {a}z", "EXEC"] {
+            assert_eq!(conn(line), conn("WATCH {a}x"));
+        }
+        assert_ne!(conn("GET {a}y"), conn("WATCH {a}x"));
+    }
+
+    #[tokio::test]
+    async fn test_transaction_errors() {
+        let server = FakeBackend::start().await;
+        let mut client = start_session(Config::default(), &server.addr).await;
+
+        let resp = client.call(&["EXEC"]).await;
+        assert_eq!(resp.error_message(), Some("ERR EXEC without MULTI"));
+
+        assert_eq!(client.call(&["MULTI"]).await, RedisResp::ok());
+        assert_eq!(
+            client.call(&["SET", "a", "1"]).await,
+            RedisResp::simple("QUEUED")
+        );
+        let resp = client.call(&["SET", "b", "1"]).await;
+        assert!(resp.error_message().unwrap().starts_with("CROSSSLOT"));
+        let resp = client.call(&["EXEC"]).await;
+        assert!(resp.error_message().unwrap().starts_with("EXECABORT"));
+        assert!(server.commands().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_blocking_command() {
+        let server = FakeBackend::start().await;
+        let mut config = Config::default();
+        config.backend.blocking_pool_size = 1;
+        config.backend.blocking_timeout = Some(Duration::from_millis(200));
+        let mut client = start_session(config, &server.addr).await;
+
+        let brpop = ["BRPOP", "q", "0.05"];
+        assert_eq!(client.call(&brpop).await, RedisResp::bulk("BRPOP q 0.05"));
+        assert_eq!(client.call(&brpop).await, RedisResp::bulk("BRPOP q 0.05"));
+        assert_eq!(client.call(&["GET", "q"]).await, RedisResp::bulk("GET q"));
+        // proxy replies nil once the blocking timeout is reached
+        assert_eq!(client.call(&["BRPOP", "q", "0"]).await, RedisResp::null());
+        assert_eq!(client.call(&brpop).await, RedisResp::bulk("BRPOP q 0.05"));
+
+        let conns = server
+            .commands()
+            .into_iter()
+            .map(|(conn, _)| conn)
+            .collect::<Vec<_>>();
+        // the first connection is reused, the timed out one is closed
+        assert_eq!(conns[0], conns[1]);
+        assert_ne!(conns[0], conns[2]);
+        assert_eq!(conns[0], conns[3]);
+        assert!(!conns[..4].contains(&conns[4]));
+    }
+
+    #[tokio::test]
+    async fn test_pubsub() {
+        let server = FakeBackend::start().await;
+        let mut subscriber = start_session(Config::default(), &server.addr).await;
+        let mut publisher = start_session(Config::default(), &server.addr).await;
+        let push = |items: &[&str], count: Option<i64>| {
+            let items = items.iter().map(|item| RedisResp::bulk(item.to_string()));
+            RedisResp::array(items.chain(count.map(RedisResp::integer)).collect())
+        };
+
+        assert_eq!(
+            subscriber.call(&["SUBSCRIBE", "news"]).await,
+            push(&["subscribe", "news"], Some(1))
+        );
+        let resp = subscriber.call(&["GET", "a"]).await;
+        assert!(resp
+            .error_message()
+            .unwrap()
+            .starts_with("ERR Can't execute 'get'"));
+        assert_eq!(
+            publisher.call(&["PUBLISH", "news", "hello"]).await,
+            RedisResp::integer(1)
+        );
+        assert_eq!(
+            subscriber.recv().await.unwrap(),
+            push(&["message", "news", "hello"], None)
+        );
+        assert_eq!(subscriber.call(&["PING"]).await, RedisResp::simple("PONG"));
+        assert_eq!(
+            subscriber.call(&["UNSUBSCRIBE", "news"]).await,
+            push(&["unsubscribe", "news"], Some(0))
+        );
+        // back to the normal mode once all unsubscribed
+        assert_eq!(
+            subscriber.call(&["GET", "a"]).await,
+            RedisResp::bulk("GET a")
+        );
+
+        assert_eq!(subscriber.call(&["MULTI"]).await, RedisResp::ok());
+        let resp = subscriber.call(&["SUBSCRIBE", "news"]).await;
+        assert!(resp.error_message().unwrap().contains("not allowed"));
+    }
+
+    #[tokio::test]
+    async fn test_scripting() {
+        let servers = [FakeBackend::start().await, FakeBackend::start().await];
+        let addrs = [servers[0].addr.as_str(), servers[1].addr.as_str()];
+        let mut client = start_cluster_session(Config::default(), &addrs).await;
+
+        let load = ["SCRIPT", "LOAD", "return 1"];
+        assert_eq!(
+            client.call(&load).await,
+            RedisResp::bulk("SCRIPT LOAD return 1")
+        );
+        for server in &servers {
+            assert_eq!(server.commands()[0].1, "SCRIPT LOAD return 1");
+        }
+        let resp = client.call(&["SCRIPT", "KILL"]).await;
+        assert_eq!(
+            resp.error_message(),
+            Some("ERR command 'SCRIPT KILL' is not allowed")
+        );
+
+        let resp = client.call(&["EVALSHA", "sha", "2", "a", "b"]).await;
+        assert!(resp.error_message().unwrap().starts_with("CROSSSLOT"));
+        assert_eq!(
+            client.call(&["EVALSHA", "sha", "2", "{a}1", "{a}2"]).await,
+            RedisResp::bulk("EVALSHA sha 2 {a}1 {a}2")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_break_on_failure() {
+        // nothing listens on the address once the listener is dropped
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let mut client = start_session(Config::default(), &addr).await;
+        let resp = client.call(&["GET", "a"]).await;
+        assert!(resp.error_message().unwrap().starts_with("IOERR"));
+        assert_eq!(client.call(&["PING"]).await, RedisResp::simple("PONG"));
+
+        let mut config = Config::default();
+        config.session.break_on_failure = true;
+        let mut client = start_session(config, &addr).await;
+        client.send(&["GET", "a"]).await.unwrap();
+        assert!(client.recv().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_max_offheap_bytes() {
+        let server = FakeBackend::start().await;
+        let metrics = Arc::<ProxyMetrics>::default();
+        let config = || {
+            let mut config = Config::default();
+            config.proxy.max_offheap_bytes = Some(512);
+            config
+        };
+        let addrs = [server.addr.as_str()];
+        let mut hog = start_session_with_metrics(config(), &addrs, metrics.clone()).await;
+        let mut client = start_session_with_metrics(config(), &addrs, metrics.clone()).await;
+        assert_eq!(client.call(&["GET", "a"]).await, RedisResp::bulk("GET a"));
+
+        // the request is held until BRPOP is answered, which is never
+        let key = "k".repeat(1024);
+        hog.send(&["BRPOP", &key, "0"]).await.unwrap();
+        while metrics.offheap_snapshot().used < 1024 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let resp = client.call(&["GET", "a"]).await;
+        assert_eq!(resp.error_message(), Some(OOM_ERROR));
+        assert!(hog.recv().await.is_err());
+        while metrics.offheap_snapshot().used > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(client.call(&["GET", "a"]).await, RedisResp::bulk("GET a"));
+        let offheap = metrics.offheap_snapshot();
+        assert_eq!((offheap.rejected, offheap.evicted), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_offheap_of_stalled_client() {
+        let server = FakeBackend::start().await;
+        let metrics = Arc::<ProxyMetrics>::default();
+        let config = || {
+            let mut config = Config::default();
+            config.proxy.max_offheap_bytes = Some(1 << 20);
+            config.session.max_pipeline = 1024;
+            config
+        };
+        let addrs = [server.addr.as_str()];
+        // the subscriber never reads, its flush stalls once the socket is full
+        let mut subscriber = start_session_with_metrics(config(), &addrs, metrics.clone()).await;
+        subscriber.call(&["SUBSCRIBE", "news"]).await;
+        let mut publisher = start_session_with_metrics(config(), &addrs, metrics.clone()).await;
+        let message = "m".repeat(64 * 1024);
+        // pushes completed behind the stalled flush are counted as well
+        let published = async {
+            while publisher
+                .call(&["PUBLISH", "news", &message])
+                .await
+                .error_message()
+                != Some(OOM_ERROR)
+            {}
+        };
+        tokio::time::timeout(Duration::from_secs(10), published)
+            .await
+            .unwrap();
+        assert_eq!(metrics.offheap_snapshot().evicted, 1);
+        let closed = async { while subscriber.recv().await.is_ok() {} };
+        tokio::time::timeout(Duration::from_secs(10), closed)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_output_buffer_limit() {
+        let server = FakeBackend::start().await;
+        let metrics = Arc::<ProxyMetrics>::default();
+        let addrs = [server.addr.as_str()];
+        let config = || {
+            let mut config = Config::default();
+            config.session.output_buffer_limit_normal = "2kb 0 0".parse().unwrap();
+            config.session.output_buffer_limit_pubsub = "0 1kb 0".parse().unwrap();
+            config
+        };
+        let mut client = start_session_with_metrics(config(), &addrs, metrics.clone()).await;
+        assert_eq!(client.call(&["GET", "a"]).await, RedisResp::bulk("GET a"));
+        let key = "k".repeat(4096);
+        client.send(&["GET", &key]).await.unwrap();
+        assert!(client.recv().await.is_err());
+
+        // the pubsub class applies once subscribed
+        let mut subscriber = start_session_with_metrics(config(), &addrs, metrics.clone()).await;
+        let mut publisher = start_session_with_metrics(config(), &addrs, metrics.clone()).await;
+        subscriber.call(&["GET", &"k".repeat(1500)]).await;
+        subscriber.call(&["SUBSCRIBE", "news"]).await;
+        let message = "m".repeat(1500);
+        publisher.call(&["PUBLISH", "news", &message]).await;
+        assert!(subscriber.recv().await.is_err());
+        assert_eq!(metrics.sessions().output_buffer_evicted, 2);
+
+        // pushes queued behind a stalled flush count toward the hard limit
+        let mut config = config();
+        config.session.output_buffer_limit_pubsub = "256kb 0 0".parse().unwrap();
+        config.session.max_pipeline = 1024;
+        let mut subscriber = start_session_with_metrics(config, &addrs, metrics.clone()).await;
+        subscriber.call(&["SUBSCRIBE", "news"]).await;
+        let message = "m".repeat(64 * 1024);
+        let published = async {
+            while metrics.sessions().output_buffer_evicted < 3 {
+                publisher.call(&["PUBLISH", "news", &message]).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(10), published)
+            .await
+            .unwrap();
+        let closed = async { while subscriber.recv().await.is_ok() {} };
+        tokio::time::timeout(Duration::from_secs(10), closed)
+            .await
+            .unwrap();
+    }
 }
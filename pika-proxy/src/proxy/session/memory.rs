@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+use serde::Serialize;
+use tokio::sync::futures::Notified;
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::models::ReplyMeter;
+
+pub const OOM_ERROR: &str = "OOM command not allowed when used memory > 'max_offheap_bytes'";
+
+/// Offheap counts the bytes of requests and responses held by all sessions,
+/// a request is held from when it's read until its response is written, and
+/// a response from when it's completed until it's written.
+#[derive(Debug, Default)]
+pub struct Offheap {
+    used: AtomicU64,
+    rejected: AtomicU64,
+    evicted: AtomicU64,
+    next_id: AtomicU64,
+    sessions: Mutex<HashMap<u64, Weak<SessionMemory>>>,
+}
+
+/// `offheap` in `/api/proxy/stats`.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct OffheapSnapshot {
+    pub used: u64,
+    pub rejected: u64,
+    pub evicted: u64,
+}
+
+impl Offheap {
+    /// Tracks the memory of the session of client `addr`, it's released
+    /// once the session memory is dropped.
+    pub fn register(self: &Arc<Self>, addr: &str) -> Arc<SessionMemory> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let memory = Arc::new(SessionMemory {
+            id,
+            addr: addr.to_string(),
+            used: AtomicU64::new(0),
+            output: AtomicU64::new(0),
+            output_changed: Notify::new(),
+            offheap: self.clone(),
+            evicted: CancellationToken::new(),
+        });
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(id, Arc::downgrade(&memory));
+        memory
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Returns false if `limit` is exceeded, the request is counted as
+    /// rejected and the session holding the most memory is evicted, unless
+    /// the ones evicted already bring it back under the limit.
+    pub fn admit(&self, limit: Option<u64>) -> bool {
+        let Some(limit) = limit else {
+            return true;
+        };
+        if self.used() <= limit {
+            return true;
+        }
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+        // upgraded out of the lock, the last reference may be dropped here
+        let alive = {
+            let sessions = self.sessions.lock().unwrap();
+            sessions
+                .values()
+                .filter_map(Weak::upgrade)
+                .filter(|session| !session.evicted.is_cancelled())
+                .collect::<Vec<_>>()
+        };
+        if alive.iter().map(|session| session.used()).sum::<u64>() <= limit {
+            return false;
+        }
+        if let Some(largest) = alive.iter().max_by_key(|session| session.used()) {
+            warn!(
+                "disconnect client {} holding {} bytes, offheap memory {} exceeds {}",
+                largest.addr,
+                largest.used(),
+                self.used(),
+                limit
+            );
+            self.evicted.fetch_add(1, Ordering::Relaxed);
+            largest.evicted.cancel();
+        }
+        false
+    }
+
+    pub fn snapshot(&self) -> OffheapSnapshot {
+        OffheapSnapshot {
+            used: self.used(),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            evicted: self.evicted.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// SessionMemory is the part of the offheap memory held by a session.
+#[derive(Debug)]
+pub struct SessionMemory {
+    id: u64,
+    addr: String,
+    used: AtomicU64,
+    // bytes of the replies completed but not written yet
+    output: AtomicU64,
+    output_changed: Notify,
+    offheap: Arc<Offheap>,
+    evicted: CancellationToken,
+}
+
+impl SessionMemory {
+    pub fn charge(&self, bytes: u64) {
+        self.used.fetch_add(bytes, Ordering::Relaxed);
+        self.offheap.used.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn release(&self, bytes: u64) {
+        self.used.fetch_sub(bytes, Ordering::Relaxed);
+        self.offheap.used.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Bytes of the replies completed but not written to the client yet.
+    pub fn output(&self) -> u64 {
+        self.output.load(Ordering::Relaxed)
+    }
+
+    /// Replies of `bytes` are written, they are released by the caller.
+    pub fn written(&self, bytes: u64) {
+        self.output.fetch_sub(bytes, Ordering::Relaxed);
+        self.output_changed.notify_waiters();
+    }
+
+    /// Resolves once `output` is changed after it's called.
+    pub fn output_changed(&self) -> Notified<'_> {
+        self.output_changed.notified()
+    }
+
+    /// Cancelled once the session is chosen to be disconnected.
+    pub fn evicted(&self) -> &CancellationToken {
+        &self.evicted
+    }
+}
+
+/// Replies are held from when they are completed until they are written.
+impl ReplyMeter for SessionMemory {
+    fn count(&self, bytes: u64) {
+        self.charge(bytes);
+        self.output.fetch_add(bytes, Ordering::Relaxed);
+        self.output_changed.notify_waiters();
+    }
+}
+
+impl Drop for SessionMemory {
+    fn drop(&mut self) {
+        self.offheap
+            .used
+            .fetch_sub(*self.used.get_mut(), Ordering::Relaxed);
+        self.offheap.sessions.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offheap() {
+        let offheap = Arc::new(Offheap::default());
+        let small = offheap.register("127.0.0.1:1");
+        let large = offheap.register("127.0.0.1:2");
+        small.charge(10);
+        large.charge(100);
+        large.release(20);
+        assert_eq!(offheap.used(), 90);
+        assert!(offheap.admit(None));
+        assert!(offheap.admit(Some(90)));
+
+        assert!(!offheap.admit(Some(50)));
+        assert!(large.evicted().is_cancelled());
+        assert!(!small.evicted().is_cancelled());
+        // the evicted one is going to release its memory
+        assert!(!offheap.admit(Some(50)));
+        assert!(!small.evicted().is_cancelled());
+        assert_eq!(
+            offheap.snapshot(),
+            OffheapSnapshot {
+                used: 90,
+                rejected: 2,
+                evicted: 1,
+            }
+        );
+
+        drop(large);
+        assert_eq!(offheap.used(), 10);
+        assert!(offheap.admit(Some(50)));
+        drop(small);
+        assert_eq!(offheap.used(), 0);
+        assert!(offheap.sessions.lock().unwrap().is_empty());
+    }
+}
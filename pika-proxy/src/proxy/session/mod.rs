@@ -1,2 +1,10 @@
+mod blocking;
+mod broadcast;
 pub mod client_session;
+pub mod memory;
+mod output;
+mod pubsub;
 pub mod server_session;
+mod transaction;
+
+const CROSSSLOT_ERROR: &str = "CROSSSLOT Keys in request don't hash to the same slot";
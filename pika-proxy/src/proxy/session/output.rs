@@ -0,0 +1,83 @@
+use anyhow::anyhow;
+use tokio::time::Instant;
+
+use crate::error::{Error, Result};
+use crate::proxy::config::OutputBufferLimit;
+
+/// OutputBuffer checks the bytes of replies not written to a client yet
+/// against the limit, and tracks since when they are over the soft limit.
+#[derive(Debug, Default)]
+pub struct OutputBuffer {
+    over_soft_since: Option<Instant>,
+}
+
+impl OutputBuffer {
+    /// Fails if `len` bytes exceed the hard limit, or have been over the
+    /// soft limit for the soft period.
+    pub fn check(&mut self, len: u64, limit: &OutputBufferLimit) -> Result<()> {
+        if let Some(hard) = limit.hard.filter(|hard| len > *hard) {
+            return Err(Error::proxy(anyhow!(
+                "output buffer {} bytes exceeds the hard limit {}",
+                len,
+                hard
+            )));
+        }
+        match self.soft_deadline(len, limit) {
+            Some(deadline) if deadline <= Instant::now() => Err(Error::proxy(anyhow!(
+                "output buffer {} bytes exceeds the soft limit {} for {:?}",
+                len,
+                limit.soft.unwrap_or_default(),
+                limit.soft_period
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// When `len` bytes have been over the soft limit for the soft period,
+    /// `None` if it's not over the soft limit.
+    pub fn soft_deadline(&mut self, len: u64, limit: &OutputBufferLimit) -> Option<Instant> {
+        match limit.soft {
+            Some(soft) if len > soft => {
+                let since = *self.over_soft_since.get_or_insert_with(Instant::now);
+                Some(since + limit.soft_period)
+            }
+            _ => {
+                self.over_soft_since = None;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_output_buffer() {
+        let limit = OutputBufferLimit {
+            hard: Some(100),
+            soft: Some(10),
+            soft_period: Duration::from_millis(100),
+        };
+        let mut buffer = OutputBuffer::default();
+        buffer.check(10, &limit).unwrap();
+        assert!(buffer.soft_deadline(10, &limit).is_none());
+        buffer.check(20, &limit).unwrap();
+        let deadline = buffer.soft_deadline(20, &limit).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        buffer.check(30, &limit).unwrap();
+        assert_eq!(buffer.soft_deadline(30, &limit), Some(deadline));
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(buffer.check(40, &limit).is_err());
+
+        // back under the soft limit, the period starts over
+        buffer.check(0, &limit).unwrap();
+        buffer.check(20, &limit).unwrap();
+        assert!(buffer.soft_deadline(20, &limit).unwrap() > deadline);
+        assert!(buffer.check(101, &limit).is_err());
+        assert!(buffer.check(1000, &OutputBufferLimit::default()).is_ok());
+    }
+}
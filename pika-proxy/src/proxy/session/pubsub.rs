@@ -0,0 +1,156 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use redis::{Frame, RedisCmd, RedisResp};
+use tokio::sync::mpsc::{unbounded_channel, Sender, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+
+use crate::error::{Error, Result};
+use crate::models::{PendingResponse, ReplyMeter, Request};
+use crate::proxy::backend::Backend;
+
+enum Message {
+    Command(Request),
+    // answered with the number of subscriptions once all replies before it
+    // are relayed
+    Barrier(oneshot::Sender<i64>),
+}
+
+enum Pong {
+    Client,
+    Barrier(oneshot::Sender<i64>),
+}
+
+/// Subscription is the backend connection of a subscribed session, replies
+/// and messages read from it are pushed to the session's writer in order.
+/// The connection is closed once the subscription is dropped.
+pub struct Subscription {
+    sender: UnboundedSender<Message>,
+}
+
+impl Subscription {
+    /// Pushes are counted by `meter` of the session once they are read.
+    pub fn open(
+        backend: Arc<Backend>,
+        addr: String,
+        responses: Sender<PendingResponse>,
+        meter: Arc<dyn ReplyMeter>,
+    ) -> Self {
+        let (sender, messages) = unbounded_channel();
+        tokio::spawn(async move {
+            if let Err(e) = relay(&backend, &addr, messages, &responses, &meter).await {
+                let pending = PendingResponse::metered(0, Err(e), meter);
+                let _ = responses.send(pending).await;
+            }
+        });
+        Self { sender }
+    }
+
+    /// Sends a command whose replies are pushed to the session.
+    pub fn send(&self, request: Request) -> Result<()> {
+        self.sender
+            .send(Message::Command(request))
+            .map_err(|_| closed())
+    }
+
+    /// Returns the number of channels and patterns subscribed, after the
+    /// replies of the commands sent are relayed.
+    pub async fn subscriptions(&self) -> Result<i64> {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(Message::Barrier(tx))
+            .map_err(|_| closed())?;
+        rx.await.map_err(|_| closed())
+    }
+}
+
+fn closed() -> Error {
+    Error::network(anyhow!("subscription connection is closed"))
+}
+
+async fn relay(
+    backend: &Backend,
+    addr: &str,
+    mut messages: UnboundedReceiver<Message>,
+    responses: &Sender<PendingResponse>,
+    meter: &Arc<dyn ReplyMeter>,
+) -> Result<()> {
+    let (mut requester, mut reader) = backend.subscription_connection(addr).await?;
+    // the replies of PING are in order, barriers are told apart from the
+    // PINGs of the client by this queue
+    let (pongs_tx, mut pongs_rx) = unbounded_channel();
+    let writer = async {
+        while let Some(message) = messages.recv().await {
+            let cmd = match message {
+                Message::Command(request) => {
+                    if request.name() == "PING" {
+                        let _ = pongs_tx.send(Pong::Client);
+                    }
+                    request.redis().clone()
+                }
+                Message::Barrier(tx) => {
+                    let _ = pongs_tx.send(Pong::Barrier(tx));
+                    RedisCmd::new(["PING"])
+                }
+            };
+            requester.send_request(&cmd).await?;
+        }
+        Ok::<_, Error>(())
+    };
+    let relay = async {
+        // channels and patterns, and sharded channels, counted separately by
+        // redis
+        let (mut count, mut shard_count) = (0, 0);
+        loop {
+            let resp = reader.read_response().await?;
+            match reply_kind(&resp).as_deref() {
+                Some("pong") => {
+                    if let Ok(Pong::Barrier(tx)) = pongs_rx.try_recv() {
+                        let _ = tx.send(count + shard_count);
+                        continue;
+                    }
+                }
+                Some("subscribe" | "unsubscribe" | "psubscribe" | "punsubscribe") => {
+                    count = reply_count(&resp).unwrap_or(count);
+                }
+                Some("ssubscribe" | "sunsubscribe") => {
+                    shard_count = reply_count(&resp).unwrap_or(shard_count);
+                }
+                _ => {}
+            }
+            if responses
+                .send(PendingResponse::metered(0, Ok(resp), meter.clone()))
+                .await
+                .is_err()
+            {
+                return Ok(());
+            }
+        }
+    };
+    tokio::select! {
+        result = writer => result,
+        result = relay => result,
+    }
+}
+
+/// The kind of a push like `message`, or `pong` for the reply of PING.
+fn reply_kind(resp: &RedisResp) -> Option<String> {
+    match resp.frame() {
+        Frame::SimpleString(s) if s.eq_ignore_ascii_case(b"PONG") => Some("pong".to_string()),
+        Frame::Array(items) => match items.first() {
+            Some(Frame::BulkString(kind)) => Some(String::from_utf8_lossy(kind).to_lowercase()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn reply_count(resp: &RedisResp) -> Option<i64> {
+    match resp.frame() {
+        Frame::Array(items) => match items.get(2) {
+            Some(Frame::Integer(count)) => Some(*count),
+            _ => None,
+        },
+        _ => None,
+    }
+}
@@ -0,0 +1,191 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use redis::{RedisCmd, RedisResp};
+
+use crate::error::{Error, Result};
+use crate::models::{hash_slot, Request};
+use crate::proxy::backend::{Backend, DedicatedConnection};
+use crate::proxy::router::Router;
+use crate::proxy::session::CROSSSLOT_ERROR;
+
+/// Transaction keeps the MULTI/EXEC and WATCH state of a session. All keys
+/// of a transaction must hash to the same slot, the transaction is sent
+/// through a connection owned by the session, so WATCH and EXEC are seen
+/// by the same backend connection.
+pub struct Transaction {
+    router: Arc<dyn Router>,
+    backend: Arc<Backend>,
+    slot: Option<u64>,
+    connection: Option<DedicatedConnection>,
+    // commands queued since MULTI, they are sent on EXEC
+    queued: Option<Vec<RedisCmd>>,
+    aborted: bool,
+}
+
+impl Transaction {
+    pub fn new(router: Arc<dyn Router>, backend: Arc<Backend>) -> Self {
+        Self {
+            router,
+            backend,
+            slot: None,
+            connection: None,
+            queued: None,
+            aborted: false,
+        }
+    }
+
+    pub fn in_multi(&self) -> bool {
+        self.queued.is_some()
+    }
+
+    fn reset(&mut self) {
+        self.slot = None;
+        self.connection = None;
+        self.queued = None;
+        self.aborted = false;
+    }
+
+    /// Returns the slot after adding the keys of `request`, or `None` if
+    /// they hash to different slots.
+    fn merge_slot(&self, request: &Request) -> Option<Option<u64>> {
+        let mut slot = self.slot;
+        for key in request.keys() {
+            let id = hash_slot(key);
+            match slot {
+                Some(pinned) if pinned != id => return None,
+                _ => slot = Some(id),
+            }
+        }
+        Some(slot)
+    }
+
+    fn connect(&mut self, slot: Option<u64>, database: u32) -> Result<&DedicatedConnection> {
+        if self.connection.is_none() {
+            let id = slot.unwrap_or_default();
+            let addr = self.router.get_slot(id).backend_addr;
+            if addr.is_empty() {
+                return Err(Error::proxy(anyhow!("slot-{:04} is not ready", id)));
+            }
+            self.connection = Some(self.backend.dedicated_connection(&addr, database)?);
+        }
+        self.slot = slot;
+        Ok(self.connection.as_ref().unwrap())
+    }
+
+    pub fn watch(&mut self, request: Request) -> Result<()> {
+        if self.in_multi() {
+            request.respond(Ok(RedisResp::error(
+                "ERR WATCH inside MULTI is not allowed",
+            )));
+            return Ok(());
+        }
+        if request.keys().is_empty() {
+            request.respond(Ok(RedisResp::error(
+                "ERR wrong number of arguments for 'watch' command",
+            )));
+            return Ok(());
+        }
+        let Some(slot) = self.merge_slot(&request) else {
+            request.respond(Ok(RedisResp::error(CROSSSLOT_ERROR)));
+            return Ok(());
+        };
+        self.connect(slot, request.database())?.forward(request)
+    }
+
+    pub fn unwatch(&mut self, request: Request) -> Result<()> {
+        if self.in_multi() {
+            return self.queue(request);
+        }
+        let result = match &self.connection {
+            Some(connection) => connection.forward(request),
+            None => {
+                request.respond(Ok(RedisResp::ok()));
+                Ok(())
+            }
+        };
+        self.reset();
+        result
+    }
+
+    pub fn multi(&mut self, request: Request) -> Result<()> {
+        if self.in_multi() {
+            request.respond(Ok(RedisResp::error("ERR MULTI calls can not be nested")));
+            return Ok(());
+        }
+        self.queued = Some(vec![]);
+        request.respond(Ok(RedisResp::ok()));
+        Ok(())
+    }
+
+    /// Queues a command inside MULTI, errors abort the transaction.
+    pub fn queue(&mut self, request: Request) -> Result<()> {
+        if let Some(command) = request.command() {
+            if command.is_not_allowed()
+                || command.is_pubsub()
+                || command.is_broadcast()
+                || matches!(command.name, "AUTH" | "SELECT")
+            {
+                let msg = format!(
+                    "ERR command '{}' is not allowed in transaction",
+                    request.name()
+                );
+                return self.abort(request, msg);
+            }
+        }
+        let Some(slot) = self.merge_slot(&request) else {
+            return self.abort(request, CROSSSLOT_ERROR.to_string());
+        };
+        self.slot = slot;
+        if let Some(queued) = &mut self.queued {
+            queued.push(request.redis().clone());
+        }
+        request.respond(Ok(RedisResp::simple("QUEUED")));
+        Ok(())
+    }
+
+    fn abort(&mut self, request: Request, msg: String) -> Result<()> {
+        self.aborted = true;
+        request.respond(Ok(RedisResp::error(msg)));
+        Ok(())
+    }
+
+    pub fn exec(&mut self, request: Request) -> Result<()> {
+        let Some(queued) = self.queued.take() else {
+            request.respond(Ok(RedisResp::error("ERR EXEC without MULTI")));
+            return Ok(());
+        };
+        if self.aborted {
+            self.reset();
+            request.respond(Ok(RedisResp::error(
+                "EXECABORT Transaction discarded because of previous errors.",
+            )));
+            return Ok(());
+        }
+        let result = self
+            .connect(self.slot, request.database())
+            .and_then(|connection| {
+                let (id, database) = (request.id(), request.database());
+                // responses of MULTI and queued commands are dropped, the client
+                // has got them already, only EXEC's response is sent back
+                connection.forward(Request::new(RedisCmd::new(["MULTI"]), id, database).0)?;
+                for cmd in queued {
+                    connection.forward(Request::new(cmd, id, database).0)?;
+                }
+                connection.forward(request)
+            });
+        // the connection is closed after EXEC is responded
+        self.reset();
+        result
+    }
+
+    pub fn discard(&mut self, request: Request) -> Result<()> {
+        if !self.in_multi() {
+            request.respond(Ok(RedisResp::error("ERR DISCARD without MULTI")));
+            return Ok(());
+        }
+        self.reset();
+        request.respond(Ok(RedisResp::ok()));
+        Ok(())
+    }
+}
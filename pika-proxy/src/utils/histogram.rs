@@ -0,0 +1,143 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::Serialize;
+
+/// values below `2 * SUB_BUCKETS` are counted exactly
+const SUB_BITS: u32 = 5;
+const SUB_BUCKETS: usize = 1 << SUB_BITS;
+/// larger values are counted as this one, about 19 hours in microseconds
+const MAX_VALUE: u64 = (1 << 36) - 1;
+const BUCKETS: usize = bucket_of(MAX_VALUE) + 1;
+
+/// Histogram counts values in log-linear buckets like an HDR histogram,
+/// each power of two is split into 32 buckets, so a percentile is off by
+/// 1/32 at most. It's updated without locks.
+#[derive(Debug)]
+pub struct Histogram {
+    counts: Box<[AtomicU64]>,
+    max: AtomicU64,
+}
+
+/// Percentiles of a histogram, in the unit of the values recorded.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Percentiles {
+    pub count: u64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub p999: u64,
+    pub max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            counts: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            max: AtomicU64::new(0),
+        }
+    }
+}
+
+const fn bucket_of(value: u64) -> usize {
+    if value < (2 * SUB_BUCKETS) as u64 {
+        return value as usize;
+    }
+    let shift = 63 - value.leading_zeros() - SUB_BITS;
+    (shift as usize + 1) * SUB_BUCKETS + (value >> shift) as usize - SUB_BUCKETS
+}
+
+/// The highest value counted in `bucket`.
+fn highest_of(bucket: usize) -> u64 {
+    if bucket < 2 * SUB_BUCKETS {
+        return bucket as u64;
+    }
+    let shift = bucket / SUB_BUCKETS - 1;
+    let sub = (bucket % SUB_BUCKETS + SUB_BUCKETS) as u64;
+    ((sub + 1) << shift) - 1
+}
+
+impl Histogram {
+    pub fn record(&self, value: u64) {
+        let value = value.min(MAX_VALUE);
+        self.counts[bucket_of(value)].fetch_add(1, Ordering::Relaxed);
+        self.max.fetch_max(value, Ordering::Relaxed);
+    }
+
+    /// Clears the values recorded, the ones recorded meanwhile may be lost.
+    pub fn reset(&self) {
+        for count in self.counts.iter() {
+            count.store(0, Ordering::Relaxed);
+        }
+        self.max.store(0, Ordering::Relaxed);
+    }
+
+    pub fn percentiles(&self) -> Percentiles {
+        let counts = self
+            .counts
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        let total = counts.iter().sum::<u64>();
+        let max = self.max.load(Ordering::Relaxed);
+        let percentile = |quantile: f64| {
+            if total == 0 {
+                return 0;
+            }
+            let rank = ((total as f64 * quantile).ceil() as u64).max(1);
+            let mut seen = 0;
+            for (bucket, count) in counts.iter().enumerate() {
+                seen += count;
+                if seen >= rank {
+                    return highest_of(bucket).min(max);
+                }
+            }
+            max
+        };
+        Percentiles {
+            count: total,
+            p50: percentile(0.5),
+            p90: percentile(0.9),
+            p99: percentile(0.99),
+            p999: percentile(0.999),
+            max,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buckets() {
+        for value in [0, 1, 63, 64, 65, 1000, 123_456, MAX_VALUE] {
+            let bucket = bucket_of(value);
+            assert!(highest_of(bucket) >= value);
+            assert!(bucket == 0 || highest_of(bucket - 1) < value);
+            assert!((highest_of(bucket) - value) as f64 <= value as f64 / 32.0);
+        }
+        assert_eq!(bucket_of(MAX_VALUE), BUCKETS - 1);
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let histogram = Histogram::default();
+        assert_eq!(histogram.percentiles(), Percentiles::default());
+        for value in 1..=1000 {
+            histogram.record(value);
+        }
+        let percentiles = histogram.percentiles();
+        assert_eq!(percentiles.count, 1000);
+        assert_eq!(percentiles.max, 1000);
+        for (p, expected) in [
+            (percentiles.p50, 500),
+            (percentiles.p90, 900),
+            (percentiles.p99, 990),
+            (percentiles.p999, 999),
+        ] {
+            assert!(p >= expected && p <= expected + expected / 32, "{}", p);
+        }
+        histogram.reset();
+        assert_eq!(histogram.percentiles().count, 0);
+    }
+}
@@ -1,2 +1,6 @@
 pub mod defer;
+pub mod histogram;
 pub mod redis;
+#[cfg(test)]
+pub mod testing;
+pub mod time;
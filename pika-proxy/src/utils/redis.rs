@@ -1 +1,194 @@
-pub struct InfoCache {}
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use redis::{Frame, RedisCmd, RedisRequester, RedisResp, RedisResponseReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tracing::debug;
+
+use crate::error::{Error, Result};
+use crate::utils::time::with_timeout;
+
+/// RedisConnection is a plain connection for the proxy's own commands, such
+/// as the ones to a registry or a sentinel, rather than client requests.
+pub struct RedisConnection {
+    pub requester: RedisRequester<OwnedWriteHalf>,
+    pub reader: RedisResponseReader<OwnedReadHalf>,
+}
+
+impl RedisConnection {
+    /// Connects to `addr`, `auth` is `password`, `user:password` or empty.
+    pub async fn open(addr: &str, auth: &str, timeout: Duration) -> Result<Self> {
+        let stream = with_timeout(timeout, async {
+            TcpStream::connect(addr)
+                .await
+                .map_err(|e| Error::network(anyhow!("connect to {} failed: {}", addr, e)))
+        })
+        .await?;
+        let (read_half, write_half) = stream.into_split();
+        let mut conn = Self {
+            requester: RedisRequester::new(write_half),
+            reader: RedisResponseReader::new(read_half),
+        };
+        if !auth.is_empty() {
+            let cmd = match auth.split_once(':') {
+                Some((user, password)) => RedisCmd::new(["AUTH", user, password].map(String::from)),
+                None => RedisCmd::new(["AUTH", auth].map(String::from)),
+            };
+            let resp = conn.call(&cmd, timeout).await?;
+            if let Some(msg) = resp.error_message() {
+                return Err(Error::server(anyhow!("{} rejected AUTH: {}", addr, msg)));
+            }
+        }
+        Ok(conn)
+    }
+
+    /// Sends `cmd` and reads its reply, error replies are returned as is.
+    pub async fn call(&mut self, cmd: &RedisCmd, timeout: Duration) -> Result<RedisResp> {
+        let Self { requester, reader } = self;
+        with_timeout(timeout, async {
+            requester.send_request(cmd).await?;
+            Ok(reader.read_response().await?)
+        })
+        .await
+    }
+}
+
+/// Info is the parsed output of INFO, fields of all sections are kept in one
+/// map, such as `run_id` and `role`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Info {
+    fields: HashMap<String, String>,
+}
+
+impl Info {
+    pub fn parse(text: &str) -> Self {
+        let fields = text
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.split_once(':'))
+            .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+            .collect();
+        Self { fields }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields.get(key).map(String::as_str)
+    }
+
+    pub fn run_id(&self) -> &str {
+        self.get("run_id").unwrap_or_default()
+    }
+
+    /// `master` or `slave`.
+    pub fn role(&self) -> &str {
+        self.get("role").unwrap_or_default()
+    }
+
+    /// Address of the master of a replica, such as `127.0.0.1:6379`.
+    pub fn master_addr(&self) -> Option<String> {
+        let host = self.get("master_host")?;
+        let port = self.get("master_port")?;
+        Some(format!("{}:{}", host, port))
+    }
+}
+
+/// InfoCache is a snapshot of INFO of some backends, so masters can be
+/// checked while slots are switched without any I/O. Backends which fail
+/// to answer are missing from the cache.
+#[derive(Debug, Default)]
+pub struct InfoCache {
+    infos: HashMap<String, Info>,
+}
+
+impl InfoCache {
+    /// Runs INFO on all `addrs` concurrently.
+    pub async fn load<'a, I>(addrs: I, auth: &str, timeout: Duration) -> Self
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let tasks = addrs
+            .into_iter()
+            .map(|addr| {
+                let (addr, auth) = (addr.to_string(), auth.to_string());
+                tokio::spawn(async move {
+                    let info = fetch_info(&addr, &auth, timeout).await;
+                    (addr, info)
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut infos = HashMap::new();
+        for task in tasks {
+            match task.await {
+                Ok((addr, Ok(info))) => {
+                    infos.insert(addr, info);
+                }
+                Ok((addr, Err(e))) => debug!("load info of {} failed: {}", addr, e),
+                Err(e) => debug!("load info failed: {}", e),
+            }
+        }
+        Self { infos }
+    }
+
+    pub fn insert(&mut self, addr: &str, info: Info) {
+        self.infos.insert(addr.to_string(), info);
+    }
+
+    pub fn get(&self, addr: &str) -> Option<&Info> {
+        self.infos.get(addr)
+    }
+
+    /// Run id of the server at `addr`, empty if it's unknown.
+    pub fn run_id(&self, addr: &str) -> &str {
+        self.get(addr).map(Info::run_id).unwrap_or_default()
+    }
+
+    pub fn is_master(&self, addr: &str) -> bool {
+        self.get(addr).is_some_and(|info| info.role() == "master")
+    }
+
+    /// Returns true if both addresses are the same server, addresses which
+    /// differ are compared by their run ids.
+    pub fn is_same_server(&self, addr1: &str, addr2: &str) -> bool {
+        if addr1 == addr2 {
+            return true;
+        }
+        let run_id = self.run_id(addr1);
+        !run_id.is_empty() && run_id == self.run_id(addr2)
+    }
+}
+
+async fn fetch_info(addr: &str, auth: &str, timeout: Duration) -> Result<Info> {
+    let mut conn = RedisConnection::open(addr, auth, timeout).await?;
+    let resp = conn.call(&RedisCmd::new(["INFO"]), timeout).await?;
+    match resp.frame() {
+        Frame::BulkString(text) => Ok(Info::parse(&String::from_utf8_lossy(text))),
+        Frame::Error(msg) => Err(Error::server(anyhow!("{} rejected INFO: {}", addr, msg))),
+        _ => Err(Error::protocol(anyhow!("bulk reply of INFO is expected"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_info_cache() {
+        let info = Info::parse(
+            "# Server\r\nrun_id:abc\r\n\r\n# Replication\r\nrole:slave\r\nmaster_host:10.0.0.1\r\nmaster_port:6379\r\n",
+        );
+        assert_eq!(info.run_id(), "abc");
+        assert_eq!(info.role(), "slave");
+        assert_eq!(info.master_addr().as_deref(), Some("10.0.0.1:6379"));
+
+        let mut cache = InfoCache::default();
+        cache.insert("a:1", info.clone());
+        cache.insert("b:1", info);
+        cache.insert("c:1", Info::parse("run_id:def\r\nrole:master\r\n"));
+        assert!(cache.is_same_server("a:1", "b:1"));
+        assert!(!cache.is_same_server("a:1", "c:1"));
+        assert!(!cache.is_same_server("x:1", "y:1"));
+        assert!(cache.is_master("c:1") && !cache.is_master("a:1"));
+    }
+}
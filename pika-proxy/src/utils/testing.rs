@@ -0,0 +1,248 @@
+//! Helpers for tests which need a redis server or a client.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use redis::error::Result as RedisResult;
+use redis::{
+    RedisCmd, RedisRequestReader, RedisRequester, RedisResp, RedisResponder, RedisResponseReader,
+};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+
+use crate::models::{Slot, MAX_SLOT_NUM};
+use crate::proxy::backend::Backend;
+use crate::proxy::config::{Config, ConfigHandle};
+use crate::proxy::router::{DefaultRouter, Router};
+use crate::proxy::server::proxy_metrics::ProxyMetrics;
+use crate::proxy::session::client_session::{ClientSession, ClientSessionOption};
+
+/// FakeBackend is a tiny redis server, every command is recorded with the
+/// index of its connection and answered with its arguments joined by space.
+/// PING, MULTI, EXEC and Pub/Sub commands behave like redis, BRPOP sleeps
+/// for its timeout before the reply, or forever if the timeout is 0. INFO
+/// tells it's a master whose run id is its address.
+pub struct FakeBackend {
+    pub addr: String,
+    log: Arc<Mutex<Vec<(usize, String)>>>,
+}
+
+impl FakeBackend {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let log = Arc::new(Mutex::new(vec![]));
+        let conns = Arc::new(AtomicUsize::new(0));
+        let server_log = log.clone();
+        let (messages, _) = broadcast::channel(16);
+        let server_addr = addr.clone();
+        tokio::spawn(async move {
+            let addr = server_addr;
+            while let Ok((conn, _)) = listener.accept().await {
+                let index = conns.fetch_add(1, Ordering::SeqCst);
+                let messages = messages.clone();
+                let run_id = addr.clone();
+                tokio::spawn(Self::serve(
+                    conn,
+                    index,
+                    run_id,
+                    server_log.clone(),
+                    messages,
+                ));
+            }
+        });
+        Self { addr, log }
+    }
+
+    async fn serve(
+        conn: TcpStream,
+        index: usize,
+        run_id: String,
+        log: Arc<Mutex<Vec<(usize, String)>>>,
+        messages: broadcast::Sender<(String, String)>,
+    ) {
+        let (reader, writer) = conn.into_split();
+        let mut reader = RedisRequestReader::new(reader);
+        let mut responder = RedisResponder::new(writer);
+        let mut queued: Option<Vec<RedisResp>> = None;
+        let mut channels: Vec<String> = vec![];
+        let mut published = messages.subscribe();
+        loop {
+            let cmd = tokio::select! {
+                cmd = reader.read_request() => match cmd {
+                    Ok(cmd) => cmd,
+                    Err(_) => break,
+                },
+                Ok((channel, msg)) = published.recv() => {
+                    if channels.contains(&channel) {
+                        let push = push(&["message", &channel, &msg], None);
+                        if responder.send_response(&push).await.is_err() {
+                            break;
+                        }
+                    }
+                    continue;
+                }
+            };
+            let line = join(&cmd);
+            log.lock().unwrap().push((index, line.clone()));
+            let args = cmd
+                .args()
+                .iter()
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect::<Vec<_>>();
+            let resps = match (cmd.name().as_str(), &mut queued) {
+                ("MULTI", None) => {
+                    queued = Some(vec![]);
+                    vec![RedisResp::ok()]
+                }
+                ("EXEC", Some(_)) => vec![RedisResp::array(queued.take().unwrap())],
+                (_, Some(queued)) => {
+                    queued.push(RedisResp::bulk(line));
+                    vec![RedisResp::simple("QUEUED")]
+                }
+                ("PING", None) => vec![RedisResp::simple("PONG")],
+                ("INFO", None) => {
+                    let info = format!(
+                        "# Server\r\nrun_id:{}\r\n# Replication\r\nrole:master\r\n",
+                        run_id
+                    );
+                    vec![RedisResp::bulk(info)]
+                }
+                ("BRPOP", None) => {
+                    match args.last().map(|arg| arg.parse::<f64>()) {
+                        Some(Ok(secs)) if secs > 0.0 => {
+                            tokio::time::sleep(Duration::from_secs_f64(secs)).await
+                        }
+                        _ => std::future::pending().await,
+                    }
+                    vec![RedisResp::bulk(line)]
+                }
+                ("SUBSCRIBE", None) => args[1..]
+                    .iter()
+                    .map(|channel| {
+                        channels.push(channel.clone());
+                        push(&["subscribe", channel], Some(channels.len()))
+                    })
+                    .collect(),
+                ("UNSUBSCRIBE", None) => args[1..]
+                    .iter()
+                    .map(|channel| {
+                        channels.retain(|c| c != channel);
+                        push(&["unsubscribe", channel], Some(channels.len()))
+                    })
+                    .collect(),
+                ("PUBLISH", None) => {
+                    let _ = messages.send((args[1].clone(), args[2].clone()));
+                    vec![RedisResp::integer(1)]
+                }
+                (_, None) => vec![RedisResp::bulk(line)],
+            };
+            for resp in &resps {
+                responder.feed_response(resp);
+            }
+            if responder.flush().await.is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Commands received so far, with the index of their connection.
+    pub fn commands(&self) -> Vec<(usize, String)> {
+        self.log.lock().unwrap().clone()
+    }
+}
+
+fn push(items: &[&str], count: Option<usize>) -> RedisResp {
+    let items = items.iter().map(|item| RedisResp::bulk(item.to_string()));
+    let count = count.map(|count| RedisResp::integer(count as i64));
+    RedisResp::array(items.chain(count).collect())
+}
+
+fn join(cmd: &RedisCmd) -> String {
+    cmd.args()
+        .iter()
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// TestClient talks to a proxy session like redis-cli.
+pub struct TestClient {
+    requester: RedisRequester<OwnedWriteHalf>,
+    reader: RedisResponseReader<OwnedReadHalf>,
+}
+
+impl TestClient {
+    pub fn new(conn: TcpStream) -> Self {
+        let (reader, writer) = conn.into_split();
+        Self {
+            requester: RedisRequester::new(writer),
+            reader: RedisResponseReader::new(reader),
+        }
+    }
+
+    pub async fn send(&mut self, args: &[&str]) -> RedisResult<()> {
+        let cmd = RedisCmd::new(args.iter().map(|arg| arg.to_string()));
+        self.requester.send_request(&cmd).await
+    }
+
+    pub async fn recv(&mut self) -> RedisResult<RedisResp> {
+        self.reader.read_response().await
+    }
+
+    pub async fn call(&mut self, args: &[&str]) -> RedisResp {
+        self.send(args).await.unwrap();
+        self.recv().await.unwrap()
+    }
+}
+
+/// Returns a connected pair of tcp streams.
+pub async fn tcp_pair() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (server, _) = listener.accept().await.unwrap();
+    (client, server)
+}
+
+/// Starts a client session whose slots are all served by `addr`, and returns
+/// a client connected to it.
+pub async fn start_session(config: Config, addr: &str) -> TestClient {
+    start_cluster_session(config, &[addr]).await
+}
+
+/// Starts a client session whose slots are spread over `addrs` by slot id.
+pub async fn start_cluster_session(config: Config, addrs: &[&str]) -> TestClient {
+    start_session_with_metrics(config, addrs, Arc::default()).await
+}
+
+/// Starts a client session which shares `metrics` with other sessions.
+pub async fn start_session_with_metrics(
+    config: Config,
+    addrs: &[&str],
+    metrics: Arc<ProxyMetrics>,
+) -> TestClient {
+    let config = ConfigHandle::new(config);
+    let backend = Arc::new(Backend::new(config.clone()));
+    let router = Arc::new(DefaultRouter::new(config.clone(), backend.clone()));
+    for id in 0..MAX_SLOT_NUM as u64 {
+        let slot = Slot {
+            id,
+            backend_addr: addrs[id as usize % addrs.len()].to_string(),
+            ..Default::default()
+        };
+        router.fill_slot(Box::new(slot)).unwrap();
+    }
+    let session = ClientSession::new(ClientSessionOption {
+        router,
+        config,
+        backend,
+        metrics,
+    });
+    let (client, server) = tcp_pair().await;
+    tokio::spawn(session.serve_client(server));
+    TestClient::new(client)
+}
@@ -0,0 +1,62 @@
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::anyhow;
+
+use crate::error::{Error, Result};
+
+/// Awaits `future` for at most `duration`, none or zero means no timeout.
+pub async fn with_timeout<T, F>(duration: impl Into<Option<Duration>>, future: F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    let duration = match duration.into() {
+        Some(duration) if !duration.is_zero() => duration,
+        _ => return future.await,
+    };
+    tokio::time::timeout(duration, future)
+        .await
+        .map_err(|_| Error::network(anyhow!("timeout after {:?}", duration)))?
+}
+
+/// Formats `time` like `2023-07-01 08:00:00 UTC`.
+pub fn format_utc(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default() as i64;
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // days to civil date, by Howard Hinnant's algorithm
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_utc() {
+        assert_eq!(format_utc(UNIX_EPOCH), "1970-01-01 00:00:00 UTC");
+        let time = UNIX_EPOCH + Duration::from_secs(1688169600 + 3661);
+        assert_eq!(format_utc(time), "2023-07-01 01:01:01 UTC");
+        let leap = UNIX_EPOCH + Duration::from_secs(951782400);
+        assert_eq!(format_utc(leap), "2000-02-29 00:00:00 UTC");
+    }
+}
@@ -0,0 +1,125 @@
+use bytes::Bytes;
+
+use crate::error::{RedisError, Result};
+use crate::Frame;
+
+/// A redis command, sent by a client as an array of bulk strings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedisCmd {
+    args: Vec<Bytes>,
+}
+
+impl RedisCmd {
+    pub fn new<I, A>(args: I) -> Self
+    where
+        I: IntoIterator<Item = A>,
+        A: Into<Bytes>,
+    {
+        Self {
+            args: args.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Upper-cased command name, empty if the command has no arguments.
+    pub fn name(&self) -> String {
+        self.args
+            .first()
+            .map(|name| String::from_utf8_lossy(name).to_ascii_uppercase())
+            .unwrap_or_default()
+    }
+
+    pub fn args(&self) -> &[Bytes] {
+        &self.args
+    }
+
+    pub fn arg(&self, index: usize) -> Option<&Bytes> {
+        self.args.get(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.args.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.args.is_empty()
+    }
+
+    /// Number of bytes the command takes on the wire.
+    pub fn encoded_len(&self) -> usize {
+        let header = 1 + digits(self.args.len()) + 2;
+        self.args.iter().fold(header, |len, arg| {
+            len + 1 + digits(arg.len()) + 2 + arg.len() + 2
+        })
+    }
+
+    pub fn to_frame(&self) -> Frame {
+        Frame::Array(self.args.iter().cloned().map(Frame::BulkString).collect())
+    }
+
+    pub(crate) fn from_frame(frame: Frame) -> Result<Self> {
+        let Frame::Array(frames) = frame else {
+            return Err(RedisError::InvalidRequest(
+                "expected array of bulk strings".into(),
+            ));
+        };
+        let args = frames
+            .into_iter()
+            .map(|frame| match frame {
+                Frame::BulkString(arg) | Frame::SimpleString(arg) => Ok(arg),
+                Frame::Integer(i) => Ok(Bytes::from(i.to_string())),
+                _ => Err(RedisError::InvalidRequest(
+                    "expected array of bulk strings".into(),
+                )),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if args.is_empty() {
+            return Err(RedisError::InvalidRequest("empty command".into()));
+        }
+        Ok(Self { args })
+    }
+
+    /// Parses an inline command such as `PING\r\n` typed into telnet.
+    pub(crate) fn from_inline(line: &[u8]) -> Result<Self> {
+        let args: Vec<Bytes> = line
+            .split(|b| b.is_ascii_whitespace())
+            .filter(|arg| !arg.is_empty())
+            .map(Bytes::copy_from_slice)
+            .collect();
+        if args.is_empty() {
+            return Err(RedisError::InvalidRequest("empty command".into()));
+        }
+        Ok(Self { args })
+    }
+}
+
+pub(crate) fn digits(mut n: usize) -> usize {
+    let mut digits = 1;
+    while n >= 10 {
+        n /= 10;
+        digits += 1;
+    }
+    digits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cmd_name_and_len() {
+        let cmd = RedisCmd::new(["set", "key", "value"]);
+        assert_eq!(cmd.name(), "SET");
+        assert_eq!(cmd.len(), 3);
+        assert_eq!(
+            cmd.encoded_len(),
+            "*3\r\n$3\r\nset\r\n$3\r\nkey\r\n$5\r\nvalue\r\n".len()
+        );
+    }
+
+    #[test]
+    fn test_cmd_from_inline() {
+        let cmd = RedisCmd::from_inline(b"get  foo ").unwrap();
+        assert_eq!(cmd, RedisCmd::new(["get", "foo"]));
+        assert!(RedisCmd::from_inline(b"   ").is_err());
+    }
+}
@@ -1,7 +1,5 @@
 use thiserror::Error;
 
-pub type Result<T> = std::result::Result<T, RedisError>;
-
 #[derive(Debug, Error)]
 pub enum RedisError {
     #[error("key or value size is invalid")]
@@ -22,4 +20,10 @@ pub enum RedisError {
     ExpiredKey,
     #[error("merging")]
     AtMerging,
+    #[error("invalid request: {0}")]
+    InvalidRequest(String),
+    #[error("bad resp: {0}")]
+    BadResp(String),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
 }
@@ -0,0 +1,289 @@
+use bytes::BytesMut;
+use redis_protocol::resp2::decode::decode_mut;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::cmd::RedisCmd;
+use crate::error::{RedisError, Result};
+use crate::resp::{encode_frame, RedisResp};
+use crate::Frame;
+
+const DEFAULT_BUFSIZE: usize = 16 * 1024;
+
+struct FrameReader<R> {
+    reader: R,
+    buffer: BytesMut,
+}
+
+impl<R: AsyncRead + Unpin> FrameReader<R> {
+    fn new(reader: R, bufsize: usize) -> Self {
+        Self {
+            reader,
+            buffer: BytesMut::with_capacity(bufsize),
+        }
+    }
+
+    /// Reads more data into the buffer, it is cancel safe since partial data
+    /// is kept in the buffer.
+    async fn fill_buffer(&mut self) -> Result<()> {
+        if self.reader.read_buf(&mut self.buffer).await? == 0 {
+            return if self.buffer.is_empty() {
+                Err(RedisError::NoMoreData)
+            } else {
+                Err(RedisError::TruncatedData)
+            };
+        }
+        Ok(())
+    }
+
+    fn decode(&mut self) -> Result<Option<Frame>> {
+        if self.buffer.is_empty() {
+            return Ok(None);
+        }
+        match decode_mut(&mut self.buffer) {
+            Ok(Some((frame, _, _))) => Ok(Some(frame)),
+            Ok(None) => Ok(None),
+            Err(e) => Err(RedisError::BadResp(e.to_string())),
+        }
+    }
+
+    async fn read_frame(&mut self) -> Result<Frame> {
+        loop {
+            if let Some(frame) = self.decode()? {
+                return Ok(frame);
+            }
+            self.fill_buffer().await?;
+        }
+    }
+
+    /// Reads a whole line, used by inline commands.
+    async fn read_line(&mut self) -> Result<BytesMut> {
+        loop {
+            if let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+                let mut line = self.buffer.split_to(pos + 1);
+                line.truncate(pos);
+                if line.last() == Some(&b'\r') {
+                    line.truncate(pos - 1);
+                }
+                return Ok(line);
+            }
+            self.fill_buffer().await?;
+        }
+    }
+}
+
+struct FrameWriter<W> {
+    writer: W,
+    buffer: BytesMut,
+}
+
+impl<W: AsyncWrite + Unpin> FrameWriter<W> {
+    fn new(writer: W, bufsize: usize) -> Self {
+        Self {
+            writer,
+            buffer: BytesMut::with_capacity(bufsize),
+        }
+    }
+
+    fn feed_frame(&mut self, frame: &Frame) {
+        encode_frame(&mut self.buffer, frame);
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        if !self.buffer.is_empty() {
+            self.writer.write_all(&self.buffer).await?;
+            self.buffer.clear();
+        }
+        self.writer.flush().await?;
+        Ok(())
+    }
+
+    async fn shutdown(&mut self) -> Result<()> {
+        self.flush().await?;
+        self.writer.shutdown().await?;
+        Ok(())
+    }
+}
+
+/// Reads commands sent by a client.
+pub struct RedisRequestReader<R> {
+    inner: FrameReader<R>,
+}
+
+impl<R: AsyncRead + Unpin> RedisRequestReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_capacity(reader, DEFAULT_BUFSIZE)
+    }
+
+    pub fn with_capacity(reader: R, bufsize: usize) -> Self {
+        Self {
+            inner: FrameReader::new(reader, bufsize),
+        }
+    }
+
+    /// Reads the next command, `RedisError::NoMoreData` is returned when
+    /// the peer closed the connection.
+    pub async fn read_request(&mut self) -> Result<RedisCmd> {
+        loop {
+            match self.inner.buffer.first() {
+                None => self.inner.fill_buffer().await?,
+                Some(b'*') => return RedisCmd::from_frame(self.inner.read_frame().await?),
+                Some(_) => {
+                    let line = self.inner.read_line().await?;
+                    if line.iter().all(u8::is_ascii_whitespace) {
+                        continue;
+                    }
+                    return RedisCmd::from_inline(&line);
+                }
+            }
+        }
+    }
+}
+
+/// Writes replies back to a client.
+pub struct RedisResponder<W> {
+    inner: FrameWriter<W>,
+}
+
+impl<W: AsyncWrite + Unpin> RedisResponder<W> {
+    pub fn new(writer: W) -> Self {
+        Self::with_capacity(writer, DEFAULT_BUFSIZE)
+    }
+
+    pub fn with_capacity(writer: W, bufsize: usize) -> Self {
+        Self {
+            inner: FrameWriter::new(writer, bufsize),
+        }
+    }
+
+    /// Buffers a reply without writing it, see `flush`.
+    pub fn feed_response(&mut self, resp: &RedisResp) {
+        self.inner.feed_frame(resp.frame());
+    }
+
+    pub async fn send_response(&mut self, resp: &RedisResp) -> Result<()> {
+        self.feed_response(resp);
+        self.flush().await
+    }
+
+    pub async fn flush(&mut self) -> Result<()> {
+        self.inner.flush().await
+    }
+
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.inner.shutdown().await
+    }
+}
+
+/// Reads replies sent by a redis server.
+pub struct RedisResponseReader<R> {
+    inner: FrameReader<R>,
+}
+
+impl<R: AsyncRead + Unpin> RedisResponseReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_capacity(reader, DEFAULT_BUFSIZE)
+    }
+
+    pub fn with_capacity(reader: R, bufsize: usize) -> Self {
+        Self {
+            inner: FrameReader::new(reader, bufsize),
+        }
+    }
+
+    /// Reads the next reply, it is cancel safe.
+    pub async fn read_response(&mut self) -> Result<RedisResp> {
+        Ok(RedisResp::from_frame(self.inner.read_frame().await?))
+    }
+}
+
+/// Writes commands to a redis server.
+pub struct RedisRequester<W> {
+    inner: FrameWriter<W>,
+}
+
+impl<W: AsyncWrite + Unpin> RedisRequester<W> {
+    pub fn new(writer: W) -> Self {
+        Self::with_capacity(writer, DEFAULT_BUFSIZE)
+    }
+
+    pub fn with_capacity(writer: W, bufsize: usize) -> Self {
+        Self {
+            inner: FrameWriter::new(writer, bufsize),
+        }
+    }
+
+    /// Buffers a command without writing it, see `flush`.
+    pub fn feed_request(&mut self, cmd: &RedisCmd) {
+        self.inner.feed_frame(&cmd.to_frame());
+    }
+
+    pub async fn send_request(&mut self, cmd: &RedisCmd) -> Result<()> {
+        self.feed_request(cmd);
+        self.flush().await
+    }
+
+    pub async fn flush(&mut self) -> Result<()> {
+        self.inner.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_request() {
+        let data: &[u8] = b"*2\r\n$3\r\nGET\r\n$3\r\nfoo\r\nPING\r\n\r\n*1\r\n$4\r\nQUIT\r\n";
+        let mut reader = RedisRequestReader::new(data);
+        assert_eq!(
+            reader.read_request().await.unwrap(),
+            RedisCmd::new(["GET", "foo"])
+        );
+        assert_eq!(
+            reader.read_request().await.unwrap(),
+            RedisCmd::new(["PING"])
+        );
+        assert_eq!(
+            reader.read_request().await.unwrap(),
+            RedisCmd::new(["QUIT"])
+        );
+        assert!(matches!(
+            reader.read_request().await,
+            Err(RedisError::NoMoreData)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_read_truncated_request() {
+        let data: &[u8] = b"*2\r\n$3\r\nGET\r\n$3\r\nf";
+        let mut reader = RedisRequestReader::new(data);
+        assert!(matches!(
+            reader.read_request().await,
+            Err(RedisError::TruncatedData)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_request_roundtrip() {
+        let (client, server) = tokio::io::duplex(64);
+        let mut requester = RedisRequester::new(client);
+        let mut reader = RedisRequestReader::new(server);
+        let cmd = RedisCmd::new(["SET", "key", "value"]);
+        requester.send_request(&cmd).await.unwrap();
+        assert_eq!(reader.read_request().await.unwrap(), cmd);
+    }
+
+    #[tokio::test]
+    async fn test_response_roundtrip() {
+        let (client, server) = tokio::io::duplex(64);
+        let mut responder = RedisResponder::new(server);
+        let mut reader = RedisResponseReader::new(client);
+        let resp = RedisResp::array(vec![RedisResp::bulk("a"), RedisResp::integer(1)]);
+        responder.feed_response(&resp);
+        responder.feed_response(&RedisResp::error("ERR oops"));
+        responder.flush().await.unwrap();
+        assert_eq!(reader.read_response().await.unwrap(), resp);
+        let err = reader.read_response().await.unwrap();
+        assert_eq!(err.error_message(), Some("ERR oops"));
+    }
+}
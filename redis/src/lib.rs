@@ -1,29 +1,10 @@
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+pub use redis_protocol::resp2::types::Frame;
 
-use error::Result;
-pub mod error;
-
-pub struct RedisCmd;
-pub struct RedisResp;
-
-pub struct RedisRequestReader {}
+pub use cmd::RedisCmd;
+pub use io::{RedisRequestReader, RedisRequester, RedisResponder, RedisResponseReader};
+pub use resp::RedisResp;
 
-impl RedisRequestReader {
-    pub fn new<R: AsyncReadExt>(reader: R) -> Self {
-        Self {}
-    }
-    pub async fn read_request(&mut self) -> Result<RedisCmd> {
-        unimplemented!()
-    }
-}
-
-pub struct RedisResponder {}
-
-impl RedisResponder {
-    pub fn new<W: AsyncWriteExt>(writer: W) -> Self {
-        Self {}
-    }
-    pub async fn send_response(&mut self, resp: &RedisResp) -> Result<()> {
-        unimplemented!()
-    }
-}
+mod cmd;
+pub mod error;
+mod io;
+mod resp;
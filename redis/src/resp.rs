@@ -0,0 +1,137 @@
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::cmd::digits;
+use crate::Frame;
+
+/// A redis reply in RESP2.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedisResp(Frame);
+
+impl RedisResp {
+    pub fn ok() -> Self {
+        Self::simple("OK")
+    }
+
+    pub fn simple<S: Into<Bytes>>(s: S) -> Self {
+        Self(Frame::SimpleString(s.into()))
+    }
+
+    /// An error reply, `msg` should start with an error prefix like `ERR`.
+    pub fn error<S: Into<String>>(msg: S) -> Self {
+        Self(Frame::Error(msg.into().into()))
+    }
+
+    pub fn integer(i: i64) -> Self {
+        Self(Frame::Integer(i))
+    }
+
+    pub fn bulk<B: Into<Bytes>>(b: B) -> Self {
+        Self(Frame::BulkString(b.into()))
+    }
+
+    pub fn null() -> Self {
+        Self(Frame::Null)
+    }
+
+    pub fn array(items: Vec<RedisResp>) -> Self {
+        Self(Frame::Array(items.into_iter().map(|r| r.0).collect()))
+    }
+
+    pub fn from_frame(frame: Frame) -> Self {
+        Self(frame)
+    }
+
+    pub fn frame(&self) -> &Frame {
+        &self.0
+    }
+
+    pub fn into_frame(self) -> Frame {
+        self.0
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.0.is_error()
+    }
+
+    pub fn error_message(&self) -> Option<&str> {
+        match &self.0 {
+            Frame::Error(msg) => Some(&**msg),
+            _ => None,
+        }
+    }
+
+    /// Number of bytes the reply takes on the wire.
+    pub fn encoded_len(&self) -> usize {
+        frame_len(&self.0)
+    }
+}
+
+fn frame_len(frame: &Frame) -> usize {
+    match frame {
+        Frame::SimpleString(s) => 1 + s.len() + 2,
+        Frame::Error(s) => 1 + s.len() + 2,
+        Frame::Integer(i) => 1 + i.to_string().len() + 2,
+        Frame::BulkString(b) => 1 + digits(b.len()) + 2 + b.len() + 2,
+        Frame::Array(items) => {
+            items.iter().map(frame_len).sum::<usize>() + 1 + digits(items.len()) + 2
+        }
+        Frame::Null => 5,
+    }
+}
+
+pub(crate) fn encode_frame(buf: &mut BytesMut, frame: &Frame) {
+    match frame {
+        Frame::SimpleString(s) => {
+            buf.put_u8(b'+');
+            buf.put_slice(s);
+        }
+        Frame::Error(s) => {
+            buf.put_u8(b'-');
+            buf.put_slice(s.as_bytes());
+        }
+        Frame::Integer(i) => {
+            buf.put_u8(b':');
+            buf.put_slice(i.to_string().as_bytes());
+        }
+        Frame::BulkString(b) => {
+            buf.put_u8(b'$');
+            buf.put_slice(b.len().to_string().as_bytes());
+            buf.put_slice(b"\r\n");
+            buf.put_slice(b);
+        }
+        Frame::Array(items) => {
+            buf.put_u8(b'*');
+            buf.put_slice(items.len().to_string().as_bytes());
+            buf.put_slice(b"\r\n");
+            for item in items {
+                encode_frame(buf, item);
+            }
+            return;
+        }
+        Frame::Null => buf.put_slice(b"$-1"),
+    }
+    buf.put_slice(b"\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_frame() {
+        let resp = RedisResp::array(vec![
+            RedisResp::ok(),
+            RedisResp::error("ERR boom"),
+            RedisResp::integer(-12),
+            RedisResp::bulk("hello"),
+            RedisResp::null(),
+        ]);
+        let mut buf = BytesMut::new();
+        encode_frame(&mut buf, resp.frame());
+        assert_eq!(
+            &buf[..],
+            b"*5\r\n+OK\r\n-ERR boom\r\n:-12\r\n$5\r\nhello\r\n$-1\r\n"
+        );
+        assert_eq!(resp.encoded_len(), buf.len());
+    }
+}